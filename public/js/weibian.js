@@ -0,0 +1,81 @@
+// Loads lazily transcluded notes when their <details> is opened.
+//
+// The backend renders a lazy transclusion as a <details> element holding only
+// the header of the note, with the URL of the rendered body in
+// `data-wb-fragment`. Heading demotion and numbering options of the
+// transclusion are applied here, since the fragment is shared by all of them.
+// Section numbers in a fragment are relative to the transcluded note, so they
+// are prefixed with the number of the transclusion (`data-wb-number`).
+
+function wbDemoteHeadings(root, levels, disableNumbering, prefix) {
+  for (const heading of root.querySelectorAll("h1, h2, h3, h4, h5, h6")) {
+    const level = Math.min(Number(heading.tagName.slice(1)) + levels, 6);
+    let target = heading;
+    if (levels > 0) {
+      target = document.createElement("h" + level);
+      for (const attr of heading.attributes) {
+        target.setAttribute(attr.name, attr.value);
+      }
+      target.append(...heading.childNodes);
+      heading.replaceWith(target);
+    }
+    if (disableNumbering) {
+      target.classList.add("disable-numbering");
+    } else if (prefix && target.dataset.number) {
+      const number = `${prefix}.${target.dataset.number}`;
+      target.dataset.number = number;
+      target.style.setProperty("--num", `'${number}'`);
+    }
+  }
+}
+
+function wbLoadFragment(details) {
+  const href = details.dataset.wbFragment;
+  if (!href) {
+    return;
+  }
+  delete details.dataset.wbFragment;
+  fetch(href)
+    .then((response) => {
+      if (!response.ok) {
+        throw new Error(`failed to load ${href}: ${response.status}`);
+      }
+      return response.text();
+    })
+    .then((html) => {
+      const template = document.createElement("template");
+      template.innerHTML = html;
+      const content = template.content;
+      const header = content.querySelector(":scope > summary");
+      if (header) {
+        header.remove();
+      }
+      wbDemoteHeadings(
+        content,
+        Number(details.dataset.wbDemoteHeadings || 0),
+        "wbDisableNumbering" in details.dataset,
+        details.dataset.wbNumber,
+      );
+      details.append(content);
+    })
+    .catch((error) => {
+      details.dataset.wbFragment = href;
+      console.error(error);
+    });
+}
+
+document.addEventListener(
+  "toggle",
+  (event) => {
+    if (event.target instanceof HTMLDetailsElement && event.target.open) {
+      wbLoadFragment(event.target);
+    }
+  },
+  true,
+);
+
+document.addEventListener("DOMContentLoaded", () => {
+  for (const details of document.querySelectorAll("details[open][data-wb-fragment]")) {
+    wbLoadFragment(details);
+  }
+});
@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use chrono::{DateTime, Utc};
 use clap::builder::{BoolishValueParser, ValueParser};
 use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum, ValueHint};
+use serde::Deserialize;
 
 /// The character typically used to separate path components
 /// in environment variables.
@@ -65,6 +66,24 @@ pub enum Command {
     /// Compiles input file(s) to designated output format(s).
     #[command(visible_alias = "c")]
     Compile(CompileCommand),
+
+    /// Exports notes in other forms than the site.
+    Export(ExportCommand),
+
+    /// Initializes a new project from the starter templates.
+    Init(InitCommand),
+
+    /// Creates a new note with the next free ID.
+    New(NewCommand),
+
+    /// Changes the ID of a note and updates every reference to it.
+    Mv(MvCommand),
+
+    /// Inspects the configuration.
+    Config(ConfigCommand),
+
+    /// Runs a language server for note references, alongside the Typst one.
+    Lsp(LspCommand),
     // /// Watches an input file and recompiles on changes.
     // #[command(visible_alias = "w")]
     // Watch(WatchCommand),
@@ -72,8 +91,6 @@ pub enum Command {
     // /// Opens a preview server.
     // #[command(visible_alias = "s")]
     // Serve(ServeCommand),
-    // /// Initializes a new project from a template.
-    // Init(InitCommand),
 
     // /// Self update the Weibian CLI.
     // #[cfg_attr(not(feature = "self-update"), clap(hide = true))]
@@ -88,6 +105,142 @@ pub struct CompileCommand {
     pub args: CompileArgs,
 }
 
+/// Exports notes in other forms than the site.
+#[derive(Debug, Clone, Parser)]
+pub struct ExportCommand {
+    /// What to export.
+    #[command(subcommand)]
+    pub target: ExportTarget,
+}
+
+/// Initializes a new project from the starter templates.
+#[derive(Debug, Clone, Parser)]
+pub struct InitCommand {
+    /// The project directory (defaults to the current directory).
+    #[clap(value_hint = ValueHint::DirPath)]
+    pub dir: Option<PathBuf>,
+
+    /// The domain of the site used for generating absolute URLs.
+    #[arg(long = "site-domain", value_name = "DOMAIN")]
+    pub domain: Option<String>,
+
+    /// Root directory of the site (for example, "/notes/"; defaults to "/").
+    #[arg(long = "site-root-dir", value_name = "DIR")]
+    pub root_dir: Option<String>,
+}
+
+/// Creates a new note with the next free ID.
+#[derive(Debug, Clone, Parser)]
+pub struct NewCommand {
+    /// The title of the note (defaults to today's date for journal notes).
+    #[clap(required_unless_present = "journal")]
+    pub title: Option<String>,
+
+    /// The taxon of the note, e.g. "Theorem".
+    #[clap(long)]
+    pub taxon: Option<String>,
+
+    /// The directory to create the note in, relative to the input directory
+    /// (defaults to the input directory itself, or to the journal directory
+    /// for journal notes).
+    #[clap(long, value_hint = ValueHint::DirPath)]
+    pub dir: Option<PathBuf>,
+
+    /// Creates the journal note of today, unless it already exists.
+    #[clap(long)]
+    pub journal: bool,
+
+    /// Path to input directory (defaults to config or "typ").
+    #[clap(long = "input", value_hint = ValueHint::DirPath)]
+    pub input: Option<PathBuf>,
+
+    /// The profile of the configuration file whose sources are searched for
+    /// IDs in use.
+    #[arg(long = "profile", env = "WB_PROFILE", value_name = "NAME")]
+    pub profile: Option<String>,
+}
+
+/// Changes the ID of a note and updates every reference to it.
+#[derive(Debug, Clone, Parser)]
+pub struct MvCommand {
+    /// The current ID of the note.
+    pub old: String,
+
+    /// The new ID of the note.
+    pub new: String,
+
+    /// Keeps the current ID as an alias in the configuration file, so that
+    /// its URL redirects to the new one.
+    #[clap(long)]
+    pub alias: bool,
+
+    /// Prints the changes as a diff instead of making them.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Arguments selecting the source files.
+    #[clap(flatten)]
+    pub sources: SourceArgs,
+}
+
+/// Inspects the configuration.
+#[derive(Debug, Clone, Parser)]
+pub struct ConfigCommand {
+    /// What to do with the configuration.
+    #[command(subcommand)]
+    pub action: ConfigAction,
+}
+
+/// What to do with the configuration.
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigAction {
+    /// Prints every setting of a build, with where its value comes from.
+    Show(ConfigShowArgs),
+}
+
+/// Arguments for showing the configuration.
+#[derive(Debug, Clone, Args)]
+pub struct ConfigShowArgs {
+    /// Arguments for compilation, which override the configuration.
+    #[clap(flatten)]
+    pub args: CompileArgs,
+}
+
+/// Runs a language server for note references, alongside the Typst one.
+#[derive(Debug, Clone, Parser)]
+pub struct LspCommand {
+    /// Arguments for compilation.
+    #[clap(flatten)]
+    pub args: CompileArgs,
+}
+
+/// What to export.
+#[derive(Debug, Clone, Subcommand)]
+pub enum ExportTarget {
+    /// Exports a note and all notes it transcludes as a single PDF.
+    Book(ExportBookArgs),
+    /// Exports a note and all notes it transcludes as an EPUB.
+    Epub(ExportEpubArgs),
+    /// Exports the pages of a note as SVG or PNG images.
+    Image(ExportImageArgs),
+}
+
+/// Arguments for exporting a book.
+#[derive(Debug, Clone, Args)]
+pub struct ExportBookArgs {
+    /// The ID of the note to export.
+    pub id: String,
+
+    /// Path to the output PDF (defaults to "pdf/<ID>.pdf" in the output
+    /// directory).
+    #[arg(long = "output-file", value_hint = ValueHint::FilePath)]
+    pub output_file: Option<PathBuf>,
+
+    /// Arguments for compilation.
+    #[clap(flatten)]
+    pub args: CompileArgs,
+}
+
 // Compiles an input file into a supported output format.
 // #[derive(Debug, Clone, Parser)]
 // pub struct WatchCommand {
@@ -109,25 +262,6 @@ pub struct CompileCommand {
 //     pub server: ServerArgs,
 // }
 
-// Initializes a new project from a template.
-// #[derive(Debug, Clone, Parser)]
-// pub struct InitCommand {
-//     /// The template to use, e.g. `@preview/charged-ieee`.
-//     ///
-//     /// You can specify the version by appending e.g. `:0.1.0`. If no version is
-//     /// specified, Typst will default to the latest version.
-//     ///
-//     /// Supports both local and published templates.
-//     pub template: String,
-
-//     /// The project directory, defaults to the template's name.
-//     pub dir: Option<String>,
-
-//     /// Arguments related to storage of packages in the system.
-//     #[clap(flatten)]
-//     pub package: PackageArgs,
-// }
-
 // /// Update the CLI using a pre-compiled binary from a Typst GitHub release.
 // #[derive(Debug, Clone, Parser)]
 // pub struct UpdateCommand {
@@ -154,13 +288,77 @@ pub struct CompileCommand {
 //     pub backup_path: Option<PathBuf>,
 // }
 
-/// Arguments for compilation and watching.
+/// Arguments for exporting an EPUB.
 #[derive(Debug, Clone, Args)]
-pub struct CompileArgs {
+pub struct ExportEpubArgs {
+    /// The ID of the note to export.
+    pub id: String,
+
+    /// Path to the output EPUB (defaults to "epub/<ID>.epub" in the output
+    /// directory).
+    #[arg(long = "output-file", value_hint = ValueHint::FilePath)]
+    pub output_file: Option<PathBuf>,
+
+    /// Arguments for compilation.
+    #[clap(flatten)]
+    pub args: CompileArgs,
+}
+
+/// Arguments for exporting the pages of a note as images.
+#[derive(Debug, Clone, Args)]
+pub struct ExportImageArgs {
+    /// The ID of the note to export.
+    pub id: String,
+
+    /// The image format.
+    #[arg(long = "image-format", value_enum, default_value_t = ImageFormat::Png)]
+    pub image_format: ImageFormat,
+
+    /// Path to the output images, where `{p}` is replaced by the page number
+    /// (defaults to "<FORMAT>/<ID>-{p}.<FORMAT>" in the output directory).
+    #[arg(long = "output-file", value_hint = ValueHint::FilePath)]
+    pub output_file: Option<PathBuf>,
+
+    /// Arguments for compilation.
+    #[clap(flatten)]
+    pub args: CompileArgs,
+}
+
+/// The format of exported images.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Svg => "svg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// Arguments selecting the source files of notes.
+#[derive(Debug, Clone, Default, Args)]
+pub struct SourceArgs {
     /// Path to input directory (defaults to config or "typ").
     #[clap(value_hint = ValueHint::DirPath)]
     pub input: Option<PathBuf>,
 
+    /// The profile of the configuration file to build with, e.g. "public".
+    #[arg(long = "profile", env = "WB_PROFILE", value_name = "NAME")]
+    pub profile: Option<String>,
+}
+
+/// Arguments for compilation and watching.
+#[derive(Debug, Clone, Default, Args)]
+pub struct CompileArgs {
+    /// Arguments selecting the source files.
+    #[clap(flatten)]
+    pub sources: SourceArgs,
+
     /// Path to public assets directory (defaults to config or "public").
     #[clap(long = "public-dir", value_hint = ValueHint::DirPath)]
     pub public: Option<PathBuf>,
@@ -175,9 +373,10 @@ pub struct CompileArgs {
     #[clap(flatten)]
     pub site: SiteArgs,
 
-    // /// The format of the output file, inferred from the extension by default.
-    // #[arg(long = "format", short = 'f', default_value = "all")]
-    // pub format: OutputFormat,
+    /// The format(s) to produce (defaults to config or "all").
+    #[arg(long = "format", short = 'f')]
+    pub format: Option<OutputFormat>,
+
     /// World arguments.
     #[clap(flatten)]
     pub world: WorldArgs,
@@ -193,7 +392,7 @@ pub struct CompileArgs {
 }
 
 /// Site configuration overrides.
-#[derive(Debug, Clone, Args)]
+#[derive(Debug, Clone, Default, Args)]
 pub struct SiteArgs {
     /// The domain of the site used for generating absolute URLs.
     #[arg(long = "site-domain", value_name = "DOMAIN")]
@@ -210,11 +409,20 @@ pub struct SiteArgs {
         value_name = "BOOL"
     )]
     pub trailing_slash: Option<bool>,
+
+    /// The pattern of the paths of note pages (for example, "/{taxon}/{id}/").
+    #[arg(long = "site-permalink", value_name = "PATTERN")]
+    pub permalink: Option<String>,
+
+    /// The ID of the note written at the root of the site (defaults to
+    /// "index").
+    #[arg(long = "site-home", value_name = "ID")]
+    pub home: Option<String>,
 }
 
 /// Arguments for the construction of a world. Shared by compile, watch, and
 /// query.
-#[derive(Debug, Clone, Args)]
+#[derive(Debug, Clone, Default, Args)]
 pub struct WorldArgs {
     /// Configures the project root (for absolute paths).
     #[clap(long = "root", env = "WEIBIAN_ROOT", value_name = "DIR", value_hint = ValueHint::DirPath)]
@@ -250,7 +458,7 @@ pub struct WorldArgs {
 }
 
 /// Arguments for configuration the process of compilaton itself.
-#[derive(Debug, Clone, Args)]
+#[derive(Debug, Clone, Default, Args)]
 pub struct ProcessArgs {
     /// Number of parallel jobs spawned during compilation. Defaults to number
     /// of CPUs. Setting it to 1 disables parallelism.
@@ -263,7 +471,7 @@ pub struct ProcessArgs {
 }
 
 /// Arguments related to where packages are stored in the system.
-#[derive(Debug, Clone, Args)]
+#[derive(Debug, Clone, Default, Args)]
 pub struct PackageArgs {
     /// Custom path to local packages, defaults to system-dependent location.
     #[clap(long = "package-path", env = "TYPST_PACKAGE_PATH", value_name = "DIR")]
@@ -279,7 +487,7 @@ pub struct PackageArgs {
 }
 
 /// Common arguments to customize available fonts.
-#[derive(Debug, Clone, Parser)]
+#[derive(Debug, Clone, Default, Parser)]
 pub struct FontArgs {
     /// Adds additional directories that are recursively searched for fonts.
     ///
@@ -333,13 +541,27 @@ macro_rules! display_possible_values {
 }
 
 /// Which format to use for the generated output file.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, ValueEnum)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
     Pdf,
     Html,
+    #[default]
     All,
 }
 
+impl OutputFormat {
+    /// Whether the HTML site is produced.
+    pub fn includes_html(self) -> bool {
+        matches!(self, Self::Html | Self::All)
+    }
+
+    /// Whether PDFs are produced.
+    pub fn includes_pdf(self) -> bool {
+        matches!(self, Self::Pdf | Self::All)
+    }
+}
+
 display_possible_values!(OutputFormat);
 
 /// Which format to use for diagnostics.
@@ -353,17 +575,20 @@ pub enum DiagnosticFormat {
 display_possible_values!(DiagnosticFormat);
 
 /// A PDF standard that Typst can enforce conformance with.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, ValueEnum, Deserialize)]
 #[allow(non_camel_case_types)]
 pub enum PdfStandard {
     /// PDF 1.7.
     #[value(name = "1.7")]
+    #[serde(rename = "1.7")]
     V_1_7,
     /// PDF/A-2b.
     #[value(name = "a-2b")]
+    #[serde(rename = "a-2b")]
     A_2b,
     /// PDF/A-3b.
     #[value(name = "a-3b")]
+    #[serde(rename = "a-3b")]
     A_3b,
 }
 
@@ -1,5 +1,5 @@
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -7,12 +7,16 @@ use crate::error::StrResult;
 use ecow::eco_format;
 use ego_tree::{NodeId, NodeRef};
 use html5ever::LocalName;
-use scraper::{Html, Node, Selector};
+use scraper::{ElementRef, Html, Node, Selector};
 use serde::Serialize;
 use tera::{Context, Error as TeraError, Tera, Value as TeraValue};
 
-use crate::config::{BuildConfig, SiteSettings};
+use crate::config::{
+    BuildConfig, FootnotePlacement, RenderSettings, SiteSettings, TransclusionMode,
+};
+use crate::head::{HeadDefaults, PageHead};
 use crate::html::{HtmlNote, add_class_to_element};
+use crate::permalink::{Aliases, Permalinks};
 
 struct Note {
     id: String,
@@ -23,50 +27,65 @@ struct Note {
 }
 
 struct ProcessedNote {
-    head_html: String,
-    body_html: String,
+    head: PageHead,
     metadata: HashMap<String, String>,
     title: Option<String>,
-}
-
-struct RenderedNote {
+    /// The body of the note with its transclusions inlined and its footnotes
+    /// listed after it, before numbering and links are rendered.
     body_html: String,
-    metadata: HashMap<String, String>,
-    citations: Vec<String>,
-    related: Vec<String>,
+    /// The leading `<summary>` of the body, holding the title and metadata.
+    header_html: String,
+    /// The header followed by the excerpt of the note.
+    summary_html: String,
+    /// The number of elements of the body in the source of the note, with
+    /// every transclusion counted as the size of its target.
+    size: usize,
 }
 
-trait TransclusionLookup {
-    fn metadata(&self, id: &str) -> Option<&HashMap<String, String>>;
-    fn body_html(&self, id: &str) -> Option<&str>;
+/// Everything needed to render notes and the transclusions in them.
+struct PageRenderer<'a> {
+    notes: &'a HashMap<String, Note>,
+    /// The processed notes; while notes are processed, only the ones they
+    /// transclude.
+    processed_notes: &'a HashMap<String, ProcessedNote>,
+    note_ids: &'a HashSet<String>,
+    site: &'a SiteSettings,
+    permalinks: &'a Permalinks,
+    render: &'a RenderSettings,
+    templates: &'a Tera,
+    lazy_targets: &'a RefCell<HashSet<String>>,
+    /// When rendering the chapters of a book, the notes that have a chapter
+    /// of their own; transclusions of them are replaced by links.
+    chapters: Option<&'a HashSet<String>>,
 }
 
-impl TransclusionLookup for HashMap<String, ProcessedNote> {
-    fn metadata(&self, id: &str) -> Option<&HashMap<String, String>> {
-        self.get(id).map(|note| &note.metadata)
-    }
-
-    fn body_html(&self, id: &str) -> Option<&str> {
-        self.get(id).map(|note| note.body_html.as_str())
-    }
+/// A note rendered as a chapter of an exported book.
+pub struct Chapter {
+    pub id: String,
+    pub title: String,
+    pub lang: Option<String>,
+    pub body_html: String,
 }
 
-impl TransclusionLookup for HashMap<String, RenderedNote> {
-    fn body_html(&self, id: &str) -> Option<&str> {
-        self.get(id).map(|note| note.body_html.as_str())
-    }
-
-    fn metadata(&self, id: &str) -> Option<&HashMap<String, String>> {
-        self.get(id).map(|note| &note.metadata)
-    }
+struct RenderedPage {
+    body_html: String,
+    citations: Vec<String>,
+    related: Vec<String>,
+    /// The number of the first transclusion of each note on the page.
+    note_numbers: HashMap<String, String>,
 }
 
 #[derive(Serialize)]
 struct Heading {
     level: u8,
     id: String,
+    /// The page of the note the heading is the title of, or the anchor of
+    /// the heading otherwise.
+    href: String,
     content: String,
     disable_numbering: bool,
+    number: Option<String>,
+    label: Option<String>,
     children: Vec<Heading>,
 }
 
@@ -81,7 +100,9 @@ struct NoteTemplateContext<'a> {
     id: &'a str,
     title: Option<&'a str>,
     metadata: &'a HashMap<String, String>,
-    head: &'a str,
+    /// The head serialized, e.g. for `{{ note.head | safe }}`.
+    head: String,
+    page_head: PageHead,
     content: &'a str,
     backmatter_sections: Vec<BackmatterSection>,
     toc: &'a [Heading],
@@ -92,6 +113,8 @@ struct LinkTemplateContext<'a> {
     target: &'a str,
     text: &'a str,
     href: &'a str,
+    number: Option<&'a str>,
+    label: Option<&'a str>,
 }
 
 #[derive(Serialize)]
@@ -99,27 +122,55 @@ struct CitationTemplateContext<'a> {
     target: &'a str,
     text: &'a str,
     href: &'a str,
+    number: Option<&'a str>,
+    label: Option<&'a str>,
 }
 
 #[derive(Serialize)]
 struct TransclusionTemplateContext<'a> {
     target: &'a str,
+    mode: TransclusionMode,
     show_metadata: bool,
     expanded: bool,
     disable_numbering: bool,
     demote_headings: usize,
+    lazy: bool,
+    fragment_href: Option<&'a str>,
     metadata: HashMap<String, String>,
     content: &'a str,
 }
 
+#[derive(Serialize)]
+struct RedirectTemplateContext<'a> {
+    alias: &'a str,
+    target: &'a str,
+    href: &'a str,
+    /// The absolute URL of the target, if the site has a domain.
+    url: &'a str,
+}
+
+/// A note that a 404 page can suggest.
+#[derive(Serialize)]
+struct NotFoundNoteContext<'a> {
+    id: &'a str,
+    title: &'a str,
+    taxon: Option<&'a str>,
+    href: String,
+}
+
 #[derive(Serialize)]
 struct SiteTemplateContext<'a> {
     root_dir: &'a str,
     trailing_slash: bool,
     domain: Option<&'a str>,
+    home: &'a str,
 }
 
-pub fn process_html(build_config: &BuildConfig, html_notes: Vec<HtmlNote>) -> StrResult<()> {
+pub fn process_html(
+    build_config: &BuildConfig,
+    html_notes: Vec<HtmlNote>,
+    permalinks: &Permalinks,
+) -> StrResult<()> {
     let public_dir = &build_config.public_directory;
     let output_dir = &build_config.output_directory;
     let templates = load_templates()?;
@@ -128,50 +179,37 @@ pub fn process_html(build_config: &BuildConfig, html_notes: Vec<HtmlNote>) -> St
     let order = topo_sort_transclusions(&notes)?;
 
     let note_ids: HashSet<String> = notes.keys().cloned().collect();
-    let mut processed_notes = HashMap::new();
-
-    for note_id in &order {
-        let note = notes
-            .get(note_id)
-            .ok_or_else(|| eco_format!("missing note {note_id} during processing"))?;
-
-        let processed_note = process_note(note, &processed_notes, &build_config.site, &templates)?;
-        processed_notes.insert(note_id.clone(), processed_note);
-    }
+    let lazy_targets = RefCell::new(HashSet::new());
+    let processed_notes = process_notes(
+        &notes,
+        &order,
+        &PageRenderer {
+            notes: &notes,
+            processed_notes: &HashMap::new(),
+            note_ids: &note_ids,
+            site: &build_config.site,
+            permalinks,
+            render: &build_config.render,
+            templates: &templates,
+            lazy_targets: &lazy_targets,
+            chapters: None,
+        },
+    )?;
 
     let backlinks = compute_backlinks(&notes);
     let contexts = compute_contexts(&notes);
     let transcluded_descendants = compute_transcluded_descendants(&notes, &order);
-    let mut rendered_notes = HashMap::new();
-    for note_id in &order {
-        let note = notes
-            .get(note_id)
-            .ok_or_else(|| eco_format!("missing note {note_id} during rendering"))?;
-        let processed = processed_notes
-            .get(note_id)
-            .ok_or_else(|| eco_format!("missing processed note for {note_id}"))?;
-        let (body_html, mut citations, mut related) = render_links_in_body(
-            processed.body_html.as_str(),
-            Some(note.path.as_path()),
-            &note_ids,
-            &processed_notes,
-            &build_config.site,
-            &templates,
-        )?;
-        if let Some(excluded) = transcluded_descendants.get(note_id) {
-            citations.retain(|id| !excluded.contains(id));
-            related.retain(|id| !excluded.contains(id));
-        }
-        rendered_notes.insert(
-            note_id.clone(),
-            RenderedNote {
-                body_html,
-                metadata: processed.metadata.clone(),
-                citations,
-                related,
-            },
-        );
-    }
+    let renderer = PageRenderer {
+        notes: &notes,
+        processed_notes: &processed_notes,
+        note_ids: &note_ids,
+        site: &build_config.site,
+        permalinks,
+        render: &build_config.render,
+        templates: &templates,
+        lazy_targets: &lazy_targets,
+        chapters: None,
+    };
 
     fs::create_dir_all(output_dir).map_err(|err| {
         eco_format!(
@@ -184,31 +222,36 @@ pub fn process_html(build_config: &BuildConfig, html_notes: Vec<HtmlNote>) -> St
         copy_dir_all(public_dir, output_dir)?;
     }
 
+    // The IDs taken by the pages each lazy fragment is loaded into.
+    let fragment_targets: HashMap<String, String> = lazy_targets
+        .borrow()
+        .iter()
+        .map(|id| (build_fragment_href(id, &build_config.site), id.clone()))
+        .collect();
+    let mut fragment_hosts: HashMap<String, HashSet<String>> = HashMap::new();
+
     for (note_id, note) in &notes {
         let processed = processed_notes
             .get(note_id)
             .ok_or_else(|| eco_format!("missing processed note for {note_id}"))?;
-        let rendered = rendered_notes
-            .get(note_id)
-            .ok_or_else(|| eco_format!("missing rendered note for {note_id}"))?;
-        let backmatter_sections = build_backmatter_sections(
-            note_id,
-            &backlinks,
-            &contexts,
-            rendered.citations.as_slice(),
-            rendered.related.as_slice(),
-            &rendered_notes,
-            &templates,
-            &build_config.site,
-        )?;
-        let toc = build_toc(rendered.body_html.as_str())?;
+        let mut page = render_page(note, processed, &renderer)?;
+        if let Some(excluded) = transcluded_descendants.get(note_id) {
+            page.citations.retain(|id| !excluded.contains(id));
+            page.related.retain(|id| !excluded.contains(id));
+        }
+        let backmatter_sections =
+            build_backmatter_sections(note_id, &backlinks, &contexts, &page, &renderer)?;
+        let toc = build_toc(page.body_html.as_str(), &renderer)?;
+
+        let page_head = build_page_head(note_id, processed, &note_ids, build_config, permalinks)?;
 
         let note_context = NoteTemplateContext {
             id: note_id.as_str(),
             title: processed.title.as_deref(),
             metadata: &processed.metadata,
-            head: processed.head_html.as_str(),
-            content: rendered.body_html.as_str(),
+            head: page_head.render(),
+            page_head,
+            content: page.body_html.as_str(),
             backmatter_sections,
             toc: &toc,
         };
@@ -217,45 +260,237 @@ pub fn process_html(build_config: &BuildConfig, html_notes: Vec<HtmlNote>) -> St
         context.insert("note", &note_context);
         context.insert("site", &site_context);
         let final_html = render_template(&templates, "note.html", &context)?;
-
-        let output_path = output_path_for_note(output_dir, &note.id, &build_config.site);
-        if let Some(parent) = output_path.parent() {
-            fs::create_dir_all(parent).map_err(|err| {
-                eco_format!(
-                    "failed to create output directory {}: {err}",
-                    parent.display()
-                )
-            })?;
+        if !fragment_targets.is_empty() {
+            let document = Html::parse_document(&final_html);
+            record_fragment_host(
+                document.tree.root(),
+                collect_ids(document.tree.root()),
+                &fragment_targets,
+                &mut fragment_hosts,
+            );
         }
-        fs::write(&output_path, final_html).map_err(|err| {
-            eco_format!(
-                "failed to write output file {}: {err}",
-                output_path.display()
-            )
-        })?;
+
+        let output_path = permalinks.output_path(output_dir, &note.id);
+        write_output_file(&output_path, &final_html)?;
+    }
+
+    if build_config.site.not_found
+        && templates
+            .get_template_names()
+            .any(|name| name == "404.html")
+    {
+        let html = render_not_found(&templates, &processed_notes, build_config, permalinks)?;
+        write_output_file(&output_dir.join("404.html"), &html)?;
+    }
+
+    for redirect in permalinks.redirects() {
+        let href = permalinks.href(&redirect.target, &build_config.site);
+        let redirect_context = RedirectTemplateContext {
+            alias: &redirect.alias,
+            target: &redirect.target,
+            href: &href,
+            url: &build_absolute_url(&href, &build_config.site),
+        };
+        let html = render_redirect(&templates, &build_config.site, &redirect_context)?;
+        let output_path = permalinks.redirect_output_path(output_dir, redirect);
+        write_output_file(&output_path, &html)?;
+    }
+
+    // Every note is processed before the pages are rendered, so the lazily
+    // transcluded notes are all known by now. A fragment is inserted into the
+    // pages it is loaded into, so its IDs must not collide with theirs; the
+    // fragments of a note are loaded into it after it is, so notes are
+    // visited transcluding ones first.
+    let lazy_targets = lazy_targets.borrow();
+    for note_id in order.iter().rev().filter(|id| lazy_targets.contains(*id)) {
+        let note = notes
+            .get(note_id)
+            .ok_or_else(|| eco_format!("missing note {note_id} during rendering"))?;
+        let processed = processed_notes
+            .get(note_id)
+            .ok_or_else(|| eco_format!("missing processed note for {note_id}"))?;
+        let page = render_page(note, processed, &renderer)?;
+        let mut used_ids = fragment_hosts.remove(note_id).unwrap_or_default();
+        let body_html = deduplicate_ids(&page.body_html, &mut used_ids)?;
+        record_fragment_host(
+            Html::parse_fragment(&body_html).tree.root(),
+            used_ids,
+            &fragment_targets,
+            &mut fragment_hosts,
+        );
+        let output_path = output_path_for_fragment(output_dir, note_id);
+        write_output_file(&output_path, &body_html)?;
     }
 
     Ok(())
 }
 
-fn process_note(
+/// Renders every note of a book as a chapter of its own. Links to notes are
+/// rendered as for the site, so they still have to be rewritten to point to
+/// the chapters.
+pub fn render_chapters(
+    build_config: &BuildConfig,
+    html_notes: Vec<HtmlNote>,
+    ids: &[String],
+    permalinks: &Permalinks,
+) -> StrResult<Vec<Chapter>> {
+    let templates = load_templates()?;
+    let notes = load_notes(html_notes)?;
+    let order = topo_sort_transclusions(&notes)?;
+
+    let note_ids: HashSet<String> = notes.keys().cloned().collect();
+    let chapter_ids: HashSet<String> = ids.iter().cloned().collect();
+    let lazy_targets = RefCell::new(HashSet::new());
+    let mut renderer = PageRenderer {
+        notes: &notes,
+        processed_notes: &HashMap::new(),
+        note_ids: &note_ids,
+        site: &build_config.site,
+        permalinks,
+        render: &build_config.render,
+        templates: &templates,
+        lazy_targets: &lazy_targets,
+        chapters: Some(&chapter_ids),
+    };
+    let processed_notes = process_notes(&notes, &order, &renderer)?;
+    renderer.processed_notes = &processed_notes;
+
+    let mut chapters = Vec::with_capacity(ids.len());
+    for id in ids {
+        let note = notes
+            .get(id)
+            .ok_or_else(|| eco_format!("note {id} does not exist"))?;
+        let processed = &processed_notes[id];
+        let page = render_page(note, processed, &renderer)?;
+        chapters.push(Chapter {
+            id: id.clone(),
+            title: processed.title.clone().unwrap_or_else(|| id.clone()),
+            lang: processed.metadata.get("lang").cloned(),
+            body_html: page.body_html,
+        });
+    }
+    Ok(chapters)
+}
+
+/// Processes every note in `order`, which lists transcluded notes before the
+/// notes transcluding them, so that each note is rendered once and then
+/// inlined wherever it is transcluded.
+fn process_notes(
+    notes: &HashMap<String, Note>,
+    order: &[String],
+    renderer: &PageRenderer,
+) -> StrResult<HashMap<String, ProcessedNote>> {
+    let mut processed_notes = HashMap::new();
+    for note_id in order {
+        let note = notes
+            .get(note_id)
+            .ok_or_else(|| eco_format!("missing note {note_id} during processing"))?;
+        let renderer = PageRenderer {
+            processed_notes: &processed_notes,
+            ..*renderer
+        };
+        let processed = process_note(note, &renderer)?;
+        processed_notes.insert(note_id.clone(), processed);
+    }
+    Ok(processed_notes)
+}
+
+/// Renders a processed note as an output page: its headings are numbered with
+/// respect to the whole page, its footnotes are renumbered, and then internal
+/// links and citations are rendered.
+fn render_page(
     note: &Note,
-    processed_notes: &HashMap<String, ProcessedNote>,
-    site: &SiteSettings,
-    templates: &Tera,
-) -> StrResult<ProcessedNote> {
-    let body_html = render_note_body(note, processed_notes, site, templates)?;
-    let head_html = render_note_head(note)?;
+    processed: &ProcessedNote,
+    renderer: &PageRenderer,
+) -> StrResult<RenderedPage> {
+    let (body_html, note_numbers) = number_headings(&processed.body_html)?;
+    let body_html = number_footnotes(&body_html, renderer.render.footnotes)?;
+    let (body_html, citations, related) = render_links_in_body(
+        body_html.as_str(),
+        Some(note.path.as_path()),
+        renderer,
+        &note_numbers,
+    )?;
+    Ok(RenderedPage {
+        body_html,
+        citations,
+        related,
+        note_numbers,
+    })
+}
+
+fn write_output_file(output_path: &Path, contents: &str) -> StrResult<()> {
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent).map_err(|err| {
+            eco_format!(
+                "failed to create output directory {}: {err}",
+                parent.display()
+            )
+        })?;
+    }
+    fs::write(output_path, contents).map_err(|err| {
+        eco_format!(
+            "failed to write output file {}: {err}",
+            output_path.display()
+        )
+    })
+}
+
+/// Renders the body, the header and the summary of a note once, inlining the
+/// processed notes it transcludes.
+fn process_note(note: &Note, renderer: &PageRenderer) -> StrResult<ProcessedNote> {
+    let head = collect_note_head(note)?;
     let metadata = crate::html::extract_metadata(&note.document)?;
     let title = crate::html::extract_note_title(&note.document, &metadata)?;
+
+    let body = note_body(note)?;
+    let size = source_size(body, renderer.processed_notes);
+    let note_ids = collect_ids(body);
+    let render = |part: fn(&Note, &RenderContext) -> StrResult<String>| {
+        let used_ids = RefCell::new(note_ids.clone());
+        let footnotes = RefCell::new(Vec::new());
+        let context = RenderContext {
+            mode: RenderMode::Transclusion {
+                renderer,
+                used_ids: &used_ids,
+                footnotes: &footnotes,
+            },
+            note_path: Some(&note.path),
+        };
+        let mut html = part(note, &context)?;
+        append_footnotes(&mut html, &footnotes.borrow());
+        StrResult::Ok(html)
+    };
     Ok(ProcessedNote {
-        head_html,
-        body_html,
+        head,
         metadata,
         title,
+        body_html: render(render_note_body)?,
+        header_html: render(render_note_header)?,
+        summary_html: render(render_note_summary)?,
+        size,
     })
 }
 
+/// Counts the elements under `root`, counting a transclusion as the size of
+/// its target, so that the size of a transclusion is known before any note
+/// is rendered.
+fn source_size(root: NodeRef<Node>, processed_notes: &HashMap<String, ProcessedNote>) -> usize {
+    root.descendants()
+        .filter_map(|node| node.value().as_element())
+        .map(|element| {
+            if !element.name().eq_ignore_ascii_case("wb-transclusion") {
+                return 1;
+            }
+            element
+                .attr("target")
+                .map(crate::html::normalize_target)
+                .and_then(|target| processed_notes.get(&target))
+                .map_or(1, |target| target.size)
+        })
+        .sum()
+}
+
 fn load_templates() -> StrResult<Tera> {
     let pattern = ".wb/templates/**/*.html";
     let mut tera = Tera::new(pattern)
@@ -276,20 +511,77 @@ fn site_template_context(site: &SiteSettings) -> SiteTemplateContext<'_> {
         root_dir: site.root_dir.as_str(),
         trailing_slash: site.trailing_slash,
         domain: site.domain.as_deref(),
+        home: site.home.as_str(),
+    }
+}
+
+/// Renders the 404 page with the `404.html` template, which receives every
+/// note so that it can suggest the ones resembling the requested path.
+fn render_not_found(
+    templates: &Tera,
+    processed_notes: &HashMap<String, ProcessedNote>,
+    build_config: &BuildConfig,
+    permalinks: &Permalinks,
+) -> StrResult<String> {
+    let site = &build_config.site;
+    let mut notes: Vec<NotFoundNoteContext> = processed_notes
+        .iter()
+        .map(|(id, processed)| NotFoundNoteContext {
+            id,
+            title: processed.title.as_deref().unwrap_or(id),
+            taxon: processed.metadata.get("taxon").map(String::as_str),
+            href: permalinks.href(id, site),
+        })
+        .collect();
+    notes.sort_by(|a, b| a.id.cmp(b.id));
+
+    let site_context = site_template_context(site);
+    let mut context = Context::new();
+    context.insert("notes", &notes);
+    context.insert("site", &site_context);
+    render_template(templates, "404.html", &context)
+}
+
+/// Renders the page redirecting from an alias to the note it stands for, with
+/// the `redirect.html` template if there is one.
+fn render_redirect(
+    templates: &Tera,
+    site: &SiteSettings,
+    redirect: &RedirectTemplateContext,
+) -> StrResult<String> {
+    if templates
+        .get_template_names()
+        .any(|name| name == "redirect.html")
+    {
+        let site_context = site_template_context(site);
+        let mut context = Context::new();
+        context.insert("redirect", redirect);
+        context.insert("site", &site_context);
+        return render_template(templates, "redirect.html", &context);
     }
+    let href = escape_attr(redirect.href);
+    let url = escape_attr(redirect.url);
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Redirecting to {target}</title>\n<link rel=\"canonical\" href=\"{url}\">\n<meta name=\"robots\" content=\"noindex\">\n<meta http-equiv=\"refresh\" content=\"0; url={href}\">\n</head>\n<body>\n<p>This note has moved to <a href=\"{href}\">{href}</a>.</p>\n</body>\n</html>\n",
+        target = escape_text(redirect.target),
+    ))
 }
 
 fn render_internal_link(
     templates: &Tera,
     site: &SiteSettings,
+    href: &str,
     target: &str,
     text: &str,
+    number: Option<&str>,
+    label: Option<&str>,
 ) -> StrResult<String> {
-    let href = build_note_href(target, site);
     let link = LinkTemplateContext {
         target,
         text,
-        href: href.as_str(),
+        href,
+        number,
+        label,
     };
     let site_context = site_template_context(site);
     let mut context = Context::new();
@@ -301,14 +593,18 @@ fn render_internal_link(
 fn render_citation(
     templates: &Tera,
     site: &SiteSettings,
+    href: &str,
     target: &str,
     text: &str,
+    number: Option<&str>,
+    label: Option<&str>,
 ) -> StrResult<String> {
-    let href = build_note_href(target, site);
     let citation = CitationTemplateContext {
         target,
         text,
-        href: href.as_str(),
+        href,
+        number,
+        label,
     };
     let site_context = site_template_context(site);
     let mut context = Context::new();
@@ -329,22 +625,378 @@ fn render_transclusion(
     render_template(templates, "transclusion.html", &context)
 }
 
-fn prepare_transclusion_content(body_html: &str) -> StrResult<String> {
-    Ok(body_html.to_string())
+fn prepare_transclusion_content(
+    body_html: &str,
+    used_ids: &RefCell<HashSet<String>>,
+) -> StrResult<String> {
+    deduplicate_ids(body_html, &mut used_ids.borrow_mut())
 }
 
-fn load_notes(html_notes: Vec<HtmlNote>) -> StrResult<HashMap<String, Note>> {
-    let mut notes = HashMap::new();
+fn collect_ids(root: NodeRef<Node>) -> HashSet<String> {
+    root.descendants()
+        .filter_map(|node| node.value().as_element()?.attr("id"))
+        .map(str::to_string)
+        .collect()
+}
 
-    for note in html_notes {
-        if notes.contains_key(&note.id) {
-            return Err(eco_format!(
-                "duplicate note id {} found while reading {}",
-                note.id,
-                note.source_path.display()
-            ));
+/// Adds `ids`, the IDs taken by a page, to the ones taken by the hosts of
+/// every lazy fragment the page loads.
+fn record_fragment_host(
+    root: NodeRef<Node>,
+    ids: HashSet<String>,
+    fragment_targets: &HashMap<String, String>,
+    fragment_hosts: &mut HashMap<String, HashSet<String>>,
+) {
+    let targets = root.descendants().filter_map(|node| {
+        let href = node.value().as_element()?.attr("data-wb-fragment")?;
+        fragment_targets.get(href)
+    });
+    for target in targets {
+        fragment_hosts
+            .entry(target.clone())
+            .or_default()
+            .extend(ids.iter().cloned());
+    }
+}
+
+/// Renames the `id` attributes in `html` that are already taken by the page it
+/// is inlined into, and rewrites the fragment links inside `html` that point
+/// to the renamed elements.
+///
+/// `html` can hold several elements with the same ID, e.g. when it transcludes
+/// a note twice, so a link is resolved to the element that is closest to it in
+/// the tree, and is left alone if that element keeps its ID.
+fn deduplicate_ids(html: &str, used_ids: &mut HashSet<String>) -> StrResult<String> {
+    let mut fragment = Html::parse_fragment(html);
+    let elements_with_id: Vec<(NodeId, String)> = fragment
+        .tree
+        .root()
+        .descendants()
+        .filter_map(|node| {
+            let id = node.value().as_element()?.attr("id")?;
+            Some((node.id(), id.to_string()))
+        })
+        .collect();
+
+    // The elements with each original ID, with their new ID if renamed.
+    let mut elements: HashMap<String, Vec<(NodeId, Option<String>)>> = HashMap::new();
+    let mut renamed_any = false;
+    for (node_id, id) in elements_with_id {
+        let renamed = if used_ids.insert(id.clone()) {
+            None
+        } else {
+            let renamed = unique_id(&id, used_ids);
+            used_ids.insert(renamed.clone());
+            with_element_mut(&mut fragment, node_id, |element| {
+                crate::html::set_attr(element, "id", &renamed);
+            });
+            renamed_any = true;
+            Some(renamed)
+        };
+        elements.entry(id).or_default().push((node_id, renamed));
+    }
+
+    if !renamed_any {
+        return Ok(html.to_string());
+    }
+
+    let links: Vec<(NodeId, String)> = fragment
+        .tree
+        .root()
+        .descendants()
+        .filter_map(|node| {
+            let href = node.value().as_element()?.attr("href")?;
+            let candidates = elements.get(href.strip_prefix('#')?)?;
+            let (_, renamed) = closest_element(node, candidates)?;
+            Some((node.id(), format!("#{}", renamed.as_ref()?)))
+        })
+        .collect();
+    for (node_id, href) in links {
+        with_element_mut(&mut fragment, node_id, |element| {
+            crate::html::set_attr(element, "href", &href);
+        });
+    }
+
+    render_fragment(fragment.tree.root())
+}
+
+/// Finds the candidate sharing the deepest ancestor with `node`, the first one
+/// in document order among equally close ones.
+fn closest_element<'a, T>(
+    node: NodeRef<Node>,
+    candidates: &'a [(NodeId, T)],
+) -> Option<&'a (NodeId, T)> {
+    let depths: HashMap<NodeId, usize> = std::iter::once(node)
+        .chain(node.ancestors())
+        .enumerate()
+        .map(|(depth, ancestor)| (ancestor.id(), depth))
+        .collect();
+    candidates.iter().min_by_key(|(candidate, _)| {
+        let candidate = node.tree().get(*candidate);
+        candidate
+            .into_iter()
+            .flat_map(|candidate| std::iter::once(candidate).chain(candidate.ancestors()))
+            .find_map(|ancestor| depths.get(&ancestor.id()).copied())
+            .unwrap_or(usize::MAX)
+    })
+}
+
+/// Numbers the footnotes of a rendered page.
+///
+/// Every processed note lists its footnotes after its body, so a page with
+/// transclusions has several such lists, and their references and backlinks
+/// are only numbered here. With [`FootnotePlacement::Page`], all footnotes are
+/// moved into one list at the end of the page and numbered in order of first
+/// reference; with [`FootnotePlacement::Section`], every list stays in place
+/// and is numbered on its own.
+fn number_footnotes(body_html: &str, placement: FootnotePlacement) -> StrResult<String> {
+    let mut fragment = Html::parse_fragment(body_html);
+    let mut refs: Vec<(NodeId, String)> = Vec::new();
+    let mut lists: Vec<NodeId> = Vec::new();
+    // The footnotes in document order, with the index of the list they are in.
+    let mut items: Vec<(String, NodeId, usize)> = Vec::new();
+    for node in fragment.root_element().descendants() {
+        let Some(element) = node.value().as_element() else {
+            continue;
+        };
+        match element.attr("role") {
+            Some("doc-noteref") => {
+                if let Some(target) = element.attr("href").and_then(|href| href.strip_prefix('#')) {
+                    refs.push((node.id(), target.to_string()));
+                }
+            }
+            Some("doc-endnotes") => {
+                for item in node.descendants() {
+                    if let Some(item_element) = item.value().as_element()
+                        && item_element.name() == "li"
+                        && let Some(id) = item_element.attr("id")
+                    {
+                        items.push((id.to_string(), item.id(), lists.len()));
+                    }
+                }
+                lists.push(node.id());
+            }
+            _ => {}
         }
+    }
+    if lists.is_empty() {
+        return Ok(body_html.to_string());
+    }
+
+    let list_of: HashMap<&str, usize> = items
+        .iter()
+        .map(|(id, _, list)| (id.as_str(), *list))
+        .collect();
+    let mut counters = vec![0usize; lists.len()];
+    let mut numbers: HashMap<String, usize> = HashMap::new();
+    let mut next_number = |list: usize| {
+        let counter = match placement {
+            FootnotePlacement::Page => &mut counters[0],
+            FootnotePlacement::Section => &mut counters[list],
+        };
+        *counter += 1;
+        *counter
+    };
+    for (node_id, target) in &refs {
+        let Some(&list) = list_of.get(target.as_str()) else {
+            continue;
+        };
+        if !numbers.contains_key(target) {
+            let number = next_number(list);
+            numbers.insert(target.clone(), number);
+        }
+        set_footnote_number(&mut fragment, *node_id, numbers[target]);
+    }
+    // Footnotes that are never referenced keep their place after the others.
+    for (id, _, list) in &items {
+        if !numbers.contains_key(id) {
+            let number = next_number(*list);
+            numbers.insert(id.clone(), number);
+        }
+    }
+    for (id, node_id, _) in &items {
+        let backlink = fragment.tree.get(*node_id).and_then(|item| {
+            item.descendants()
+                .find(|node| {
+                    node.value()
+                        .as_element()
+                        .is_some_and(|element| element.attr("role") == Some("doc-backlink"))
+                })
+                .map(|node| node.id())
+        });
+        if let Some(backlink) = backlink {
+            set_footnote_number(&mut fragment, backlink, numbers[id]);
+        }
+    }
+
+    let context = RenderContext {
+        mode: RenderMode::Fragment,
+        note_path: None,
+    };
+    match placement {
+        FootnotePlacement::Section => render_children(*fragment.root_element(), &context),
+        FootnotePlacement::Page => {
+            items.sort_by_key(|(id, _, _)| numbers[id]);
+            let mut footnotes_html = String::new();
+            for (_, node_id, _) in &items {
+                if let Some(item) = fragment.tree.get(*node_id) {
+                    footnotes_html.push_str(&render_node(item, &context)?);
+                }
+            }
+            for node_id in lists {
+                if let Some(mut node) = fragment.tree.get_mut(node_id) {
+                    node.detach();
+                }
+            }
+            let mut out = render_children(*fragment.root_element(), &context)?;
+            if !footnotes_html.is_empty() {
+                out.push_str(&footnote_list(&footnotes_html));
+            }
+            Ok(out)
+        }
+    }
+}
+
+/// Replaces the number shown by a footnote reference or backlink.
+fn set_footnote_number(fragment: &mut Html, node_id: NodeId, number: usize) {
+    let Some(node) = fragment.tree.get(node_id) else {
+        return;
+    };
+    let target = node
+        .descendants()
+        .find(|node| {
+            node.value()
+                .as_element()
+                .is_some_and(|element| element.name() == "sup")
+        })
+        .map_or(node_id, |node| node.id());
+    let Some(mut target) = fragment.tree.get_mut(target) else {
+        return;
+    };
+    while let Some(mut child) = target.first_child() {
+        child.detach();
+    }
+    target.append(Node::Text(scraper::node::Text {
+        text: number.to_string().into(),
+    }));
+}
+
+/// Marks `id` as used, renaming it first if it is already taken.
+fn claim_id(id: String, used_ids: &mut HashSet<String>) -> String {
+    let id = if used_ids.contains(&id) {
+        unique_id(&id, used_ids)
+    } else {
+        id
+    };
+    used_ids.insert(id.clone());
+    id
+}
+
+fn unique_id(id: &str, used_ids: &HashSet<String>) -> String {
+    (2..)
+        .map(|n| format!("{id}-{n}"))
+        .find(|candidate| !used_ids.contains(candidate))
+        .expect("unbounded range always yields an unused id")
+}
+
+/// The notes of a build by ID, and the notes each of them refers to.
+///
+/// It is shared by the backend, which rejects duplicate IDs, and the language
+/// server, which reports them.
+pub struct NoteGraph {
+    /// The index of the first note with each ID.
+    ids: HashMap<String, usize>,
+    /// The notes whose ID is already taken by an earlier note, with the index
+    /// of that note.
+    duplicates: Vec<(usize, usize)>,
+    /// The targets each note links to, cites or transcludes, as written.
+    references: Vec<BTreeSet<String>>,
+    aliases: Aliases,
+}
+
+impl NoteGraph {
+    pub fn new(notes: &[HtmlNote], aliases: Aliases) -> Self {
+        let mut ids = HashMap::new();
+        let mut duplicates = Vec::new();
+        for (index, note) in notes.iter().enumerate() {
+            match ids.get(&note.id) {
+                Some(&previous) => duplicates.push((index, previous)),
+                None => {
+                    ids.insert(note.id.clone(), index);
+                }
+            }
+        }
+        let references = notes
+            .iter()
+            .map(|note| {
+                crate::html::referenced_targets(&note.document)
+                    .into_iter()
+                    .collect()
+            })
+            .collect();
+        Self {
+            ids,
+            duplicates,
+            references,
+            aliases,
+        }
+    }
+
+    /// Whether a note has this ID.
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    /// The index of the note with an ID or an alias of it.
+    pub fn resolve(&self, target: &str) -> Option<usize> {
+        let id = self.aliases.resolve(target).unwrap_or(target);
+        self.ids.get(id).copied()
+    }
+
+    /// Every ID with the index of its note.
+    pub fn ids(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.ids.iter().map(|(id, &index)| (id.as_str(), index))
+    }
+
+    pub fn duplicates(&self) -> &[(usize, usize)] {
+        &self.duplicates
+    }
+
+    pub fn aliases(&self) -> &Aliases {
+        &self.aliases
+    }
+
+    /// The targets a note refers to.
+    pub fn references(&self, index: usize) -> &BTreeSet<String> {
+        &self.references[index]
+    }
 
+    /// The other notes referring to a note, by its ID or an alias of it.
+    pub fn referrers(&self, index: usize) -> Vec<usize> {
+        (0..self.references.len())
+            .filter(|&other| {
+                other != index
+                    && self.references[other]
+                        .iter()
+                        .any(|target| self.resolve(target) == Some(index))
+            })
+            .collect()
+    }
+}
+
+fn load_notes(html_notes: Vec<HtmlNote>) -> StrResult<HashMap<String, Note>> {
+    let graph = NoteGraph::new(&html_notes, Aliases::default());
+    if let Some(&(index, _)) = graph.duplicates().first() {
+        let note = &html_notes[index];
+        return Err(eco_format!(
+            "duplicate note id {} found while reading {}",
+            note.id,
+            note.source_path.display()
+        ));
+    }
+
+    let mut notes = HashMap::new();
+    for note in html_notes {
         let transcludes =
             crate::html::collect_targets(&note.document, "wb-transclusion", &note.source_path)?;
         let links_out =
@@ -436,12 +1088,7 @@ fn visit(
     Ok(())
 }
 
-fn render_note_body(
-    note: &Note,
-    transclusion_lookup: &dyn TransclusionLookup,
-    site: &SiteSettings,
-    templates: &Tera,
-) -> StrResult<String> {
+fn note_body(note: &Note) -> StrResult<NodeRef<'_, Node>> {
     let selector = Selector::parse("body")
         .map_err(|err| eco_format!("failed to parse selector body: {err}"))?;
     let body = note
@@ -449,36 +1096,84 @@ fn render_note_body(
         .select(&selector)
         .next()
         .ok_or_else(|| eco_format!("missing <body> in {}", note.path.display()))?;
+    Ok(*body)
+}
 
-    let context = RenderContext {
-        mode: RenderMode::Transclusion {
-            transclusion_lookup,
-            site,
-            templates,
+fn render_note_body(note: &Note, context: &RenderContext) -> StrResult<String> {
+    render_children(note_body(note)?, context)
+}
+
+/// Renders the header of a note, i.e. the leading `<summary>` of its body
+/// holding the title and metadata.
+fn render_note_header(note: &Note, context: &RenderContext) -> StrResult<String> {
+    let header = note_body(note)?.children().find(|child| {
+        child
+            .value()
+            .as_element()
+            .is_some_and(|element| element.name().eq_ignore_ascii_case("summary"))
+    });
+    match header {
+        Some(header) => render_node(header, context),
+        None => Ok(String::new()),
+    }
+}
+
+/// Renders the header of a note followed by its excerpt.
+///
+/// The excerpt is the content of the first `<wb-excerpt>` element of the body,
+/// or the first paragraph outside the header if the note has no such marker.
+fn render_note_summary(note: &Note, context: &RenderContext) -> StrResult<String> {
+    let body = note_body(note)?;
+    let mut out = render_note_header(note, context)?;
+
+    let excerpt = crate::html::find_first_element_by_tag(body, "wb-excerpt");
+    let excerpt_html = match excerpt.and_then(|id| note.document.tree.get(id)) {
+        Some(excerpt) => render_children(excerpt, context)?,
+        None => match find_first_paragraph(body) {
+            Some(paragraph) => render_node(paragraph, context)?,
+            None => String::new(),
         },
-        note_path: Some(&note.path),
     };
+    if !excerpt_html.is_empty() {
+        out.push_str("<div class=\"excerpt\">");
+        out.push_str(&excerpt_html);
+        out.push_str("</div>");
+    }
 
-    render_children(*body, &context)
+    Ok(out)
+}
+
+fn find_first_paragraph(root: NodeRef<Node>) -> Option<NodeRef<Node>> {
+    root.descendants().find(|node| {
+        node.value()
+            .as_element()
+            .is_some_and(|element| element.name().eq_ignore_ascii_case("p"))
+            && !node.ancestors().any(|ancestor| {
+                ancestor
+                    .value()
+                    .as_element()
+                    .is_some_and(|element| element.name().eq_ignore_ascii_case("summary"))
+            })
+    })
 }
 
 fn render_links_in_body(
     body_html: &str,
     note_path: Option<&Path>,
-    note_ids: &HashSet<String>,
-    processed_notes: &HashMap<String, ProcessedNote>,
-    site: &SiteSettings,
-    templates: &Tera,
+    renderer: &PageRenderer,
+    note_numbers: &HashMap<String, String>,
 ) -> StrResult<(String, Vec<String>, Vec<String>)> {
     let fragment = Html::parse_fragment(body_html);
     let citations = RefCell::new(HashSet::new());
     let related = RefCell::new(HashSet::new());
     let context = RenderContext {
         mode: RenderMode::Links {
-            note_ids,
-            processed_notes,
-            site,
-            templates,
+            note_ids: renderer.note_ids,
+            processed_notes: renderer.processed_notes,
+            note_numbers,
+            site: renderer.site,
+            permalinks: renderer.permalinks,
+            templates: renderer.templates,
             citations: Some(&citations),
             related: Some(&related),
         },
@@ -492,7 +1187,7 @@ fn render_links_in_body(
     Ok((rendered, citations, related))
 }
 
-fn render_note_head(note: &Note) -> StrResult<String> {
+fn collect_note_head(note: &Note) -> StrResult<PageHead> {
     let selector = Selector::parse("head")
         .map_err(|err| eco_format!("failed to parse selector head: {err}"))?;
     let head = note
@@ -500,13 +1195,73 @@ fn render_note_head(note: &Note) -> StrResult<String> {
         .select(&selector)
         .next()
         .ok_or_else(|| eco_format!("missing <head> in {}", note.path.display()))?;
+    Ok(PageHead::collect(head))
+}
 
-    let context = RenderContext {
-        mode: RenderMode::Fragment,
-        note_path: None,
+/// Completes the head of a note with its canonical URL, its Open Graph and
+/// Twitter tags, and the links to its translations.
+fn build_page_head(
+    note_id: &str,
+    processed: &ProcessedNote,
+    note_ids: &HashSet<String>,
+    build_config: &BuildConfig,
+    permalinks: &Permalinks,
+) -> StrResult<PageHead> {
+    let site = &build_config.site;
+    let url = build_absolute_url(&permalinks.href(note_id, site), site);
+    let image = build_config
+        .og_card
+        .as_ref()
+        .map(|_| build_absolute_url(&build_og_image_href(note_id, site), site));
+    let alternates = match processed.metadata.get("alternates") {
+        Some(value) => parse_alternates(note_id, value, note_ids, site, permalinks)?,
+        None => Vec::new(),
     };
 
-    render_children(*head, &context)
+    let mut head = processed.head.clone();
+    head.complete(&HeadDefaults {
+        url: &url,
+        title: processed.title.as_deref().unwrap_or(note_id),
+        description: processed.metadata.get("description").map(String::as_str),
+        lang: processed.metadata.get("lang").map(String::as_str),
+        image: image.as_deref(),
+        alternates: &alternates,
+    });
+    Ok(head)
+}
+
+/// Parses the `alternates` metadata of a note, a comma-separated list of
+/// `<lang>=<ID>` pairs, into the URLs of its translations.
+fn parse_alternates(
+    note_id: &str,
+    value: &str,
+    note_ids: &HashSet<String>,
+    site: &SiteSettings,
+    permalinks: &Permalinks,
+) -> StrResult<Vec<(String, String)>> {
+    let mut alternates = Vec::new();
+    for pair in value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let Some((lang, target)) = pair.split_once('=') else {
+            return Err(eco_format!(
+                "invalid alternates in {note_id}: expected <lang>=<ID>, found \"{pair}\""
+            ));
+        };
+        let target = crate::html::normalize_target(target.trim());
+        if !note_ids.contains(&target) {
+            return Err(eco_format!(
+                "alternate {target} referenced by {note_id} does not exist"
+            ));
+        }
+        alternates.push((
+            lang.trim().to_string(),
+            build_absolute_url(&permalinks.href(&target, site), site),
+        ));
+    }
+    Ok(alternates)
 }
 
 struct HeadingRef {
@@ -514,7 +1269,7 @@ struct HeadingRef {
     level: u8,
 }
 
-fn build_toc(body_html: &str) -> StrResult<Vec<Heading>> {
+fn build_toc(body_html: &str, renderer: &PageRenderer) -> StrResult<Vec<Heading>> {
     let mut toc: Vec<Heading> = Vec::new();
     let mut stack: Vec<HeadingRef> = Vec::new();
 
@@ -537,11 +1292,27 @@ fn build_toc(body_html: &str) -> StrResult<Vec<Heading>> {
             .attr("class")
             .map(|class| class.split_whitespace().any(|c| c == "disable-numbering"))
             .unwrap_or(false);
+        let number = element.attr("data-number").map(str::to_string);
+        let label = number.as_ref().map(|number| {
+            let taxon = heading_taxon(heading);
+            match taxon {
+                Some(taxon) => format!("{taxon} {number}"),
+                None => number.clone(),
+            }
+        });
+        let href = if renderer.note_ids.contains(&id) {
+            renderer.permalinks.href(&id, renderer.site)
+        } else {
+            format!("#{id}")
+        };
         let heading = Heading {
             level,
+            href,
             id,
             content,
             disable_numbering,
+            number,
+            label,
             children: Vec::new(),
         };
 
@@ -560,6 +1331,15 @@ fn build_toc(body_html: &str) -> StrResult<Vec<Heading>> {
     Ok(toc)
 }
 
+/// Returns the text of the `span.taxon` inside a heading, if any.
+fn heading_taxon(heading: ElementRef) -> Option<String> {
+    let selector = Selector::parse("span.taxon").ok()?;
+    let taxon = heading.select(&selector).next()?;
+    let text = taxon.text().collect::<String>();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
 fn push_heading(
     toc: &mut Vec<Heading>,
     parent_path: Option<&[usize]>,
@@ -636,41 +1416,41 @@ fn compute_transcluded_descendants(
     descendants
 }
 
-#[allow(clippy::too_many_arguments)]
 fn build_backmatter_sections(
     note_id: &str,
     backlinks: &HashMap<String, Vec<String>>,
     contexts: &HashMap<String, Vec<String>>,
-    references: &[String],
-    related: &[String],
-    transclusion_lookup: &dyn TransclusionLookup,
-    templates: &Tera,
-    site: &SiteSettings,
+    page: &RenderedPage,
+    renderer: &PageRenderer,
 ) -> StrResult<Vec<BackmatterSection>> {
+    let used_ids = RefCell::new(collect_ids(
+        Html::parse_fragment(&page.body_html).tree.root(),
+    ));
+    let footnotes = RefCell::new(Vec::new());
+    let context = RenderContext {
+        mode: RenderMode::Transclusion {
+            renderer,
+            used_ids: &used_ids,
+            footnotes: &footnotes,
+        },
+        note_path: None,
+    };
+
     let mut sections = vec![];
     if let Some(ids) = contexts.get(note_id) {
-        let section =
-            render_backmatter_section("Contexts", ids, transclusion_lookup, templates, site)?;
+        let section = render_backmatter_section("Contexts", ids, &context, page)?;
         sections.push(section);
     }
-    if !references.is_empty() {
-        let section = render_backmatter_section(
-            "References",
-            references,
-            transclusion_lookup,
-            templates,
-            site,
-        )?;
+    if !page.citations.is_empty() {
+        let section = render_backmatter_section("References", &page.citations, &context, page)?;
         sections.push(section);
     }
     if let Some(ids) = backlinks.get(note_id) {
-        let section =
-            render_backmatter_section("Backlinks", ids, transclusion_lookup, templates, site)?;
+        let section = render_backmatter_section("Backlinks", ids, &context, page)?;
         sections.push(section);
     }
-    if !related.is_empty() {
-        let section =
-            render_backmatter_section("Related", related, transclusion_lookup, templates, site)?;
+    if !page.related.is_empty() {
+        let section = render_backmatter_section("Related", &page.related, &context, page)?;
         sections.push(section);
     }
     Ok(sections)
@@ -679,10 +1459,15 @@ fn build_backmatter_sections(
 fn render_backmatter_section(
     title: &str,
     included_note_ids: &[String],
-    transclusion_lookup: &dyn TransclusionLookup,
-    templates: &Tera,
-    site: &SiteSettings,
+    context: &RenderContext,
+    page: &RenderedPage,
 ) -> StrResult<BackmatterSection> {
+    let RenderMode::Transclusion { renderer, .. } = context.mode else {
+        return Err(eco_format!(
+            "backmatter must be rendered in transclusion mode"
+        ));
+    };
+
     if included_note_ids.is_empty() {
         return Ok(BackmatterSection {
             title: title.to_string(),
@@ -699,6 +1484,8 @@ fn render_backmatter_section(
             String::new()
                 + "<wb-transclusion target=\""
                 + id.as_str()
+                + "\" mode=\""
+                + renderer.render.backmatter_mode.as_str()
                 + "\" show-metadata=\"true\" expanded=\"false\" hide-numbering=\"true\" demote-headings=\"1\"></wb-transclusion>"
         })
         .collect::<String>();
@@ -714,7 +1501,10 @@ fn render_backmatter_section(
         links_out: included_ids, // unused
     };
 
-    let body_html = render_note_body(&virtual_note, transclusion_lookup, site, templates)?;
+    let body_html = render_note_body(&virtual_note, context)?;
+    let body_html = number_footnotes(&body_html, FootnotePlacement::Section)?;
+    let (body_html, _, _) =
+        render_links_in_body(body_html.as_str(), None, renderer, &page.note_numbers)?;
 
     let section = BackmatterSection {
         title: title.to_string(),
@@ -729,16 +1519,27 @@ struct RenderContext<'a> {
     note_path: Option<&'a Path>,
 }
 
+/// A `<wb-footnote>` of a note, replaced by a reference to its list item.
+struct Footnote {
+    id: String,
+    ref_id: String,
+    content: String,
+}
+
 enum RenderMode<'a> {
     Transclusion {
-        transclusion_lookup: &'a dyn TransclusionLookup,
-        site: &'a SiteSettings,
-        templates: &'a Tera,
+        renderer: &'a PageRenderer<'a>,
+        /// The IDs used by the note being rendered.
+        used_ids: &'a RefCell<HashSet<String>>,
+        /// The footnotes of the note being rendered, listed after its body.
+        footnotes: &'a RefCell<Vec<Footnote>>,
     },
     Links {
         note_ids: &'a HashSet<String>,
         processed_notes: &'a HashMap<String, ProcessedNote>,
+        note_numbers: &'a HashMap<String, String>,
         site: &'a SiteSettings,
+        permalinks: &'a Permalinks,
         templates: &'a Tera,
         citations: Option<&'a RefCell<HashSet<String>>>,
         related: Option<&'a RefCell<HashSet<String>>>,
@@ -786,49 +1587,26 @@ fn render_element(
 
     match &context.mode {
         RenderMode::Transclusion {
-            transclusion_lookup,
-            site,
-            templates,
+            renderer,
+            used_ids,
+            footnotes,
         } => {
+            if tag.eq_ignore_ascii_case("wb-footnote") {
+                return render_footnote(node, used_ids, footnotes, context);
+            }
             if tag.eq_ignore_ascii_case("wb-transclusion") {
-                let target_raw = element.attr("target").ok_or_else(|| {
-                    eco_format!(
-                        "wb-transclusion missing target in {}",
-                        path_display(context)
-                    )
-                })?;
-                let target = crate::html::normalize_target(target_raw);
-                let body_html = transclusion_lookup.body_html(&target).ok_or_else(|| {
-                    eco_format!(
-                        "transclusion target {target} referenced by {} is not processed yet",
-                        path_display(context)
-                    )
-                })?;
-                let metadata = transclusion_lookup.metadata(&target).cloned().unwrap_or_default();
-                let show_metadata =
-                    crate::html::parse_bool_attr(element.attr("show-metadata"), true);
-                let expanded = crate::html::parse_bool_attr(element.attr("expanded"), true);
-                let disable_numbering =
-                    crate::html::parse_bool_attr(element.attr("disable-numbering"), false);
-                let demote_headings =
-                    crate::html::parse_non_negative_usize_attr(element.attr("demote-headings"), 1);
-                let content_html = prepare_transclusion_content(body_html)?;
-                let transclusion = TransclusionTemplateContext {
-                    target: target.as_str(),
-                    show_metadata,
-                    expanded,
-                    disable_numbering,
-                    demote_headings,
-                    metadata,
-                    content: content_html.as_str(),
-                };
-                return render_transclusion(templates, site, &transclusion);
+                return render_transclusion_element(element, renderer, used_ids, context);
+            }
+            if tag.eq_ignore_ascii_case("wb-excerpt") {
+                return render_children(node, context);
             }
         }
         RenderMode::Links {
             note_ids,
             processed_notes,
+            note_numbers,
             site,
+            permalinks,
             templates,
             citations,
             related,
@@ -836,6 +1614,24 @@ fn render_element(
             if tag.eq_ignore_ascii_case("wb-transclusion") {
                 return Err(eco_format!("unexpected wb-transclusion in link rendering"));
             }
+            if let Some(href) = element.attr("href")
+                && let Some(target) = href.strip_prefix("wb:")
+            {
+                let (target, fragment) = match target.find('#') {
+                    Some(index) => target.split_at(index),
+                    None => (target, ""),
+                };
+                if !note_ids.contains(target) {
+                    return Err(eco_format!(
+                        "link target {target} referenced by {} does not exist",
+                        path_display(context)
+                    ));
+                }
+                let mut element = element.clone();
+                let href = format!("{}{fragment}", permalinks.href(target, site));
+                crate::html::set_attr(&mut element, "href", &href);
+                return render_plain_element(node, &element, context);
+            }
             if tag.eq_ignore_ascii_case("wb-internal-link") || tag.eq_ignore_ascii_case("wb-cite") {
                 let target_raw = element.attr("target").ok_or_else(|| {
                     eco_format!("{} missing target in {}", tag, path_display(context))
@@ -865,10 +1661,33 @@ fn render_element(
                 {
                     content = escape_text(title);
                 }
+                let number = note_numbers.get(&target).map(String::as_str);
+                let label = number.map(|number| {
+                    numbered_label(
+                        number,
+                        processed_notes.get(&target).map(|note| &note.metadata),
+                    )
+                });
                 if tag.eq_ignore_ascii_case("wb-cite") {
-                    return render_citation(templates, site, &target, &content);
+                    return render_citation(
+                        templates,
+                        site,
+                        &permalinks.href(&target, site),
+                        &target,
+                        &content,
+                        number,
+                        label.as_deref(),
+                    );
                 }
-                return render_internal_link(templates, site, &target, &content);
+                return render_internal_link(
+                    templates,
+                    site,
+                    &permalinks.href(&target, site),
+                    &target,
+                    &content,
+                    number,
+                    label.as_deref(),
+                );
             }
         }
         RenderMode::Fragment => {
@@ -878,6 +1697,15 @@ fn render_element(
         }
     }
 
+    render_plain_element(node, element, context)
+}
+
+fn render_plain_element(
+    node: NodeRef<Node>,
+    element: &scraper::node::Element,
+    context: &RenderContext,
+) -> StrResult<String> {
+    let tag = element.name();
     let (attrs, is_void) = build_attributes(element);
 
     if tag.eq_ignore_ascii_case("script") || tag.eq_ignore_ascii_case("style") {
@@ -912,6 +1740,255 @@ fn render_element(
     Ok(out)
 }
 
+fn render_transclusion_element(
+    element: &scraper::node::Element,
+    renderer: &PageRenderer,
+    used_ids: &RefCell<HashSet<String>>,
+    context: &RenderContext,
+) -> StrResult<String> {
+    let target_raw = element.attr("target").ok_or_else(|| {
+        eco_format!(
+            "wb-transclusion missing target in {}",
+            path_display(context)
+        )
+    })?;
+    let target = crate::html::normalize_target(target_raw);
+    if !renderer.notes.contains_key(&target) {
+        return Err(eco_format!(
+            "transclusion target {target} referenced by {} does not exist",
+            path_display(context)
+        ));
+    }
+    let target_note = renderer
+        .processed_notes
+        .get(&target)
+        .ok_or_else(|| eco_format!("transclusion target {target} is not processed yet"))?;
+    if renderer
+        .chapters
+        .is_some_and(|chapters| chapters.contains(&target))
+    {
+        let title = target_note.title.as_deref().unwrap_or(target.as_str());
+        return Ok(format!(
+            "<p class=\"chapter-link\"><a href=\"{}\">{}</a></p>",
+            escape_attr(&renderer.permalinks.href(&target, renderer.site)),
+            escape_text(title)
+        ));
+    }
+    let metadata = target_note.metadata.clone();
+    let mode =
+        crate::html::parse_transclusion_mode_attr(element.attr("mode"), TransclusionMode::Full);
+    let show_metadata = crate::html::parse_bool_attr(element.attr("show-metadata"), true);
+    let expanded = crate::html::parse_bool_attr(element.attr("expanded"), true);
+    let disable_numbering = crate::html::parse_bool_attr(element.attr("disable-numbering"), false);
+    let demote_headings =
+        crate::html::parse_non_negative_usize_attr(element.attr("demote-headings"), 1);
+    let explicit_lazy = element
+        .attr("lazy")
+        .map(|value| crate::html::parse_bool_attr(Some(value), false));
+
+    let (content_html, lazy) = match mode {
+        TransclusionMode::Summary => (&target_note.summary_html, false),
+        TransclusionMode::Full => {
+            let lazy = is_lazy(
+                explicit_lazy,
+                expanded,
+                renderer.render.lazy_threshold,
+                target_note.size,
+            );
+            if lazy {
+                (&target_note.header_html, true)
+            } else {
+                (&target_note.body_html, false)
+            }
+        }
+    };
+
+    if lazy {
+        renderer.lazy_targets.borrow_mut().insert(target.clone());
+    }
+    let content_html = prepare_transclusion_content(content_html, used_ids)?;
+    let fragment_href = lazy.then(|| build_fragment_href(&target, renderer.site));
+
+    let transclusion = TransclusionTemplateContext {
+        target: target.as_str(),
+        mode,
+        show_metadata,
+        expanded,
+        disable_numbering,
+        demote_headings,
+        lazy,
+        fragment_href: fragment_href.as_deref(),
+        metadata,
+        content: content_html.as_str(),
+    };
+    render_transclusion(renderer.templates, renderer.site, &transclusion)
+}
+
+/// Whether a full transclusion is loaded from the fragment of its target:
+/// as its `lazy` attribute says, or else if it is collapsed and its target is
+/// larger than the threshold.
+fn is_lazy(explicit: Option<bool>, expanded: bool, threshold: Option<usize>, size: usize) -> bool {
+    explicit.unwrap_or_else(|| !expanded && threshold.is_some_and(|threshold| size > threshold))
+}
+
+/// Replaces a `<wb-footnote>` with a reference to the footnote, collecting its
+/// content to be listed after the rendered note. The markup mirrors the one of
+/// Typst's own footnotes; the numbers are filled in once the page is
+/// assembled, see [`number_footnotes`].
+fn render_footnote(
+    node: NodeRef<Node>,
+    used_ids: &RefCell<HashSet<String>>,
+    footnotes: &RefCell<Vec<Footnote>>,
+    context: &RenderContext,
+) -> StrResult<String> {
+    let content = render_children(node, context)?;
+    let index = footnotes.borrow().len() + 1;
+    let mut used_ids = used_ids.borrow_mut();
+    let id = claim_id(format!("fn-{index}"), &mut used_ids);
+    let ref_id = claim_id(format!("fnref-{index}"), &mut used_ids);
+    let out = format!("<a id=\"{ref_id}\" href=\"#{id}\" role=\"doc-noteref\"><sup></sup></a>");
+    footnotes.borrow_mut().push(Footnote {
+        id,
+        ref_id,
+        content,
+    });
+    Ok(out)
+}
+
+/// Appends the list of the footnotes of a note to its rendered body.
+fn append_footnotes(body_html: &mut String, footnotes: &[Footnote]) {
+    if footnotes.is_empty() {
+        return;
+    }
+    let items = footnotes
+        .iter()
+        .map(|footnote| {
+            format!(
+                "<li id=\"{}\"><a href=\"#{}\" role=\"doc-backlink\"><sup></sup></a>{}</li>",
+                footnote.id, footnote.ref_id, footnote.content
+            )
+        })
+        .collect::<String>();
+    body_html.push_str(&footnote_list(&items));
+}
+
+fn footnote_list(items_html: &str) -> String {
+    format!(
+        "<section role=\"doc-endnotes\"><ol style=\"list-style-type: none\">{items_html}</ol></section>"
+    )
+}
+
+/// Prefixes a section number with the taxon of the note, e.g. "Theorem 1.2".
+fn numbered_label(number: &str, metadata: Option<&HashMap<String, String>>) -> String {
+    match metadata.and_then(|metadata| metadata.get("taxon")) {
+        Some(taxon) if !taxon.trim().is_empty() => format!("{} {number}", taxon.trim()),
+        _ => number.to_string(),
+    }
+}
+
+/// Numbers the headings of a rendered page hierarchically (`1`, `1.2`, ...).
+///
+/// The `h1` of the page is its title and is not numbered, so counters start at
+/// `h2`; headings with the `disable-numbering` class are skipped. The title
+/// heading of a transcluded note is recognized by the link to the note it
+/// holds, so the number of the first transclusion of each note is returned
+/// along with the page. Lazy transclusions record that number in
+/// `data-wb-number`, since their fragments are numbered on their own.
+fn number_headings(html: &str) -> StrResult<(String, HashMap<String, String>)> {
+    let mut fragment = Html::parse_fragment(html);
+    let mut counters = [0usize; 5];
+    let mut numbers: HashMap<NodeId, String> = HashMap::new();
+    let mut note_numbers = HashMap::new();
+    let mut lazy = Vec::new();
+    for node in fragment.root_element().descendants() {
+        let Some(element) = node.value().as_element() else {
+            continue;
+        };
+        if element.name().eq_ignore_ascii_case("details")
+            && element.attr("data-wb-fragment").is_some()
+        {
+            lazy.push(node);
+        }
+        let Some(level) = heading_level(element.name()) else {
+            continue;
+        };
+        let disabled = element
+            .attr("class")
+            .is_some_and(|class| crate::html::has_class(class, "disable-numbering"));
+        if level < 2 || disabled {
+            continue;
+        }
+        let depth = usize::from(level) - 1;
+        counters[depth - 1] += 1;
+        counters[depth..].fill(0);
+        let number = counters[..depth]
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(".");
+        if let Some(note_id) = heading_note(node) {
+            note_numbers
+                .entry(note_id.to_string())
+                .or_insert_with(|| number.clone());
+        }
+        numbers.insert(node.id(), number);
+    }
+    let lazy: Vec<(NodeId, String)> = lazy
+        .into_iter()
+        .filter_map(|details| {
+            let number = details
+                .descendants()
+                .find_map(|node| numbers.get(&node.id()))?;
+            Some((details.id(), number.clone()))
+        })
+        .collect();
+
+    if numbers.is_empty() {
+        return Ok((html.to_string(), note_numbers));
+    }
+    for (node_id, number) in &numbers {
+        with_element_mut(&mut fragment, *node_id, |element| {
+            set_heading_number(element, number);
+        });
+    }
+    for (node_id, number) in &lazy {
+        with_element_mut(&mut fragment, *node_id, |element| {
+            crate::html::set_attr(element, "data-wb-number", number);
+        });
+    }
+    Ok((render_fragment(*fragment.root_element())?, note_numbers))
+}
+
+/// The note whose title a heading is, i.e. the target of the self-link of a
+/// note header (`<a class="slug" href="wb:...">`).
+fn heading_note<'a>(heading: NodeRef<'a, Node>) -> Option<&'a str> {
+    heading.descendants().find_map(|node| {
+        let element = node.value().as_element()?;
+        if !element.name().eq_ignore_ascii_case("a")
+            || !crate::html::has_class(element.attr("class")?, "slug")
+        {
+            return None;
+        }
+        element.attr("href")?.strip_prefix("wb:")
+    })
+}
+
+/// Records a computed section number on a heading. The number is exposed as
+/// `data-number` and as the `--num` custom property used by the stylesheet.
+fn set_heading_number(element: &mut scraper::node::Element, number: &str) {
+    crate::html::set_attr(element, "data-number", number);
+    let style = match element.attr("style") {
+        Some(style) if !style.trim().is_empty() => {
+            format!(
+                "{}; --num: '{number}'",
+                style.trim_end().trim_end_matches(';')
+            )
+        }
+        _ => format!("--num: '{number}'"),
+    };
+    crate::html::set_attr(element, "style", &style);
+}
+
 fn build_attributes(element: &scraper::node::Element) -> (String, bool) {
     let is_void = is_void_element(element.name());
 
@@ -1070,26 +2147,32 @@ fn is_heading_tag(tag: &str) -> bool {
     )
 }
 
-fn build_note_href(note_id: &str, site: &SiteSettings) -> String {
-    if note_id == "index" {
-        return site.root_dir.clone();
-    }
-    if site.trailing_slash {
-        format!("{}{note_id}/", site.root_dir)
-    } else {
-        format!("{}{note_id}.html", site.root_dir)
+/// Turns a root-absolute href into an absolute URL if the site has a domain.
+pub fn build_absolute_url(href: &str, site: &SiteSettings) -> String {
+    match site.domain.as_deref() {
+        Some(domain) if !domain.is_empty() => {
+            let domain = domain.trim_end_matches('/');
+            if domain.contains("://") {
+                format!("{domain}{href}")
+            } else {
+                format!("https://{domain}{href}")
+            }
+        }
+        _ => href.to_string(),
     }
 }
 
-fn output_path_for_note(output_dir: &Path, note_id: &str, site: &SiteSettings) -> PathBuf {
-    if note_id == "index" {
-        return output_dir.join("index.html");
-    }
-    if site.trailing_slash {
-        output_dir.join(note_id).join("index.html")
-    } else {
-        output_dir.join(format!("{note_id}.html"))
-    }
+/// The href of the social preview image of a note.
+pub fn build_og_image_href(note_id: &str, site: &SiteSettings) -> String {
+    format!("{}og/{note_id}.png", site.root_dir)
+}
+
+fn build_fragment_href(note_id: &str, site: &SiteSettings) -> String {
+    format!("{}fragments/{note_id}.html", site.root_dir)
+}
+
+fn output_path_for_fragment(output_dir: &Path, note_id: &str) -> PathBuf {
+    output_dir.join("fragments").join(format!("{note_id}.html"))
 }
 
 fn is_void_element(tag: &str) -> bool {
@@ -1112,13 +2195,13 @@ fn is_void_element(tag: &str) -> bool {
     )
 }
 
-fn escape_text(text: &str) -> String {
+pub fn escape_text(text: &str) -> String {
     text.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
 }
 
-fn escape_attr(value: &str) -> String {
+pub fn escape_attr(value: &str) -> String {
     value
         .replace('&', "&amp;")
         .replace('"', "&quot;")
@@ -1172,3 +2255,228 @@ fn copy_dir_all(src: &Path, dst: &Path) -> StrResult<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The values of an attribute in `html`, in document order.
+    fn attr_values(html: &str, name: &str) -> Vec<String> {
+        Html::parse_fragment(html)
+            .tree
+            .root()
+            .descendants()
+            .filter_map(|node| node.value().as_element()?.attr(name))
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn used(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    /// A note with one footnote per ID, rendered as by [`process_note`].
+    fn note_with_footnotes(ids: &[&str]) -> String {
+        let mut html = String::new();
+        let mut footnotes = Vec::new();
+        for id in ids {
+            html.push_str(&format!(
+                "<a id=\"ref-{id}\" href=\"#{id}\" role=\"doc-noteref\"><sup></sup></a>"
+            ));
+            footnotes.push(Footnote {
+                id: id.to_string(),
+                ref_id: format!("ref-{id}"),
+                content: id.to_string(),
+            });
+        }
+        append_footnotes(&mut html, &footnotes);
+        html
+    }
+
+    /// The numbers shown by the footnote references, backlinks and lists of
+    /// `html`, in document order.
+    fn footnote_numbers(html: &str) -> (Vec<String>, Vec<String>, usize) {
+        let fragment = Html::parse_fragment(html);
+        let numbers = |role: &str| {
+            fragment
+                .tree
+                .root()
+                .descendants()
+                .filter(|node| {
+                    node.value()
+                        .as_element()
+                        .is_some_and(|element| element.attr("role") == Some(role))
+                })
+                .map(|node| {
+                    ElementRef::wrap(node)
+                        .map(|element| element.text().collect())
+                        .unwrap_or_default()
+                })
+                .collect::<Vec<String>>()
+        };
+        let lists = numbers("doc-endnotes").len();
+        (numbers("doc-noteref"), numbers("doc-backlink"), lists)
+    }
+
+    /// The summary of a note with the given body.
+    fn summary(body: &str) -> String {
+        let note = Note {
+            id: "0001".into(),
+            path: PathBuf::from("typ/0001.typ"),
+            document: Html::parse_document(&format!("<html><body>{body}</body></html>")),
+            transcludes: Vec::new(),
+            links_out: Vec::new(),
+        };
+        let context = RenderContext {
+            mode: RenderMode::Fragment,
+            note_path: None,
+        };
+        render_note_summary(&note, &context).unwrap()
+    }
+
+    #[test]
+    fn test_summary_uses_the_excerpt() {
+        assert_eq!(
+            summary(
+                "<summary><p>Title</p></summary><p>First</p><wb-excerpt><em>Gist</em></wb-excerpt>"
+            ),
+            r#"<summary><p>Title</p></summary><div class="excerpt"><em>Gist</em></div>"#
+        );
+    }
+
+    #[test]
+    fn test_summary_falls_back_to_the_first_paragraph() {
+        assert_eq!(
+            summary("<summary><p>Title</p></summary><h2>Intro</h2><p>First</p><p>Second</p>"),
+            r#"<summary><p>Title</p></summary><div class="excerpt"><p>First</p></div>"#
+        );
+        assert_eq!(
+            summary("<summary><p>Title</p></summary><h2>Intro</h2>"),
+            "<summary><p>Title</p></summary>"
+        );
+        assert_eq!(summary(""), "");
+    }
+
+    #[test]
+    fn test_source_size_counts_transclusions_as_their_target() {
+        let document = Html::parse_document(
+            r#"<html><body><p>a</p><wb-transclusion target="wb:0002"></wb-transclusion><wb-transclusion target="0003"></wb-transclusion></body></html>"#,
+        );
+        let processed = |size| ProcessedNote {
+            head: PageHead::default(),
+            metadata: HashMap::new(),
+            title: None,
+            body_html: String::new(),
+            header_html: String::new(),
+            summary_html: String::new(),
+            size,
+        };
+        let processed_notes = HashMap::from([("0002".to_string(), processed(10))]);
+        let body = *document
+            .select(&Selector::parse("body").unwrap())
+            .next()
+            .unwrap();
+        // The body, the paragraph, the known target and the unknown one.
+        assert_eq!(source_size(body, &processed_notes), 1 + 1 + 10 + 1);
+    }
+
+    #[test]
+    fn test_is_lazy_above_the_threshold() {
+        assert!(!is_lazy(None, false, Some(10), 10));
+        assert!(is_lazy(None, false, Some(10), 11));
+        assert!(!is_lazy(None, true, Some(10), 11));
+        assert!(!is_lazy(None, false, None, 1000));
+        assert!(is_lazy(Some(true), true, None, 1));
+        assert!(!is_lazy(Some(false), false, Some(10), 11));
+    }
+
+    #[test]
+    fn test_number_footnotes_on_page() {
+        let html = format!(
+            "{}{}",
+            note_with_footnotes(&["a", "b"]),
+            note_with_footnotes(&["c"])
+        );
+        let out = number_footnotes(&html, FootnotePlacement::Page).unwrap();
+        assert_eq!(
+            footnote_numbers(&out),
+            (
+                vec!["1".into(), "2".into(), "3".into()],
+                vec!["1".into(), "2".into(), "3".into()],
+                1
+            )
+        );
+        assert_eq!(
+            attr_values(&out, "id").last().map(String::as_str),
+            Some("c")
+        );
+    }
+
+    #[test]
+    fn test_number_footnotes_by_section() {
+        let html = format!(
+            "{}{}",
+            note_with_footnotes(&["a", "b"]),
+            note_with_footnotes(&["c"])
+        );
+        let out = number_footnotes(&html, FootnotePlacement::Section).unwrap();
+        assert_eq!(
+            footnote_numbers(&out),
+            (
+                vec!["1".into(), "2".into(), "1".into()],
+                vec!["1".into(), "2".into(), "1".into()],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn test_number_footnotes_in_order_of_first_reference() {
+        let mut html = note_with_footnotes(&["a", "b"]);
+        // A second reference to `b`, placed before the others.
+        html.insert_str(0, r##"<a href="#b" role="doc-noteref"><sup></sup></a>"##);
+        let out = number_footnotes(&html, FootnotePlacement::Page).unwrap();
+        let (refs, backlinks, _) = footnote_numbers(&out);
+        assert_eq!(refs, ["1", "2", "1"]);
+        assert_eq!(backlinks, ["1", "2"]);
+        assert_eq!(attr_values(&out, "id")[2..], ["b", "a"]);
+    }
+
+    #[test]
+    fn test_deduplicate_ids_keeps_free_ids() {
+        let html = r##"<h2 id="intro">Intro</h2><a href="#intro">intro</a>"##;
+        let mut used_ids = used(&["other"]);
+        assert_eq!(deduplicate_ids(html, &mut used_ids).unwrap(), html);
+        assert_eq!(used_ids, used(&["other", "intro"]));
+    }
+
+    #[test]
+    fn test_deduplicate_ids_renames_taken_ids_and_their_links() {
+        let html =
+            r##"<h2 id="intro">Intro</h2><a href="#intro">intro</a><a href="#outside">x</a>"##;
+        let mut used_ids = used(&["intro", "intro-2"]);
+        let out = deduplicate_ids(html, &mut used_ids).unwrap();
+        assert_eq!(attr_values(&out, "id"), ["intro-3"]);
+        assert_eq!(attr_values(&out, "href"), ["#intro-3", "#outside"]);
+        assert!(used_ids.contains("intro-3"));
+    }
+
+    #[test]
+    fn test_deduplicate_ids_resolves_links_to_the_closest_copy() {
+        let copy = r##"<section><h2 id="b">B</h2><a href="#b">b</a></section>"##;
+        let html = format!("{copy}{copy}");
+        let mut used_ids = used(&["b"]);
+        let out = deduplicate_ids(&html, &mut used_ids).unwrap();
+        assert_eq!(attr_values(&out, "id"), ["b-2", "b-3"]);
+        assert_eq!(attr_values(&out, "href"), ["#b-2", "#b-3"]);
+    }
+
+    #[test]
+    fn test_deduplicate_ids_leaves_links_to_kept_copies() {
+        let html = r##"<section><h2 id="b">B</h2><a href="#b">b</a></section><section><h2 id="b">B</h2><a href="#b">b</a></section>"##;
+        let mut used_ids = HashSet::new();
+        let out = deduplicate_ids(html, &mut used_ids).unwrap();
+        assert_eq!(attr_values(&out, "id"), ["b", "b-2"]);
+        assert_eq!(attr_values(&out, "href"), ["#b", "#b-2"]);
+    }
+}
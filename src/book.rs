@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use ecow::eco_format;
+
+use crate::args::PdfStandard;
+use crate::compiler::{
+    CompileArtifact, CompileInput, CompileOutput, CompileRequest, CompileTarget, TypstCompiler,
+};
+use crate::config::BuildConfig;
+use crate::error::StrResult;
+use crate::generated::GeneratedData;
+use crate::html::HtmlNote;
+
+/// A note exported together with every note it transcludes, directly or not.
+#[derive(Debug, Clone)]
+pub struct Book {
+    pub root_id: String,
+    /// The notes of the book in the order they first appear, starting with
+    /// the root. It is the order of the chapters of an EPUB; a PDF needs no
+    /// order of its own, as Typst expands the transclusions of the root in
+    /// place.
+    pub ids: Vec<String>,
+    /// For every note, the notes that first appear in its transclusions.
+    pub children: HashMap<String, Vec<String>>,
+}
+
+impl Book {
+    pub fn collect(notes: &[HtmlNote], root_id: &str) -> StrResult<Self> {
+        let mut transcludes = HashMap::new();
+        for note in notes {
+            let targets =
+                crate::html::collect_targets(&note.document, "wb-transclusion", &note.source_path)?;
+            transcludes.insert(note.id.as_str(), targets);
+        }
+        if !transcludes.contains_key(root_id) {
+            return Err(eco_format!("note {root_id} does not exist"));
+        }
+
+        let mut book = Self {
+            root_id: root_id.to_string(),
+            ids: Vec::new(),
+            children: HashMap::new(),
+        };
+        let mut seen = HashSet::new();
+        let mut stack = Vec::new();
+        visit(root_id, &transcludes, &mut book, &mut seen, &mut stack)?;
+        Ok(book)
+    }
+}
+
+fn visit(
+    id: &str,
+    transcludes: &HashMap<&str, Vec<String>>,
+    book: &mut Book,
+    seen: &mut HashSet<String>,
+    stack: &mut Vec<String>,
+) -> StrResult<()> {
+    if stack.iter().any(|ancestor| ancestor == id) {
+        stack.push(id.to_string());
+        return Err(eco_format!(
+            "transclusion cycle detected: {}",
+            stack.join(" -> ")
+        ));
+    }
+    let targets = transcludes.get(id).ok_or_else(|| {
+        eco_format!(
+            "transclusion target {id} referenced by {} does not exist",
+            stack.last().map(String::as_str).unwrap_or_default()
+        )
+    })?;
+    // The transclusions of a note are the same wherever it appears.
+    if !seen.insert(id.to_string()) {
+        return Ok(());
+    }
+    book.ids.push(id.to_string());
+    if let Some(parent) = stack.last() {
+        book.children
+            .entry(parent.clone())
+            .or_default()
+            .push(id.to_string());
+    }
+
+    stack.push(id.to_string());
+    for target in targets {
+        visit(target, transcludes, book, seen, stack)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// Compiles a book into a single PDF.
+///
+/// The book is compiled from a generated driver document that only includes
+/// the root note in paged mode: the other notes appear where the root, or a
+/// note it transcludes, transcludes them, so the PDF follows the transclusion
+/// tree without the driver listing them. The IDs of the notes in the book are
+/// passed as the `wb-book-ids` input, so that links between them become links
+/// within the PDF, while links to other notes point to the site.
+pub fn compile_book(
+    build_config: &BuildConfig,
+    compiler: &dyn TypstCompiler,
+    book: &Book,
+    root_filename: &str,
+    output_path: &Path,
+    data: &GeneratedData,
+    pdf_standards: &[PdfStandard],
+) -> StrResult<()> {
+    let driver = driver_source(root_filename);
+    let book_ids = book.ids.join(",");
+    let inputs = [("wb-book-ids", book_ids.as_str())];
+
+    let request = CompileRequest {
+        source: CompileInput::Stdin(&driver),
+        target: CompileTarget::Pdf,
+        output: CompileOutput::File(output_path),
+        additional_inputs: &inputs,
+        pdf_standards,
+        data: Some(data),
+    };
+    match compiler
+        .compile(build_config, &request)
+        .map_err(|err| eco_format!("failed to export book {}: {err}", book.root_id))?
+    {
+        CompileArtifact::FileWritten => Ok(()),
+        CompileArtifact::Stdout(_) => Err(eco_format!(
+            "typst compiler returned stdout for book compilation of {}",
+            book.root_id
+        )),
+    }
+}
+
+/// The driver document of a book, which includes its root note.
+fn driver_source(root_filename: &str) -> String {
+    let path = root_filename.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("#include \"{path}\"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use scraper::Html;
+
+    use super::*;
+
+    /// Notes given by ID and the targets they transclude, in order.
+    fn notes(transclusions: &[(&str, &[&str])]) -> Vec<HtmlNote> {
+        transclusions
+            .iter()
+            .map(|(id, targets)| {
+                let body: String = targets
+                    .iter()
+                    .map(|target| {
+                        format!("<wb-transclusion target=\"wb:{target}\"></wb-transclusion>")
+                    })
+                    .collect();
+                HtmlNote {
+                    id: id.to_string(),
+                    source_path: PathBuf::from(format!("typ/{id}.typ")),
+                    document: Html::parse_document(&format!("<html><body>{body}</body></html>")),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_collect_orders_notes_by_first_appearance() {
+        let notes = notes(&[
+            ("root", &["b", "a", "b"]),
+            ("a", &["c", "b"]),
+            ("b", &["d"]),
+            ("c", &[]),
+            ("d", &[]),
+            ("other", &["root"]),
+        ]);
+        let book = Book::collect(&notes, "root").unwrap();
+        assert_eq!(book.ids, ["root", "b", "d", "a", "c"]);
+        assert_eq!(book.children["root"], ["b", "a"]);
+        assert_eq!(book.children["a"], ["c"]);
+        assert_eq!(book.children["b"], ["d"]);
+        assert!(!book.children.contains_key("c"));
+    }
+
+    #[test]
+    fn test_collect_rejects_cycles_and_missing_notes() {
+        let cyclic = notes(&[("a", &["b"]), ("b", &["a"])]);
+        let err = Book::collect(&cyclic, "a").unwrap_err();
+        assert!(err.contains("a -> b -> a"), "{err}");
+
+        let missing = notes(&[("a", &["x"])]);
+        let err = Book::collect(&missing, "a").unwrap_err();
+        assert!(err.contains("target x referenced by a"), "{err}");
+        assert!(Book::collect(&missing, "x").is_err());
+    }
+
+    #[test]
+    fn test_driver_source_escapes_the_path() {
+        assert_eq!(
+            driver_source("/dir/say \"hi\".typ"),
+            "#include \"/dir/say \\\"hi\\\".typ\"\n"
+        );
+    }
+}
@@ -2,16 +2,26 @@ use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Component, Path, PathBuf};
 
+use clap::ValueEnum;
 use ecow::eco_format;
 
 use crate::error::StrResult;
 use crate::html::HtmlNote;
 
-use crate::args::CompileCommand;
+use crate::args::{
+    CompileCommand, ExportBookArgs, ExportCommand, ExportEpubArgs, ExportImageArgs, ExportTarget,
+    ImageFormat, OutputFormat, PdfStandard,
+};
+use crate::book::{self, Book};
 use crate::compiler::{
-    CliTypstCompiler, CompileArtifact, CompileOutput, CompileRequest, CompileTarget, TypstCompiler,
+    CliTypstCompiler, CompileArtifact, CompileInput, CompileOutput, CompileRequest, CompileTarget,
+    TypstCompiler,
 };
 use crate::config::{BuildConfig, WeibianConfig};
+use crate::epub::{self, EpubOptions};
+use crate::generated::{DataFiles, GeneratedData};
+use crate::index::build_note_index;
+use crate::permalink::{Aliases, Permalinks};
 use crate::{backend, frontend};
 // use crate::args::Output;
 // use crate::args::Input;
@@ -19,26 +29,64 @@ use crate::{backend, frontend};
 // type CodespanResult<T> = Result<T, CodespanError>;
 // type CodespanError = codespan_reporting::files::Error;
 
+/// How many times notes with `wb-two-pass` are compiled at most, including
+/// the first pass.
+const MAX_PASSES: usize = 4;
+
 struct PdfExportNote {
     id: String,
     source_path: PathBuf,
     export_pdf: bool,
+    /// The standards set by the note itself, if any.
+    pdf_standards: Option<Vec<PdfStandard>>,
+    /// Set for notes exported together with their transclusions
+    /// (`pdf-mode="book"`).
+    book: Option<Book>,
 }
 
-const ID_FILENAME_MAP_FILENAME: &str = "id-filename.json";
-const ID_FILENAME_MAP_ROOT_PATH: &str = "/id-filename.json";
-
 pub fn compile(command: &CompileCommand, config: &WeibianConfig) -> StrResult<()> {
     let build_config = BuildConfig::from(&command.args, config)?;
     let compiler = CliTypstCompiler;
 
-    let html_notes = frontend::compile_html(&build_config, &compiler)?;
+    // The HTML pass is needed for PDF-only builds too: it yields the note list
+    // and the `export-pdf` metadata.
+    let (html_notes, aliases) = compile_notes(&build_config, &compiler)?;
+    if build_config.format.includes_html() {
+        check_home(&build_config, &html_notes)?;
+    }
     let pdf_export_notes = collect_pdf_export_notes(&html_notes)?;
-    let id_filename_map = build_id_filename_map(&build_config, &html_notes)?;
-    let id_filename_map_json = serialize_id_filename_map(&id_filename_map)?;
-    backend::process_html(&build_config, html_notes)?;
-    write_id_filename_map(&build_config, id_filename_map_json.as_str())?;
-    export_pdf_notes(&build_config, &compiler, &pdf_export_notes)?;
+    let og_cards = match &build_config.og_card {
+        Some(_) if build_config.format.includes_html() => collect_og_cards(&html_notes)?,
+        _ => Vec::new(),
+    };
+    let id_filename_map = build_id_filename_map(&build_config, &html_notes, &aliases)?;
+    let permalinks = Permalinks::collect(&html_notes, &build_config.site, &aliases)?;
+    // The generated data is only needed, and written, for PDF exports.
+    let data = if exports_pdf(build_config.format, &pdf_export_notes) {
+        Some(generate_data(
+            &build_config,
+            &html_notes,
+            &id_filename_map,
+            &permalinks,
+        )?)
+    } else {
+        None
+    };
+    if build_config.format.includes_html() {
+        backend::process_html(&build_config, html_notes, &permalinks)?;
+    }
+    if let Some(card) = &build_config.og_card {
+        export_og_cards(&build_config, &compiler, card, &og_cards)?;
+    }
+    if let Some(data) = &data {
+        export_pdf_notes(
+            &build_config,
+            &compiler,
+            &pdf_export_notes,
+            &id_filename_map,
+            data,
+        )?;
+    }
 
     // let mut world = SystemWorld::new(
     //     &command.args.input,
@@ -66,6 +114,367 @@ pub fn compile(command: &CompileCommand, config: &WeibianConfig) -> StrResult<()
     Ok(())
 }
 
+/// Checks that the home note set by the configuration is one of the notes,
+/// as the root of the site is its page.
+fn check_home(build_config: &BuildConfig, html_notes: &[HtmlNote]) -> StrResult<()> {
+    let home = &build_config.site.home;
+    if !build_config.home_configured || html_notes.iter().any(|note| &note.id == home) {
+        return Ok(());
+    }
+    Err(eco_format!(
+        "home note {home} does not exist in the input directory {}",
+        build_config.input_directory.display()
+    ))
+}
+
+/// Compiles the notes to HTML, and replaces the aliases they refer to other
+/// notes by with the IDs of those notes.
+///
+/// Notes with a `wb-two-pass` meta tag are then compiled again with the data
+/// generated from the first pass, until the data they yield stops changing.
+fn compile_notes(
+    build_config: &BuildConfig,
+    compiler: &dyn TypstCompiler,
+) -> StrResult<(Vec<HtmlNote>, Aliases)> {
+    let mut html_notes = frontend::compile_html(build_config, compiler)?;
+    let aliases = Aliases::collect(&html_notes, &build_config.aliases)?;
+    let replaced = aliases.resolve_targets(&mut html_notes);
+
+    let two_pass = collect_two_pass_notes(&html_notes)?;
+    if !two_pass.is_empty() {
+        let mut files = graph_data_files(build_config, &html_notes, &aliases)?;
+        let mut pass = 1;
+        loop {
+            if pass == MAX_PASSES {
+                let ids: Vec<&str> = two_pass
+                    .iter()
+                    .map(|&index| html_notes[index].id.as_str())
+                    .collect();
+                return Err(eco_format!(
+                    "the note graph did not converge after {MAX_PASSES} passes: the two-pass notes {} keep changing the data they are compiled with",
+                    ids.join(", ")
+                ));
+            }
+            pass += 1;
+
+            let data = GeneratedData::create(build_config, &files)?;
+            let sources: Vec<&Path> = two_pass
+                .iter()
+                .map(|&index| html_notes[index].source_path.as_path())
+                .collect();
+            let recompiled = frontend::recompile_html(build_config, compiler, &sources, &data)?;
+            for (&index, note) in two_pass.iter().zip(recompiled) {
+                if note.id != html_notes[index].id {
+                    return Err(eco_format!(
+                        "note {} changed its ID to {} in pass {pass}",
+                        html_notes[index].id,
+                        note.id
+                    ));
+                }
+                html_notes[index] = note;
+            }
+            // Aliases used by the recompiled notes were reported in the
+            // first pass.
+            aliases.resolve_targets(&mut html_notes);
+
+            let next = graph_data_files(build_config, &html_notes, &aliases)?;
+            if next == files {
+                break;
+            }
+            files = next;
+        }
+    }
+
+    if build_config.render.alias_warnings {
+        for message in replaced {
+            crate::print_warning(&message);
+        }
+    }
+    Ok((html_notes, aliases))
+}
+
+/// The data generated from the notes of a compile pass.
+fn graph_data_files(
+    build_config: &BuildConfig,
+    html_notes: &[HtmlNote],
+    aliases: &Aliases,
+) -> StrResult<DataFiles> {
+    let id_filename_map = build_id_filename_map(build_config, html_notes, aliases)?;
+    let permalinks = Permalinks::collect(html_notes, &build_config.site, aliases)?;
+    data_files(build_config, html_notes, &id_filename_map, &permalinks)
+}
+
+/// The indices of the notes that are compiled again with the generated data.
+fn collect_two_pass_notes(html_notes: &[HtmlNote]) -> StrResult<Vec<usize>> {
+    let mut indices = Vec::new();
+    for (index, note) in html_notes.iter().enumerate() {
+        let metadata = crate::html::extract_metadata(&note.document)?;
+        if metadata
+            .get("wb-two-pass")
+            .is_some_and(|value| value != "false")
+        {
+            indices.push(index);
+        }
+    }
+    Ok(indices)
+}
+
+pub fn export(command: &ExportCommand, config: &WeibianConfig) -> StrResult<()> {
+    match &command.target {
+        ExportTarget::Book(args) => export_book(args, config),
+        ExportTarget::Epub(args) => export_epub(args, config),
+        ExportTarget::Image(args) => export_image(args, config),
+    }
+}
+
+fn export_book(args: &ExportBookArgs, config: &WeibianConfig) -> StrResult<()> {
+    let build_config = BuildConfig::from(&args.args, config)?;
+    let compiler = CliTypstCompiler;
+
+    let (html_notes, aliases) = compile_notes(&build_config, &compiler)?;
+    let book = Book::collect(&html_notes, &args.id)?;
+    let pdf_standards = collect_pdf_export_notes(&html_notes)?
+        .into_iter()
+        .find(|note| note.id == args.id)
+        .and_then(|note| note.pdf_standards)
+        .unwrap_or_else(|| build_config.pdf_standards.clone());
+    let id_filename_map = build_id_filename_map(&build_config, &html_notes, &aliases)?;
+    let data = generate_data(
+        &build_config,
+        &html_notes,
+        &id_filename_map,
+        &Permalinks::collect(&html_notes, &build_config.site, &aliases)?,
+    )?;
+
+    let output_path = export_output_path(
+        args.output_file.as_deref(),
+        &build_config,
+        "pdf",
+        &args.id,
+        ".pdf",
+    )?;
+
+    book::compile_book(
+        &build_config,
+        &compiler,
+        &book,
+        &id_filename_map[&args.id],
+        &output_path,
+        &data,
+        &pdf_standards,
+    )
+}
+
+fn export_epub(args: &ExportEpubArgs, config: &WeibianConfig) -> StrResult<()> {
+    let build_config = BuildConfig::from(&args.args, config)?;
+    let compiler = CliTypstCompiler;
+
+    let (html_notes, aliases) = compile_notes(&build_config, &compiler)?;
+    let book = Book::collect(&html_notes, &args.id)?;
+    let permalinks = Permalinks::collect(&html_notes, &build_config.site, &aliases)?;
+    let chapters = backend::render_chapters(&build_config, html_notes, &book.ids, &permalinks)?;
+
+    let output_path = export_output_path(
+        args.output_file.as_deref(),
+        &build_config,
+        "epub",
+        &args.id,
+        ".epub",
+    )?;
+
+    let options = EpubOptions {
+        site: &build_config.site,
+        permalinks: &permalinks,
+        public_dir: &build_config.public_directory,
+        modified: args
+            .args
+            .world
+            .creation_timestamp
+            .unwrap_or_else(chrono::Utc::now),
+    };
+    epub::write_epub(&output_path, &book, &chapters, &options)
+}
+
+fn export_image(args: &ExportImageArgs, config: &WeibianConfig) -> StrResult<()> {
+    let build_config = BuildConfig::from(&args.args, config)?;
+    let compiler = CliTypstCompiler;
+
+    let (html_notes, aliases) = compile_notes(&build_config, &compiler)?;
+    let note = html_notes
+        .iter()
+        .find(|note| note.id == args.id)
+        .ok_or_else(|| eco_format!("note {} does not exist", args.id))?;
+    let id_filename_map = build_id_filename_map(&build_config, &html_notes, &aliases)?;
+    let data = generate_data(
+        &build_config,
+        &html_notes,
+        &id_filename_map,
+        &Permalinks::collect(&html_notes, &build_config.site, &aliases)?,
+    )?;
+
+    let extension = args.image_format.extension();
+    let output_path = export_output_path(
+        args.output_file.as_deref(),
+        &build_config,
+        extension,
+        &args.id,
+        &format!("-{{p}}.{extension}"),
+    )?;
+
+    let request = CompileRequest {
+        source: CompileInput::File(note.source_path.as_path()),
+        target: match args.image_format {
+            ImageFormat::Svg => CompileTarget::Svg,
+            ImageFormat::Png => CompileTarget::Png,
+        },
+        output: CompileOutput::File(output_path.as_path()),
+        additional_inputs: &[],
+        pdf_standards: &[],
+        data: Some(&data),
+    };
+    match compiler
+        .compile(&build_config, &request)
+        .map_err(|err| eco_format!("failed to export note {} to {extension}: {err}", args.id))?
+    {
+        CompileArtifact::FileWritten => Ok(()),
+        CompileArtifact::Stdout(_) => Err(eco_format!(
+            "typst compiler returned stdout for {extension} compilation of {}",
+            note.source_path.display()
+        )),
+    }
+}
+
+/// The path an export is written to: `output_file` if given, or
+/// `<KIND>/<ID><SUFFIX>` in the output directory otherwise. Its parent
+/// directory is created.
+fn export_output_path(
+    output_file: Option<&Path>,
+    build_config: &BuildConfig,
+    kind: &str,
+    id: &str,
+    suffix: &str,
+) -> StrResult<PathBuf> {
+    match output_file {
+        Some(path) => {
+            create_parent_dir(path, kind)?;
+            Ok(path.to_path_buf())
+        }
+        None => note_output_path(&build_config.output_directory.join(kind), id, suffix, kind),
+    }
+}
+
+/// The path of the output of a note in a directory, `<ID><SUFFIX>`, with its
+/// parent directory created, as namespaced IDs are written to
+/// subdirectories.
+fn note_output_path(dir: &Path, id: &str, suffix: &str, kind: &str) -> StrResult<PathBuf> {
+    let path = dir.join(format!("{id}{suffix}"));
+    create_parent_dir(&path, kind)?;
+    Ok(path)
+}
+
+fn create_parent_dir(path: &Path, kind: &str) -> StrResult<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent).map_err(|err| {
+            eco_format!(
+                "failed to create {kind} output directory {}: {err}",
+                parent.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// What the social preview image of a note shows.
+struct OgCard {
+    id: String,
+    title: String,
+    taxon: String,
+    authors: String,
+}
+
+fn collect_og_cards(html_notes: &[HtmlNote]) -> StrResult<Vec<OgCard>> {
+    let mut cards = Vec::with_capacity(html_notes.len());
+    for note in html_notes {
+        let metadata = crate::html::extract_metadata(&note.document).map_err(|err| {
+            eco_format!(
+                "failed to extract metadata for {}: {err}",
+                note.source_path.display()
+            )
+        })?;
+        let title = crate::html::extract_note_title(&note.document, &metadata)?;
+        cards.push(OgCard {
+            id: note.id.clone(),
+            title: title.unwrap_or_else(|| note.id.clone()),
+            taxon: metadata.get("taxon").cloned().unwrap_or_default(),
+            authors: metadata.get("author").cloned().unwrap_or_default(),
+        });
+    }
+    Ok(cards)
+}
+
+/// Renders the social preview image of every note into `og/<id>.png` in the
+/// output directory, by compiling the card template once per note with the
+/// note's title, taxon and authors as inputs.
+fn export_og_cards(
+    build_config: &BuildConfig,
+    compiler: &dyn TypstCompiler,
+    card: &Path,
+    cards: &[OgCard],
+) -> StrResult<()> {
+    if cards.is_empty() {
+        return Ok(());
+    }
+
+    let card_path = build_config.input_directory.join(card);
+    if !card_path.is_file() {
+        return Err(eco_format!(
+            "social preview template {} does not exist",
+            card_path.display()
+        ));
+    }
+    let og_output_dir = build_config.output_directory.join("og");
+
+    let mut failures = Vec::new();
+    for card in cards {
+        let output_path = note_output_path(&og_output_dir, &card.id, ".png", "social preview")?;
+        let inputs = [
+            ("wb-og-id", card.id.as_str()),
+            ("wb-og-title", card.title.as_str()),
+            ("wb-og-taxon", card.taxon.as_str()),
+            ("wb-og-authors", card.authors.as_str()),
+        ];
+        let request = CompileRequest {
+            source: CompileInput::File(card_path.as_path()),
+            target: CompileTarget::Png,
+            output: CompileOutput::File(output_path.as_path()),
+            additional_inputs: &inputs,
+            pdf_standards: &[],
+            data: None,
+        };
+        match compiler.compile(build_config, &request) {
+            Ok(CompileArtifact::FileWritten) => {}
+            Ok(CompileArtifact::Stdout(_)) => {
+                return Err(eco_format!(
+                    "typst compiler returned stdout for social preview of {}",
+                    card.id
+                ));
+            }
+            Err(err) => failures.push(eco_format!(
+                "failed to render social preview of note {}: {err}",
+                card.id
+            )),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("\n").into())
+    }
+}
+
 fn collect_pdf_export_notes(html_notes: &[HtmlNote]) -> StrResult<Vec<PdfExportNote>> {
     let mut pdf_export_notes = Vec::with_capacity(html_notes.len());
 
@@ -76,71 +485,136 @@ fn collect_pdf_export_notes(html_notes: &[HtmlNote]) -> StrResult<Vec<PdfExportN
                 note.source_path.display()
             )
         })?;
+        let pdf_standards = metadata
+            .get("pdf-standard")
+            .map(|value| parse_pdf_standards(value))
+            .transpose()
+            .map_err(|err| eco_format!("invalid pdf-standard in {}: {err}", note.id))?;
+        let export_pdf = should_export_pdf(&metadata);
+        let book = match metadata.get("pdf-mode").map(String::as_str) {
+            None | Some("note") => None,
+            Some("book") if export_pdf => Some(Book::collect(html_notes, &note.id)?),
+            Some("book") => None,
+            Some(other) => {
+                return Err(eco_format!(
+                    "invalid pdf-mode in {}: expected \"note\" or \"book\", found \"{other}\"",
+                    note.id
+                ));
+            }
+        };
         pdf_export_notes.push(PdfExportNote {
             id: note.id.clone(),
             source_path: note.source_path.clone(),
-            export_pdf: should_export_pdf(&metadata),
+            export_pdf,
+            pdf_standards,
+            book,
         });
     }
 
     Ok(pdf_export_notes)
 }
 
+/// Whether a build in a format exports any of the notes as PDF.
+fn exports_pdf(format: OutputFormat, notes: &[PdfExportNote]) -> bool {
+    format.includes_pdf() && notes.iter().any(|note| note.export_pdf)
+}
+
 fn should_export_pdf(metadata: &HashMap<String, String>) -> bool {
     !metadata
         .get("export-pdf")
         .is_some_and(|value| value.eq_ignore_ascii_case("false"))
 }
 
+fn parse_pdf_standards(value: &str) -> StrResult<Vec<PdfStandard>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|standard| !standard.is_empty())
+        .map(|standard| {
+            PdfStandard::from_str(standard, false).map_err(|_| {
+                let expected = PdfStandard::value_variants()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                eco_format!("unknown PDF standard \"{standard}\" (expected one of {expected})")
+            })
+        })
+        .collect()
+}
+
 fn export_pdf_notes(
     build_config: &BuildConfig,
     compiler: &dyn TypstCompiler,
     notes: &[PdfExportNote],
+    id_filename_map: &BTreeMap<String, String>,
+    data: &GeneratedData,
 ) -> StrResult<()> {
-    if !notes.iter().any(|note| note.export_pdf) {
-        return Ok(());
-    }
-
     let pdf_output_dir = build_config.output_directory.join("pdf");
-    fs::create_dir_all(&pdf_output_dir).map_err(|err| {
-        eco_format!(
-            "failed to create pdf output directory {}: {err}",
-            pdf_output_dir.display()
-        )
-    })?;
-
-    let additional_inputs = [("wb-id-filename-map-file", ID_FILENAME_MAP_ROOT_PATH)];
 
+    // A note failing to export (e.g. because it does not conform to a PDF
+    // standard) does not stop the others, so that all failures are reported.
+    let mut failures = Vec::new();
     for note in notes {
         if !note.export_pdf {
             continue;
         }
 
-        let output_path = pdf_output_dir.join(format!("{}.pdf", note.id));
+        let output_path = note_output_path(&pdf_output_dir, &note.id, ".pdf", "pdf")?;
+        let pdf_standards = note
+            .pdf_standards
+            .as_deref()
+            .unwrap_or(&build_config.pdf_standards);
+        if let Some(book) = &note.book {
+            if let Err(err) = book::compile_book(
+                build_config,
+                compiler,
+                book,
+                &id_filename_map[&note.id],
+                &output_path,
+                data,
+                pdf_standards,
+            ) {
+                failures.push(err);
+            }
+            continue;
+        }
+
         let request = CompileRequest {
-            source: note.source_path.as_path(),
+            source: CompileInput::File(note.source_path.as_path()),
             target: CompileTarget::Pdf,
             output: CompileOutput::File(output_path.as_path()),
-            additional_inputs: &additional_inputs,
+            additional_inputs: &[],
+            pdf_standards,
+            data: Some(data),
         };
 
-        match compiler.compile(build_config, &request)? {
-            CompileArtifact::FileWritten => {}
-            CompileArtifact::Stdout(_) => {
+        match compiler.compile(build_config, &request) {
+            Ok(CompileArtifact::FileWritten) => {}
+            Ok(CompileArtifact::Stdout(_)) => {
                 return Err(eco_format!(
                     "typst compiler returned stdout for pdf compilation of {}",
                     note.source_path.display()
                 ));
             }
+            Err(err) => failures.push(eco_format!(
+                "failed to export note {} to PDF: {err}",
+                note.id
+            )),
         }
     }
 
-    Ok(())
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("\n").into())
+    }
 }
 
 fn build_id_filename_map(
     build_config: &BuildConfig,
     html_notes: &[HtmlNote],
+    aliases: &Aliases,
 ) -> StrResult<BTreeMap<String, String>> {
     let mut id_filename_map = BTreeMap::new();
 
@@ -159,6 +633,11 @@ fn build_id_filename_map(
         let rooted_filename = to_root_absolute_filename(relative_path)?;
         id_filename_map.insert(note.id.clone(), rooted_filename);
     }
+    for (alias, target) in aliases.iter() {
+        if let Some(filename) = id_filename_map.get(target).cloned() {
+            id_filename_map.insert(alias.to_string(), filename);
+        }
+    }
 
     Ok(id_filename_map)
 }
@@ -194,14 +673,31 @@ fn to_root_absolute_filename(relative_path: &Path) -> StrResult<String> {
     Ok(rooted_filename)
 }
 
-fn write_id_filename_map(build_config: &BuildConfig, id_filename_map_json: &str) -> StrResult<()> {
-    let output_path = build_config.input_directory.join(ID_FILENAME_MAP_FILENAME);
+/// Writes the data the paged exports read: the source file and the href of
+/// every note, for links within a book and to the site, and the note index.
+fn generate_data(
+    build_config: &BuildConfig,
+    html_notes: &[HtmlNote],
+    id_filename_map: &BTreeMap<String, String>,
+    permalinks: &Permalinks,
+) -> StrResult<GeneratedData> {
+    let files = data_files(build_config, html_notes, id_filename_map, permalinks)?;
+    GeneratedData::create(build_config, &files)
+}
 
-    fs::write(&output_path, id_filename_map_json).map_err(|err| {
-        eco_format!(
-            "failed to write id-filename map file {}: {err}",
-            output_path.display()
-        )
+fn data_files(
+    build_config: &BuildConfig,
+    html_notes: &[HtmlNote],
+    id_filename_map: &BTreeMap<String, String>,
+    permalinks: &Permalinks,
+) -> StrResult<DataFiles> {
+    let notes = build_note_index(html_notes, &build_config.site, permalinks, id_filename_map)?;
+    Ok(DataFiles {
+        id_filename: serialize_id_filename_map(id_filename_map)?,
+        permalinks: serde_json::to_string(&permalinks.hrefs(&build_config.site))
+            .map_err(|err| eco_format!("failed to serialize permalink map: {err}"))?,
+        notes: serde_json::to_string(&notes)
+            .map_err(|err| eco_format!("failed to serialize note index: {err}"))?,
     })
 }
 
@@ -348,3 +844,220 @@ fn write_id_filename_map(build_config: &BuildConfig, id_filename_map_json: &str)
 //         })
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::args::{CompileArgs, SourceArgs};
+
+    /// A compiler that writes no files and compiles a source to its own text,
+    /// with `{pass}` replaced by how many times it was compiled to HTML and
+    /// `{data}` by whether it was compiled with generated data.
+    #[derive(Default)]
+    struct FakeCompiler {
+        requests: RefCell<Vec<(PathBuf, CompileTarget, Vec<PdfStandard>)>>,
+    }
+
+    impl FakeCompiler {
+        /// The sources compiled to a target, in order.
+        fn compiled(&self, target: CompileTarget) -> Vec<(String, Vec<PdfStandard>)> {
+            self.requests
+                .borrow()
+                .iter()
+                .filter(|(_, request_target, _)| *request_target == target)
+                .map(|(path, _, standards)| {
+                    let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+                    (stem, standards.clone())
+                })
+                .collect()
+        }
+    }
+
+    impl TypstCompiler for FakeCompiler {
+        fn compile(
+            &self,
+            _: &BuildConfig,
+            request: &CompileRequest<'_>,
+        ) -> StrResult<CompileArtifact> {
+            let CompileInput::File(path) = request.source else {
+                return Err("unexpected stdin".into());
+            };
+            self.requests.borrow_mut().push((
+                path.to_path_buf(),
+                request.target,
+                request.pdf_standards.to_vec(),
+            ));
+            if request.target != CompileTarget::Html {
+                return Ok(CompileArtifact::FileWritten);
+            }
+            let pass = self
+                .compiled(CompileTarget::Html)
+                .iter()
+                .filter(|(stem, _)| path.file_stem().is_some_and(|own| own == stem.as_str()))
+                .count();
+            let html = fs::read_to_string(path)
+                .unwrap()
+                .replace("{pass}", &pass.to_string())
+                .replace("{data}", &request.data.is_some().to_string());
+            Ok(CompileArtifact::Stdout(html.into_bytes()))
+        }
+    }
+
+    /// A project with sources given by ID and HTML, whose head holds the
+    /// given elements.
+    fn project(notes: &[(&str, &str)]) -> (TempDir, BuildConfig) {
+        let root = tempfile::tempdir().unwrap();
+        let input = root.path().join("typ");
+        fs::create_dir(&input).unwrap();
+        for (id, head) in notes {
+            let html = format!(
+                "<html><head><meta name=\"identifier\" content=\"{id}\">{head}</head><body></body></html>"
+            );
+            fs::write(input.join(format!("{id}.typ")), html).unwrap();
+        }
+        let args = CompileArgs {
+            sources: SourceArgs {
+                input: Some(input),
+                ..Default::default()
+            },
+            output: Some(root.path().join("dist")),
+            ..Default::default()
+        };
+        let build_config = BuildConfig::from(&args, &WeibianConfig::default()).unwrap();
+        (root, build_config)
+    }
+
+    fn pdf_export_note(id: &str, export_pdf: bool) -> PdfExportNote {
+        PdfExportNote {
+            id: id.to_string(),
+            source_path: PathBuf::from(format!("typ/{id}.typ")),
+            export_pdf,
+            pdf_standards: None,
+            book: None,
+        }
+    }
+
+    #[test]
+    fn test_format_selects_outputs() {
+        assert!(OutputFormat::Html.includes_html() && !OutputFormat::Html.includes_pdf());
+        assert!(!OutputFormat::Pdf.includes_html() && OutputFormat::Pdf.includes_pdf());
+        assert!(OutputFormat::All.includes_html() && OutputFormat::All.includes_pdf());
+
+        let notes = [
+            pdf_export_note("0001", false),
+            pdf_export_note("0002", true),
+        ];
+        assert!(!exports_pdf(OutputFormat::Html, &notes));
+        assert!(exports_pdf(OutputFormat::Pdf, &notes));
+        assert!(exports_pdf(OutputFormat::All, &notes));
+        assert!(!exports_pdf(OutputFormat::All, &notes[..1]));
+    }
+
+    #[test]
+    fn test_parse_pdf_standards() {
+        assert_eq!(
+            parse_pdf_standards(" a-2b, 1.7 ,").unwrap(),
+            [PdfStandard::A_2b, PdfStandard::V_1_7]
+        );
+        assert!(parse_pdf_standards("").unwrap().is_empty());
+        let err = parse_pdf_standards("a-2b, b-9").unwrap_err();
+        assert!(err.contains("unknown PDF standard \"b-9\""), "{err}");
+    }
+
+    #[test]
+    fn test_pdf_standards_are_forwarded_per_note() {
+        let (_root, mut build_config) = project(&[
+            ("0001", r#"<meta name="pdf-standard" content="a-2b">"#),
+            ("0002", ""),
+            ("0003", r#"<meta name="export-pdf" content="false">"#),
+        ]);
+        build_config.pdf_standards = vec![PdfStandard::V_1_7];
+        let compiler = FakeCompiler::default();
+
+        let (html_notes, aliases) = compile_notes(&build_config, &compiler).unwrap();
+        let notes = collect_pdf_export_notes(&html_notes).unwrap();
+        let id_filename_map = build_id_filename_map(&build_config, &html_notes, &aliases).unwrap();
+        let permalinks = Permalinks::collect(&html_notes, &build_config.site, &aliases).unwrap();
+        let data =
+            generate_data(&build_config, &html_notes, &id_filename_map, &permalinks).unwrap();
+        export_pdf_notes(&build_config, &compiler, &notes, &id_filename_map, &data).unwrap();
+
+        let mut compiled = compiler.compiled(CompileTarget::Pdf);
+        compiled.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            compiled,
+            [
+                ("0001".to_string(), vec![PdfStandard::A_2b]),
+                ("0002".to_string(), vec![PdfStandard::V_1_7]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_pdf_standard_names_the_note() {
+        let (_root, build_config) =
+            project(&[("0001", r#"<meta name="pdf-standard" content="a-9">"#)]);
+        let (html_notes, _) = compile_notes(&build_config, &FakeCompiler::default()).unwrap();
+        let err = collect_pdf_export_notes(&html_notes).err().unwrap();
+        assert!(err.contains("invalid pdf-standard in 0001"), "{err}");
+    }
+
+    /// How many times each source was compiled to HTML.
+    fn html_compiles(compiler: &FakeCompiler, id: &str) -> usize {
+        compiler
+            .compiled(CompileTarget::Html)
+            .iter()
+            .filter(|(stem, _)| stem == id)
+            .count()
+    }
+
+    #[test]
+    fn test_two_pass_notes_are_compiled_until_the_data_converges() {
+        let (_root, build_config) = project(&[
+            (
+                "0001",
+                r#"<meta name="wb-two-pass" content="true"><title>{data}</title>"#,
+            ),
+            ("0002", "<title>Plain</title>"),
+        ]);
+        let compiler = FakeCompiler::default();
+        let (html_notes, _) = compile_notes(&build_config, &compiler).unwrap();
+
+        // The title changes with the data of the first pass, and again
+        // yields the same data in the third one.
+        assert_eq!(html_compiles(&compiler, "0001"), 3);
+        assert_eq!(html_compiles(&compiler, "0002"), 1);
+        let note = html_notes.iter().find(|note| note.id == "0001").unwrap();
+        let metadata = crate::html::extract_metadata(&note.document).unwrap();
+        let title = crate::html::extract_note_title(&note.document, &metadata).unwrap();
+        assert_eq!(title.as_deref(), Some("true"));
+    }
+
+    #[test]
+    fn test_two_pass_notes_stop_after_max_passes() {
+        let (_root, build_config) = project(&[(
+            "0001",
+            r#"<meta name="wb-two-pass" content="true"><title>{pass}</title>"#,
+        )]);
+        let compiler = FakeCompiler::default();
+        let err = compile_notes(&build_config, &compiler).err().unwrap();
+        assert!(err.contains("did not converge after 4 passes"), "{err}");
+        assert!(err.contains("0001"), "{err}");
+        assert_eq!(html_compiles(&compiler, "0001"), MAX_PASSES);
+    }
+
+    #[test]
+    fn test_notes_without_two_pass_are_compiled_once() {
+        let (_root, build_config) = project(&[(
+            "0001",
+            r#"<meta name="wb-two-pass" content="false"><title>{pass}</title>"#,
+        )]);
+        let compiler = FakeCompiler::default();
+        compile_notes(&build_config, &compiler).unwrap();
+        assert_eq!(html_compiles(&compiler, "0001"), 1);
+    }
+}
@@ -1,15 +1,22 @@
+use std::io::Write;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use ecow::eco_format;
 
+use crate::args::PdfStandard;
 use crate::config::BuildConfig;
 use crate::error::StrResult;
+use crate::generated::{self, GeneratedData};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum CompileTarget {
     Html,
     Pdf,
+    /// One SVG file per page.
+    Svg,
+    /// One PNG file per page.
+    Png,
 }
 
 impl CompileTarget {
@@ -17,6 +24,25 @@ impl CompileTarget {
         match self {
             Self::Html => "html",
             Self::Pdf => "pdf",
+            Self::Svg => "svg",
+            Self::Png => "png",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum CompileInput<'a> {
+    File(&'a Path),
+    /// Source text piped to the compiler. Paths in it are resolved against the
+    /// project root.
+    Stdin(&'a str),
+}
+
+impl CompileInput<'_> {
+    fn describe(&self) -> String {
+        match self {
+            Self::File(path) => path.display().to_string(),
+            Self::Stdin(_) => "<stdin>".to_string(),
         }
     }
 }
@@ -29,10 +55,15 @@ pub enum CompileOutput<'a> {
 
 #[derive(Debug)]
 pub struct CompileRequest<'a> {
-    pub source: &'a Path,
+    pub source: CompileInput<'a>,
     pub target: CompileTarget,
     pub output: CompileOutput<'a>,
     pub additional_inputs: &'a [(&'a str, &'a str)],
+    /// The PDF standards to enforce; ignored for other targets.
+    pub pdf_standards: &'a [PdfStandard],
+    /// The data generated for paged exports, exposed to the document as a
+    /// package.
+    pub data: Option<&'a GeneratedData>,
 }
 
 #[derive(Debug)]
@@ -58,67 +89,15 @@ impl TypstCompiler for CliTypstCompiler {
         build_config: &BuildConfig,
         request: &CompileRequest<'_>,
     ) -> StrResult<CompileArtifact> {
-        let root = build_config
-            .world
-            .root
-            .as_ref()
-            .unwrap_or(&build_config.input_directory);
-
-        let mut cmd = Command::new("typst");
-        cmd.arg("compile")
-            .arg("--format")
-            .arg(request.target.as_str())
-            .arg("--features")
-            .arg("html")
-            .arg("--root")
-            .arg(root);
-
-        if let Some(jobs) = build_config.process.jobs {
-            cmd.arg("--jobs").arg(jobs.to_string());
-        }
-
-        for (key, value) in &build_config.world.inputs {
-            cmd.arg("--input").arg(format!("{key}={value}"));
-        }
-
-        for font_path in &build_config.world.font.font_paths {
-            cmd.arg("--font-path").arg(font_path);
-        }
-
-        if build_config.world.font.ignore_system_fonts {
-            cmd.arg("--ignore-system-fonts");
-        }
-
-        if let Some(path) = &build_config.world.package.package_path {
-            cmd.arg("--package-path").arg(path);
+        let mut cmd = typst_command(build_config, request);
+        let output = match request.source {
+            CompileInput::File(_) => cmd.output(),
+            CompileInput::Stdin(text) => run_with_stdin(&mut cmd, text),
         }
-
-        if let Some(path) = &build_config.world.package.package_cache_path {
-            cmd.arg("--package-cache-path").arg(path);
-        }
-
-        for (key, value) in generate_inputs_from_build_config(build_config, request.target) {
-            cmd.arg("--input").arg(format!("{key}={value}"));
-        }
-
-        for (key, value) in request.additional_inputs {
-            cmd.arg("--input").arg(format!("{key}={value}"));
-        }
-
-        cmd.arg(request.source);
-        match request.output {
-            CompileOutput::Stdout => {
-                cmd.arg("-");
-            }
-            CompileOutput::File(path) => {
-                cmd.arg(path);
-            }
-        }
-
-        let output = cmd.output().map_err(|err| {
+        .map_err(|err| {
             eco_format!(
                 "failed to run typst for {}: {err}",
-                request.source.display()
+                request.source.describe()
             )
         })?;
 
@@ -129,7 +108,7 @@ impl TypstCompiler for CliTypstCompiler {
             };
             return Err(eco_format!(
                 "typst compile failed for {} -> {}: {}",
-                request.source.display(),
+                request.source.describe(),
                 destination,
                 String::from_utf8_lossy(&output.stderr)
             ));
@@ -142,6 +121,102 @@ impl TypstCompiler for CliTypstCompiler {
     }
 }
 
+/// The `typst compile` invocation for a request.
+fn typst_command(build_config: &BuildConfig, request: &CompileRequest<'_>) -> Command {
+    let root = build_config
+        .world
+        .root
+        .as_ref()
+        .unwrap_or(&build_config.input_directory);
+
+    let mut cmd = Command::new("typst");
+    cmd.arg("compile")
+        .arg("--format")
+        .arg(request.target.as_str())
+        .arg("--features")
+        .arg("html")
+        .arg("--root")
+        .arg(root);
+
+    if let Some(jobs) = build_config.process.jobs {
+        cmd.arg("--jobs").arg(jobs.to_string());
+    }
+
+    for (key, value) in &build_config.world.inputs {
+        cmd.arg("--input").arg(format!("{key}={value}"));
+    }
+
+    for font_path in &build_config.world.font.font_paths {
+        cmd.arg("--font-path").arg(font_path);
+    }
+
+    if build_config.world.font.ignore_system_fonts {
+        cmd.arg("--ignore-system-fonts");
+    }
+
+    // Typst takes a single local package directory, so the generated one
+    // also holds the packages of the configured one.
+    if let Some(data) = request.data {
+        cmd.arg("--package-path").arg(data.package_path());
+        cmd.arg("--input")
+            .arg(format!("wb-data-package={}", generated::DATA_PACKAGE));
+    } else if let Some(path) = &build_config.world.package.package_path {
+        cmd.arg("--package-path").arg(path);
+    }
+
+    if let Some(path) = &build_config.world.package.package_cache_path {
+        cmd.arg("--package-cache-path").arg(path);
+    }
+
+    for (key, value) in generate_inputs_from_build_config(build_config, request.target) {
+        cmd.arg("--input").arg(format!("{key}={value}"));
+    }
+
+    for (key, value) in request.additional_inputs {
+        cmd.arg("--input").arg(format!("{key}={value}"));
+    }
+
+    if request.target == CompileTarget::Pdf && !request.pdf_standards.is_empty() {
+        let standards = request
+            .pdf_standards
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        cmd.arg("--pdf-standard").arg(standards);
+    }
+
+    match request.source {
+        CompileInput::File(path) => {
+            cmd.arg(path);
+        }
+        CompileInput::Stdin(_) => {
+            cmd.arg("-");
+        }
+    }
+    match request.output {
+        CompileOutput::Stdout => {
+            cmd.arg("-");
+        }
+        CompileOutput::File(path) => {
+            cmd.arg(path);
+        }
+    }
+    cmd
+}
+
+fn run_with_stdin(cmd: &mut Command, text: &str) -> std::io::Result<std::process::Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    child.wait_with_output()
+}
+
 fn generate_inputs_from_build_config(
     build_config: &BuildConfig,
     target: CompileTarget,
@@ -168,6 +243,55 @@ fn generate_inputs_from_build_config(
             "false".to_string()
         },
     ));
+    inputs.push(("wb-home".to_string(), build_config.site.home.clone()));
     inputs.push(("wb-target".to_string(), target.as_str().to_string()));
     inputs
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{CompileArgs, SourceArgs};
+    use crate::config::WeibianConfig;
+
+    /// The arguments following `flag` in a command.
+    fn flag_values(cmd: &Command, flag: &str) -> Vec<String> {
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_string_lossy()).collect();
+        args.windows(2)
+            .filter(|pair| pair[0] == flag)
+            .map(|pair| pair[1].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_pdf_standards_are_passed_to_pdf_compilations() {
+        let root = tempfile::tempdir().unwrap();
+        let input = root.path().join("typ");
+        std::fs::create_dir(&input).unwrap();
+        let args = CompileArgs {
+            sources: SourceArgs {
+                input: Some(input.clone()),
+                ..Default::default()
+            },
+            output: Some(root.path().join("dist")),
+            ..Default::default()
+        };
+        let build_config = BuildConfig::from(&args, &WeibianConfig::default()).unwrap();
+        let source = input.join("0001.typ");
+        let standards = [PdfStandard::A_2b, PdfStandard::V_1_7];
+        let request = |target| CompileRequest {
+            source: CompileInput::File(&source),
+            target,
+            output: CompileOutput::Stdout,
+            additional_inputs: &[],
+            pdf_standards: &standards,
+            data: None,
+        };
+
+        let pdf = typst_command(&build_config, &request(CompileTarget::Pdf));
+        assert_eq!(flag_values(&pdf, "--pdf-standard"), ["a-2b,1.7"]);
+        assert_eq!(flag_values(&pdf, "--format"), ["pdf"]);
+        let html = typst_command(&build_config, &request(CompileTarget::Html));
+        assert!(flag_values(&html, "--pdf-standard").is_empty());
+    }
+}
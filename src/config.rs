@@ -1,17 +1,30 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::path::{Path, PathBuf};
 
 use ecow::eco_format;
-use figment::Figment;
 use figment::providers::{Format, Toml};
+use figment::value::{Dict, Map, Value};
+use figment::{Figment, Metadata, Profile, Provider};
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::de::{self, SeqAccess, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::args::{CompileArgs, ProcessArgs, WorldArgs};
+use clap::ValueEnum;
+
+use crate::args::{
+    CompileArgs, ConfigAction, ConfigCommand, OutputFormat, PdfStandard, ProcessArgs, WorldArgs,
+};
 use crate::error::StrResult;
 
 const DEFAULT_CONFIG_PATH: &str = ".wb/config.toml";
+/// The prefix of the environment variables overriding configuration values,
+/// whose sections and keys are separated by `__`, e.g. `WB_SITE__DOMAIN`.
+const ENV_PREFIX: &str = "WB_";
+const ENV_SEPARATOR: &str = "__";
+/// The name of the environment in the metadata of configuration values.
+const ENV_NAME: &str = "environment variables";
 
 #[derive(Debug, Default, Deserialize)]
 pub struct WeibianConfig {
@@ -20,6 +33,40 @@ pub struct WeibianConfig {
 
     #[serde(default)]
     pub site: SiteConfig,
+
+    #[serde(default)]
+    pub render: RenderConfig,
+
+    #[serde(default)]
+    pub pdf: PdfConfig,
+
+    #[serde(default)]
+    pub og: OgConfig,
+
+    #[serde(default)]
+    pub new: NewConfig,
+
+    /// Former note IDs, mapped to the IDs of the notes they stand for.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+
+    /// Named sets of overrides, selected with `--profile`.
+    #[serde(default)]
+    pub profile: BTreeMap<String, ProfileConfig>,
+
+    /// The keys set by the configuration file or the environment that are
+    /// not part of the configuration, described for the user.
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
+
+    /// The path of the configuration file.
+    #[serde(skip)]
+    pub path: PathBuf,
+
+    /// The values of the configuration file and the environment, with where
+    /// each of them comes from.
+    #[serde(skip)]
+    pub figment: Figment,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -31,6 +78,7 @@ pub struct FilesConfig {
     pub include: Vec<String>,
     #[serde(default, deserialize_with = "deserialize_glob_list")]
     pub exclude: Vec<String>,
+    pub format: Option<OutputFormat>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -38,12 +86,114 @@ pub struct SiteConfig {
     pub domain: Option<String>,
     pub root_dir: Option<String>,
     pub trailing_slash: Option<bool>,
+    pub permalink: Option<String>,
+    pub home: Option<String>,
+    pub not_found: Option<bool>,
 }
+
+/// Overrides of the base configuration for one kind of build, e.g. a public
+/// site built from the same notes as a private one.
+#[derive(Debug, Default, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default, deserialize_with = "deserialize_optional_glob_list")]
+    pub include: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_optional_glob_list")]
+    pub exclude: Option<Vec<String>>,
+    pub output_dir: Option<PathBuf>,
+    pub domain: Option<String>,
+    pub root_dir: Option<String>,
+    /// Extra key-value pairs visible through `sys.inputs`.
+    #[serde(default)]
+    pub inputs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PdfConfig {
+    #[serde(default)]
+    pub standards: Vec<PdfStandard>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OgConfig {
+    /// The Typst template rendering the social preview image of a note,
+    /// relative to the input directory.
+    pub card: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NewConfig {
+    /// The Tera template of the source of new notes, relative to the input
+    /// directory.
+    pub skeleton: Option<PathBuf>,
+    /// The Tera template of the source of new journal notes, relative to the
+    /// input directory (defaults to `skeleton`).
+    pub journal_skeleton: Option<PathBuf>,
+    /// Where journal notes are created, relative to the input directory.
+    pub journal_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RenderConfig {
+    pub backmatter_mode: Option<TransclusionMode>,
+    pub lazy_threshold: Option<usize>,
+    pub footnotes: Option<FootnotePlacement>,
+    pub alias_warnings: Option<bool>,
+}
+
+/// How much of a note a transclusion renders.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransclusionMode {
+    /// The whole body of the note.
+    #[default]
+    Full,
+    /// The title, metadata and an excerpt of the note.
+    Summary,
+}
+
+impl TransclusionMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Summary => "summary",
+        }
+    }
+}
+
+/// Where the footnotes of a page are listed.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FootnotePlacement {
+    /// In a single list at the end of the page, numbered across the page.
+    #[default]
+    Page,
+    /// At the end of each transcluded note, numbered within it.
+    Section,
+}
+
 #[derive(Debug, Clone)]
 pub struct SiteSettings {
     pub domain: Option<String>,
     pub root_dir: String,
     pub trailing_slash: bool,
+    /// The pattern of the paths of note pages, e.g. `/{taxon}/{id}/`.
+    pub permalink: Option<String>,
+    /// The ID of the note written at the root of the site.
+    pub home: String,
+    /// Whether to render a 404 page with the `404.html` template.
+    pub not_found: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub backmatter_mode: TransclusionMode,
+    /// Collapsed transclusions of notes with more than this many elements,
+    /// counting the notes they transclude, are loaded client-side instead of
+    /// being inlined.
+    pub lazy_threshold: Option<usize>,
+    pub footnotes: FootnotePlacement,
+    /// Whether to warn about notes referring to others by an alias.
+    pub alias_warnings: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -86,79 +236,756 @@ impl InputFilters {
 pub struct BuildConfig {
     pub input_directory: PathBuf,
     pub input_filters: InputFilters,
+    /// The globs the input filters are built from.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
     pub public_directory: PathBuf,
     pub output_directory: PathBuf,
+    pub format: OutputFormat,
     pub site: SiteSettings,
+    pub render: RenderSettings,
+    /// The PDF standards enforced for notes that do not set their own.
+    pub pdf_standards: Vec<PdfStandard>,
+    /// The template of the social preview images, if they are generated.
+    pub og_card: Option<PathBuf>,
+    pub aliases: BTreeMap<String, String>,
+    /// Whether the home note is set by the configuration, in which case the
+    /// site cannot be built without it.
+    pub home_configured: bool,
     pub world: WorldArgs,
     pub process: ProcessArgs,
 }
 
+/// The path of the configuration file.
+pub fn config_path(explicit: Option<&Path>) -> PathBuf {
+    explicit
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+}
+
 pub fn load_config(config_path: Option<&Path>) -> StrResult<WeibianConfig> {
-    let (path, is_default) = match config_path {
-        Some(path) => (path.to_path_buf(), false),
-        None => (PathBuf::from(DEFAULT_CONFIG_PATH), true),
+    let path = self::config_path(config_path);
+    if config_path.is_some() && !path.exists() {
+        return Err(eco_format!("config file {} does not exist", path.display()));
+    }
+
+    load(path, env())
+}
+
+fn load(path: PathBuf, env: EnvLayer) -> StrResult<WeibianConfig> {
+    let figment = Figment::new().merge(Toml::file(&path)).merge(env);
+    let mut config = figment
+        .extract_lossy::<WeibianConfig>()
+        .map_err(|err| eco_format!("failed to load config {}: {err}", path.display()))?;
+    config.unknown_keys = find_unknown_keys(&figment)?;
+    config.path = path;
+    config.figment = figment;
+    Ok(config)
+}
+
+/// Describes every key that the configuration does not use, with the known
+/// key it is closest to.
+fn find_unknown_keys(figment: &Figment) -> StrResult<Vec<String>> {
+    /// The keys ignored when deserializing the configuration.
+    struct Unknown(Vec<String>);
+
+    impl<'de> Deserialize<'de> for Unknown {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let mut unknown = Vec::new();
+            serde_ignored::deserialize::<_, _, WeibianConfig>(deserializer, |path| {
+                unknown.push(path.to_string())
+            })?;
+            Ok(Self(unknown))
+        }
+    }
+
+    let Unknown(unknown) = figment
+        .extract_lossy()
+        .map_err(|err| eco_format!("failed to load config: {err}"))?;
+    Ok(unknown
+        .into_iter()
+        .map(|key| {
+            let segments: Vec<&str> = key.split('.').collect();
+            let (name, section) = segments.split_last().unwrap_or((&"", &[]));
+            let origin = match figment.find_metadata(&key) {
+                Some(metadata) if metadata.name == ENV_NAME => {
+                    format!(" set by environment variable {}", env_var(&key))
+                }
+                Some(metadata) => match &metadata.source {
+                    Some(source) => format!(" in config file {source}"),
+                    None => String::new(),
+                },
+                None => String::new(),
+            };
+            let suggestion = known_keys(section)
+                .iter()
+                .map(|known| (strsim::jaro_winkler(name, known), known))
+                .filter(|(similarity, _)| *similarity >= 0.8)
+                .max_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, known)| {
+                    let mut path = section.to_vec();
+                    path.push(known);
+                    format!("; did you mean `{}`?", path.join("."))
+                })
+                .unwrap_or_default();
+            format!("unknown key `{key}`{origin}{suggestion}")
+        })
+        .collect())
+}
+
+/// The keys of a section of the configuration, given by its path.
+fn known_keys(section: &[&str]) -> &'static [&'static str] {
+    match section {
+        [] => struct_fields::<WeibianConfig>(),
+        ["files" | "directories"] => struct_fields::<FilesConfig>(),
+        ["site"] => struct_fields::<SiteConfig>(),
+        ["render"] => struct_fields::<RenderConfig>(),
+        ["pdf"] => struct_fields::<PdfConfig>(),
+        ["og"] => struct_fields::<OgConfig>(),
+        ["new"] => struct_fields::<NewConfig>(),
+        ["profile", _] => struct_fields::<ProfileConfig>(),
+        _ => &[],
+    }
+}
+
+/// The fields a struct is deserialized from, as declared to serde.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    /// A deserializer that only records the fields it is asked for.
+    struct Fields<'a>(&'a Cell<&'static [&'static str]>);
+
+    impl<'de> Deserializer<'de> for Fields<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0.set(fields);
+            Err(de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let fields = Cell::new(&[][..]);
+    let _ = T::deserialize(Fields(&fields));
+    fields.get()
+}
+
+/// The environment variable setting a configuration key.
+fn env_var(key: &str) -> String {
+    let variable = key
+        .split('.')
+        .map(str::to_ascii_uppercase)
+        .collect::<Vec<_>>()
+        .join(ENV_SEPARATOR);
+    format!("{ENV_PREFIX}{variable}")
+}
+
+/// Fails with every problem found in the configuration, in a single list.
+fn report(problems: Vec<String>) -> StrResult<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let list: Vec<String> = problems
+        .iter()
+        .map(|problem| format!("  - {problem}"))
+        .collect();
+    Err(eco_format!("invalid configuration:\n{}", list.join("\n")))
+}
+
+/// The sources of the configuration for a build, from the lowest precedence
+/// to the highest: the configuration file, the selected profile, the
+/// environment and the command line. Built-in defaults fill in what none of
+/// them sets.
+///
+/// The configuration file and the environment are those loaded by
+/// `load_config`, so the profile only sets the keys that the environment
+/// does not.
+fn layers(
+    args: &CompileArgs,
+    profile: Option<(&str, &ProfileConfig)>,
+    config: &WeibianConfig,
+) -> Figment {
+    let mut figment = config.figment.clone();
+    if let Some((name, profile)) = profile {
+        let mut layer = profile_layer(name, profile);
+        layer.retain(|key| !is_from_env(&config.figment, key));
+        figment = figment.merge(layer);
+    }
+    figment.merge(cli_layer(args))
+}
+
+/// Whether the value of a key is set by an environment variable.
+fn is_from_env(figment: &Figment, key: &str) -> bool {
+    figment
+        .find_metadata(key)
+        .is_some_and(|metadata| metadata.name == ENV_NAME)
+}
+
+fn env() -> EnvLayer {
+    EnvLayer::new(std::env::vars())
+}
+
+/// The configuration values set by `WB_` environment variables.
+///
+/// Values are kept as strings, unlike with figment's `Env`, so that note IDs
+/// like `0008` are not read as numbers; settings that are numbers or booleans
+/// are read from the strings when the configuration is extracted. Arrays,
+/// e.g. `[a-2b, "1.7"]`, are parsed.
+struct EnvLayer {
+    values: Dict,
+}
+
+impl EnvLayer {
+    fn new(vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut values = Dict::new();
+        for (name, value) in vars {
+            let Some(variable) = name
+                .get(..ENV_PREFIX.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(ENV_PREFIX))
+                .map(|_| &name[ENV_PREFIX.len()..])
+            else {
+                continue;
+            };
+            // Only keys in a section, so that e.g. `WB_PROFILE` is left to
+            // the CLI.
+            if !variable.contains(ENV_SEPARATOR) {
+                continue;
+            }
+            let keys: Vec<String> = variable
+                .split(ENV_SEPARATOR)
+                .map(str::to_ascii_lowercase)
+                .collect();
+            let value = value.trim();
+            let value = match value.parse::<Value>() {
+                Ok(parsed @ (Value::Array(..) | Value::Dict(..))) => parsed,
+                _ => Value::from(value.to_string()),
+            };
+            insert_nested(&mut values, &keys, value);
+        }
+        Self { values }
+    }
+}
+
+/// Sets the value of a key given by its path in nested dictionaries.
+fn insert_nested(dict: &mut Dict, keys: &[String], value: Value) {
+    let Some((key, rest)) = keys.split_first() else {
+        return;
     };
+    if rest.is_empty() {
+        dict.insert(key.clone(), value);
+        return;
+    }
+    let entry = dict
+        .entry(key.clone())
+        .or_insert_with(|| Value::from(Dict::new()));
+    if !matches!(entry, Value::Dict(..)) {
+        *entry = Value::from(Dict::new());
+    }
+    if let Value::Dict(_, inner) = entry {
+        insert_nested(inner, rest, value);
+    }
+}
+
+impl Provider for EnvLayer {
+    fn metadata(&self) -> Metadata {
+        Metadata::named(ENV_NAME)
+            .interpolater(|_: &Profile, keys: &[&str]| env_var(&keys.join(".")))
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        Ok(Profile::Default.collect(self.values.clone()))
+    }
+}
+
+/// Configuration values set by something else than a file or the
+/// environment, named after it.
+struct Layer {
+    name: String,
+    values: Dict,
+}
 
-    if !path.exists() {
-        if is_default {
-            return Ok(WeibianConfig::default());
+impl Layer {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            values: Dict::new(),
         }
-        return Err(eco_format!("config file {} does not exist", path.display()));
     }
 
-    Figment::new()
-        .merge(Toml::file(&path))
-        .extract::<WeibianConfig>()
-        .map_err(|err| eco_format!("failed to load config {}: {err}", path.display()))
+    /// Keeps only the keys, e.g. `site.domain`, that `keep` returns true for.
+    fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        for (section, values) in &mut self.values {
+            if let Value::Dict(_, values) = values {
+                values.retain(|key, _| keep(&format!("{section}.{key}")));
+            }
+        }
+    }
+
+    /// Sets the value of a key of a section, e.g. `site.domain`.
+    fn set(&mut self, section: &str, key: &str, value: impl Into<Value>) {
+        let section = self
+            .values
+            .entry(section.to_string())
+            .or_insert_with(|| Value::from(Dict::new()));
+        if let Value::Dict(_, section) = section {
+            section.insert(key.to_string(), value.into());
+        }
+    }
+}
+
+impl Provider for Layer {
+    fn metadata(&self) -> Metadata {
+        Metadata::named(self.name.clone())
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        Ok(Profile::Default.collect(self.values.clone()))
+    }
+}
+
+/// The values a profile overrides, under the keys of the base configuration.
+fn profile_layer(name: &str, profile: &ProfileConfig) -> Layer {
+    let mut layer = Layer::new(format!("profile {name}"));
+    if let Some(include) = &profile.include {
+        layer.set("files", "include", include.clone());
+    }
+    if let Some(exclude) = &profile.exclude {
+        layer.set("files", "exclude", exclude.clone());
+    }
+    if let Some(output_dir) = &profile.output_dir {
+        layer.set("files", "output_dir", path_value(output_dir));
+    }
+    if let Some(domain) = &profile.domain {
+        layer.set("site", "domain", domain.as_str());
+    }
+    if let Some(root_dir) = &profile.root_dir {
+        layer.set("site", "root_dir", root_dir.as_str());
+    }
+    layer
+}
+
+/// The values set by command line flags, under the keys of the
+/// configuration.
+fn cli_layer(args: &CompileArgs) -> Layer {
+    let mut layer = Layer::new("command line".to_string());
+    let directories = [
+        ("input_dir", &args.sources.input),
+        ("public_dir", &args.public),
+        ("output_dir", &args.output),
+    ];
+    for (key, dir) in directories {
+        if let Some(dir) = dir {
+            layer.set("files", key, path_value(dir));
+        }
+    }
+    if let Some(format) = args.format {
+        layer.set("files", "format", value_name(format));
+    }
+    let site = [
+        ("domain", &args.site.domain),
+        ("root_dir", &args.site.root_dir),
+        ("permalink", &args.site.permalink),
+        ("home", &args.site.home),
+    ];
+    for (key, value) in site {
+        if let Some(value) = value {
+            layer.set("site", key, value.as_str());
+        }
+    }
+    if let Some(trailing_slash) = args.site.trailing_slash {
+        layer.set("site", "trailing_slash", trailing_slash);
+    }
+    if !args.pdf_standard.is_empty() {
+        let standards: Vec<String> = args.pdf_standard.iter().copied().map(value_name).collect();
+        layer.set("pdf", "standards", standards);
+    }
+    layer
+}
+
+fn path_value(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// The name of a value of a command line flag, which is also its name in the
+/// configuration.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Runs a `wb config` command.
+pub fn config(command: &ConfigCommand, config: &WeibianConfig) -> StrResult<()> {
+    match &command.action {
+        ConfigAction::Show(args) => show_config(&args.args, config),
+    }
+}
+
+/// Prints every setting of a build, with where its value comes from.
+fn show_config(args: &CompileArgs, config: &WeibianConfig) -> StrResult<()> {
+    let build_config = BuildConfig::from(args, config)?;
+    let profile = selected_profile(args, config)?;
+    let figment = layers(args, profile, config);
+    let source = |key: &str| match figment.find_metadata(key) {
+        Some(metadata) if metadata.name == ENV_NAME => {
+            format!("environment variable {}", env_var(key))
+        }
+        Some(metadata) => match &metadata.source {
+            Some(source) => format!("config file {source}"),
+            None => metadata.name.to_string(),
+        },
+        None => "default".to_string(),
+    };
+
+    let site = &build_config.site;
+    let render = &build_config.render;
+    let standards: Vec<String> = build_config
+        .pdf_standards
+        .iter()
+        .copied()
+        .map(value_name)
+        .collect();
+    let mut entries = vec![
+        (
+            "files.input_dir",
+            serde_json::json!(build_config.input_directory),
+        ),
+        (
+            "files.output_dir",
+            serde_json::json!(build_config.output_directory),
+        ),
+        (
+            "files.public_dir",
+            serde_json::json!(build_config.public_directory),
+        ),
+        ("files.include", serde_json::json!(build_config.include)),
+        ("files.exclude", serde_json::json!(build_config.exclude)),
+        (
+            "files.format",
+            serde_json::json!(value_name(build_config.format)),
+        ),
+        ("site.domain", serde_json::json!(site.domain)),
+        ("site.root_dir", serde_json::json!(site.root_dir)),
+        (
+            "site.trailing_slash",
+            serde_json::json!(site.trailing_slash),
+        ),
+        ("site.permalink", serde_json::json!(site.permalink)),
+        ("site.home", serde_json::json!(site.home)),
+        ("site.not_found", serde_json::json!(site.not_found)),
+        (
+            "render.backmatter_mode",
+            serde_json::json!(render.backmatter_mode),
+        ),
+        (
+            "render.lazy_threshold",
+            serde_json::json!(render.lazy_threshold),
+        ),
+        ("render.footnotes", serde_json::json!(render.footnotes)),
+        (
+            "render.alias_warnings",
+            serde_json::json!(render.alias_warnings),
+        ),
+        ("pdf.standards", serde_json::json!(standards)),
+        ("og.card", serde_json::json!(build_config.og_card)),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value, source(key)))
+    .collect::<Vec<_>>();
+    for (alias, target) in &build_config.aliases {
+        let key = format!("aliases.{alias}");
+        let source = source(&key);
+        entries.push((key, serde_json::json!(target), source));
+    }
+    for (key, value) in &build_config.world.inputs {
+        let source = if args.world.inputs.iter().any(|(cli_key, _)| cli_key == key) {
+            "command line".to_string()
+        } else {
+            format!(
+                "profile {}",
+                args.sources.profile.as_deref().unwrap_or_default()
+            )
+        };
+        entries.push((format!("inputs.{key}"), serde_json::json!(value), source));
+    }
+
+    for (key, value, source) in entries {
+        println!("{key} = {value} # {source}");
+    }
+    Ok(())
+}
+
+/// The profile selected by the command line, if any.
+fn selected_profile<'a>(
+    args: &'a CompileArgs,
+    config: &'a WeibianConfig,
+) -> StrResult<Option<(&'a str, &'a ProfileConfig)>> {
+    let Some(name) = &args.sources.profile else {
+        return Ok(None);
+    };
+    let profile = config
+        .profile
+        .get(name)
+        .ok_or_else(|| eco_format!("profile {name} is not defined in the configuration"))?;
+    Ok(Some((name.as_str(), profile)))
 }
 
 impl BuildConfig {
+    /// Resolves every setting from the command line, the environment, the
+    /// selected profile and the configuration file, in this order of
+    /// precedence, falling back to the built-in defaults.
     pub fn from(args: &CompileArgs, config: &WeibianConfig) -> StrResult<Self> {
-        let input_directory =
-            resolve_dir(args.input.as_ref(), config.files.input_dir.as_ref(), "typ");
-        let input_filters = InputFilters::new(&config.files.include, &config.files.exclude)?;
-        let public_directory = resolve_dir(
-            args.public.as_ref(),
-            config.files.public_dir.as_ref(),
-            "public",
-        );
-        let output_directory = resolve_dir(
-            args.output.as_ref(),
-            config.files.output_dir.as_ref(),
-            "dist",
-        );
+        let profile = selected_profile(args, config)?;
+        let resolved = layers(args, profile, config)
+            .extract_lossy::<WeibianConfig>()
+            .map_err(|err| eco_format!("failed to load config {}: {err}", config.path.display()))?;
+        let WeibianConfig {
+            files,
+            site,
+            render,
+            pdf,
+            og,
+            aliases,
+            ..
+        } = resolved;
 
-        let domain = args
-            .site
-            .domain
-            .clone()
-            .or_else(|| config.site.domain.clone());
-        let root_dir = normalize_root_dir(
-            args.site
-                .root_dir
-                .as_deref()
-                .or(config.site.root_dir.as_deref()),
-        );
-        let trailing_slash = args
-            .site
-            .trailing_slash
-            .unwrap_or(config.site.trailing_slash.unwrap_or(false));
+        let input_directory = resolve_dir(files.input_dir.as_ref(), "typ");
+        let public_directory = resolve_dir(files.public_dir.as_ref(), "public");
+        let output_directory = resolve_dir(files.output_dir.as_ref(), "dist");
+
+        let mut problems = config.unknown_keys.clone();
+        problems.extend(check_directories(
+            &input_directory,
+            &output_directory,
+            files.public_dir.is_some().then_some(&public_directory),
+        ));
+        if let Some(domain) = &site.domain
+            && let Err(problem) = check_domain(domain)
+        {
+            problems.push(problem);
+        }
+        problems.extend(check_globs(
+            &input_directory,
+            &files.include,
+            &files.exclude,
+        ));
+        report(problems)?;
+
+        let input_filters = InputFilters::new(&files.include, &files.exclude)?;
+        let home_configured = site.home.is_some();
+        let profile_inputs = profile.map(|(_, profile)| profile.inputs.clone());
         Ok(Self {
             input_directory,
             input_filters,
+            include: files.include,
+            exclude: files.exclude,
             public_directory,
             output_directory,
+            format: files.format.unwrap_or_default(),
             site: SiteSettings {
-                domain,
-                root_dir,
-                trailing_slash,
+                domain: site.domain,
+                root_dir: normalize_root_dir(site.root_dir.as_deref()),
+                trailing_slash: site.trailing_slash.unwrap_or(false),
+                permalink: site.permalink,
+                home: site.home.unwrap_or_else(|| "index".to_string()),
+                not_found: site.not_found.unwrap_or(true),
+            },
+            render: RenderSettings {
+                backmatter_mode: render.backmatter_mode.unwrap_or(TransclusionMode::Summary),
+                lazy_threshold: render.lazy_threshold,
+                footnotes: render.footnotes.unwrap_or_default(),
+                alias_warnings: render.alias_warnings.unwrap_or(false),
+            },
+            pdf_standards: pdf.standards,
+            og_card: og.card,
+            aliases,
+            home_configured,
+            world: WorldArgs {
+                inputs: merge_inputs(&profile_inputs.unwrap_or_default(), &args.world.inputs),
+                ..args.world.clone()
             },
-            world: args.world.clone(),
             process: args.process.clone(),
         })
     }
 }
 
+/// Checks that the input directory and an explicitly set public directory
+/// exist, and that the output directory does not overlap with either.
+fn check_directories(input: &Path, output: &Path, public: Option<&PathBuf>) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !input.is_dir() {
+        problems.push(format!(
+            "input directory {} does not exist",
+            input.display()
+        ));
+    }
+    if let Some(public) = public
+        && !public.is_dir()
+    {
+        problems.push(format!(
+            "public directory {} does not exist",
+            public.display()
+        ));
+    }
+
+    let (input_path, output_path) = (resolve_path(input), resolve_path(output));
+    if output_path.starts_with(&input_path) {
+        problems.push(format!(
+            "output directory {} is inside the input directory {}, so its files would be compiled as notes",
+            output.display(),
+            input.display()
+        ));
+    } else if input_path.starts_with(&output_path) {
+        problems.push(format!(
+            "input directory {} is inside the output directory {}, so the sources would be mixed with the site",
+            input.display(),
+            output.display()
+        ));
+    }
+    if let Some(public) = public {
+        let public_path = resolve_path(public);
+        if public_path.starts_with(&output_path) || output_path.starts_with(&public_path) {
+            problems.push(format!(
+                "public directory {} and output directory {} overlap, so the public files would be copied into themselves",
+                public.display(),
+                output.display()
+            ));
+        }
+    }
+    problems
+}
+
+/// The absolute form of a path that may not exist yet, with the symbolic
+/// links of its existing ancestors resolved.
+fn resolve_path(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut missing = Vec::new();
+    let mut existing = absolute.as_path();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return absolute,
+        }
+    }
+}
+
+/// Checks that a domain is a host name, with an optional `http` or `https`
+/// scheme and port.
+fn check_domain(domain: &str) -> Result<(), String> {
+    let malformed = |reason: &str| Err(format!("domain \"{domain}\" is malformed: {reason}"));
+    let rest = match domain.split_once("://") {
+        Some(("http" | "https", rest)) => rest,
+        Some((scheme, _)) => {
+            return malformed(&format!("the scheme must be http or https, not {scheme}"));
+        }
+        None => domain,
+    };
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    if rest.contains('/') {
+        return malformed("it must not contain a path; set root_dir instead");
+    }
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (rest, None),
+    };
+    if let Some(port) = port
+        && port.parse::<u16>().is_err()
+    {
+        return malformed(&format!("\"{port}\" is not a port"));
+    }
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if host.is_empty() || !host.split('.').all(valid_label) {
+        return malformed(
+            "the host must be labels of ASCII letters, digits and \"-\" separated by \".\"",
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the include and exclude globs are valid, and that each
+/// include glob matches a source in the input directory.
+fn check_globs(input: &Path, include: &[String], exclude: &[String]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut matchers = Vec::new();
+    for (label, patterns) in [("include", include), ("exclude", exclude)] {
+        for pattern in patterns {
+            match Glob::new(pattern) {
+                Ok(glob) if label == "include" => {
+                    matchers.push((pattern, glob.compile_matcher()));
+                }
+                Ok(_) => {}
+                Err(err) => problems.push(format!("invalid {label} glob \"{pattern}\": {err}")),
+            }
+        }
+    }
+    if matchers.is_empty() || !input.is_dir() {
+        return problems;
+    }
+
+    let mut stack = vec![input.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(input).unwrap_or(&path);
+            matchers.retain(|(_, matcher)| !matcher.is_match(relative));
+        }
+    }
+    for (pattern, _) in matchers {
+        problems.push(format!(
+            "include glob \"{pattern}\" matches no file in the input directory {}",
+            input.display()
+        ));
+    }
+    problems
+}
+
+/// The inputs of a profile, overridden by those given on the command line.
+fn merge_inputs(
+    profile: &BTreeMap<String, String>,
+    cli: &[(String, String)],
+) -> Vec<(String, String)> {
+    profile
+        .iter()
+        .filter(|(key, _)| !cli.iter().any(|(cli_key, _)| cli_key == *key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .chain(cli.iter().cloned())
+        .collect()
+}
+
 fn build_globset(patterns: &[String], label: &str) -> StrResult<GlobSet> {
     let mut builder = GlobSetBuilder::new();
     for pattern in patterns {
@@ -213,10 +1040,16 @@ where
     deserializer.deserialize_any(GlobListVisitor)
 }
 
-fn resolve_dir(cli: Option<&PathBuf>, config: Option<&PathBuf>, default: &str) -> PathBuf {
-    cli.cloned()
-        .or_else(|| config.cloned())
-        .unwrap_or_else(|| PathBuf::from(default))
+fn deserialize_optional_glob_list<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_glob_list(deserializer).map(Some)
+}
+
+/// A configured directory, or its default.
+fn resolve_dir(config: Option<&PathBuf>, default: &str) -> PathBuf {
+    config.cloned().unwrap_or_else(|| PathBuf::from(default))
 }
 
 fn normalize_root_dir(raw: Option<&str>) -> String {
@@ -232,3 +1065,183 @@ fn normalize_root_dir(raw: Option<&str>) -> String {
     }
     root
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_layer(vars: &[(&str, &str)]) -> EnvLayer {
+        EnvLayer::new(
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+    }
+
+    #[test]
+    fn test_env_keeps_numeric_looking_ids() {
+        let env = env_layer(&[
+            ("WB_SITE__HOME", "0008"),
+            ("WB_ALIASES__OLD", "0010"),
+            ("WB_RENDER__LAZY_THRESHOLD", "500"),
+            ("WB_SITE__TRAILING_SLASH", "true"),
+            ("WB_PDF__STANDARDS", "[a-2b, \"1.7\"]"),
+            ("WB_PROFILE", "public"),
+            ("OTHER__KEY", "1"),
+        ]);
+        let config = load(PathBuf::from("/nonexistent/config.toml"), env).unwrap();
+        assert_eq!(config.site.home.as_deref(), Some("0008"));
+        assert_eq!(config.aliases.get("old").map(String::as_str), Some("0010"));
+        assert_eq!(config.render.lazy_threshold, Some(500));
+        assert_eq!(config.site.trailing_slash, Some(true));
+        assert_eq!(
+            config.pdf.standards,
+            [PdfStandard::A_2b, PdfStandard::V_1_7]
+        );
+        assert!(config.unknown_keys.is_empty(), "{:?}", config.unknown_keys);
+    }
+
+    #[test]
+    fn test_env_reports_variables() {
+        let env = env_layer(&[("WB_RENDER__LAZY_THRESHOLD", "many")]);
+        let err = load(PathBuf::from("/nonexistent/config.toml"), env).unwrap_err();
+        assert!(err.contains("WB_RENDER__LAZY_THRESHOLD"), "{err}");
+
+        let env = env_layer(&[("WB_SITE__HOMEE", "0008")]);
+        let config = load(PathBuf::from("/nonexistent/config.toml"), env).unwrap();
+        assert_eq!(
+            config.unknown_keys,
+            [
+                "unknown key `site.homee` set by environment variable WB_SITE__HOMEE; did you mean `site.home`?"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layers_use_the_loaded_configuration() {
+        let env = env_layer(&[("WB_SITE__DOMAIN", "example.org")]);
+        let config = load(PathBuf::from("/nonexistent/config.toml"), env).unwrap();
+        let figment = layers(&CompileArgs::default(), None, &config);
+        assert!(is_from_env(&figment, "site.domain"));
+        assert_eq!(
+            figment.extract_inner::<String>("site.domain").unwrap(),
+            "example.org"
+        );
+    }
+
+    #[test]
+    fn test_layers_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[files]
+output_dir = "file-dist"
+public_dir = "file-public"
+
+[site]
+domain = "file.org"
+root_dir = "/file/"
+home = "file"
+trailing_slash = true
+
+[profile.public]
+output_dir = "profile-dist"
+domain = "profile.org"
+root_dir = "/profile/"
+"#,
+        )
+        .unwrap();
+        let env = env_layer(&[
+            ("WB_SITE__DOMAIN", "env.org"),
+            ("WB_SITE__ROOT_DIR", "/env/"),
+            ("WB_SITE__HOME", "env"),
+        ]);
+        let config = load(path, env).unwrap();
+        let mut args = CompileArgs::default();
+        args.sources.profile = Some("public".into());
+        args.site.domain = Some("cli.org".into());
+        let profile = selected_profile(&args, &config).unwrap();
+
+        let figment = layers(&args, profile, &config);
+        let resolved = figment.extract_lossy::<WeibianConfig>().unwrap();
+        let source = |key: &str| figment.find_metadata(key).unwrap().name.to_string();
+        // Set in the file only.
+        assert_eq!(
+            resolved.files.public_dir,
+            Some(PathBuf::from("file-public"))
+        );
+        assert_eq!(resolved.site.trailing_slash, Some(true));
+        // The profile overrides the file.
+        assert_eq!(
+            resolved.files.output_dir,
+            Some(PathBuf::from("profile-dist"))
+        );
+        assert_eq!(source("files.output_dir"), "profile public");
+        // The environment overrides the file and the profile.
+        assert_eq!(resolved.site.home.as_deref(), Some("env"));
+        assert_eq!(resolved.site.root_dir.as_deref(), Some("/env/"));
+        assert_eq!(source("site.root_dir"), ENV_NAME);
+        // The command line overrides everything.
+        assert_eq!(resolved.site.domain.as_deref(), Some("cli.org"));
+        assert_eq!(source("site.domain"), "command line");
+    }
+
+    #[test]
+    fn test_check_domain_accepts_hosts() {
+        for domain in [
+            "example.com",
+            "https://example.com",
+            "http://example.com/",
+            "localhost:8080",
+            "https://sub-1.example.org:443",
+        ] {
+            assert_eq!(check_domain(domain), Ok(()), "{domain}");
+        }
+    }
+
+    #[test]
+    fn test_check_domain_rejects_malformed() {
+        let cases = [
+            (
+                "ftp://example.com",
+                "the scheme must be http or https, not ftp",
+            ),
+            ("example.com/blog", "must not contain a path"),
+            ("example.com:http", "\"http\" is not a port"),
+            ("example.com:70000", "\"70000\" is not a port"),
+            ("", "the host must be labels"),
+            ("-example.com", "the host must be labels"),
+            ("example..com", "the host must be labels"),
+            ("exa_mple.com", "the host must be labels"),
+        ];
+        for (domain, reason) in cases {
+            let err = check_domain(domain).unwrap_err();
+            assert!(err.contains(reason), "{domain}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_check_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path();
+        std::fs::create_dir_all(input.join("notes")).unwrap();
+        std::fs::write(input.join("notes/0001.typ"), "").unwrap();
+
+        let problems = check_globs(
+            input,
+            &["notes/*.typ".into(), "drafts/*.typ".into(), "[".into()],
+            &["{".into()],
+        );
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].starts_with("invalid include glob \"[\""));
+        assert!(problems[1].starts_with("invalid exclude glob \"{\""));
+        assert!(problems[2].starts_with("include glob \"drafts/*.typ\" matches no file"));
+    }
+
+    #[test]
+    fn test_check_globs_without_input() {
+        let input = Path::new("/nonexistent/weibian");
+        assert!(check_globs(input, &["*.typ".into()], &[]).is_empty());
+    }
+}
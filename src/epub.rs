@@ -0,0 +1,593 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use ecow::eco_format;
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+use crate::backend::{Chapter, build_absolute_url, escape_attr, escape_text};
+use crate::book::Book;
+use crate::config::SiteSettings;
+use crate::error::StrResult;
+use crate::permalink::Permalinks;
+
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+/// Everything packaged into an EPUB besides the chapters.
+pub struct EpubOptions<'a> {
+    pub site: &'a SiteSettings,
+    pub permalinks: &'a Permalinks,
+    pub public_dir: &'a Path,
+    pub modified: DateTime<Utc>,
+}
+
+/// Writes the chapters of a book as an EPUB 3 file.
+///
+/// The chapters keep the markup rendered by the backend. Links to notes with
+/// a chapter are rewritten to point to the chapter, links to other notes and
+/// files point to the site, and the files of the public directory that the
+/// chapters can use (stylesheets, images and fonts) are embedded.
+pub fn write_epub(
+    output_path: &Path,
+    book: &Book,
+    chapters: &[Chapter],
+    options: &EpubOptions,
+) -> StrResult<()> {
+    let root = chapters
+        .iter()
+        .find(|chapter| chapter.id == book.root_id)
+        .ok_or_else(|| eco_format!("missing chapter for {}", book.root_id))?;
+    let lang = root.lang.as_deref().unwrap_or("en");
+
+    let assets = collect_assets(options.public_dir)?;
+    let files = chapter_files(chapters)?;
+    let links = LinkRewriter {
+        chapters: chapters
+            .iter()
+            .map(|chapter| {
+                (
+                    options.permalinks.href(&chapter.id, options.site),
+                    files[chapter.id.as_str()].clone(),
+                )
+            })
+            .collect(),
+        assets: &assets,
+        site: options.site,
+    };
+    let stylesheets: Vec<&str> = assets
+        .iter()
+        .filter(|(_, media_type)| **media_type == "text/css")
+        .map(|(path, _)| path.as_str())
+        .collect();
+
+    let file = fs::File::create(output_path).map_err(|err| {
+        eco_format!(
+            "failed to create epub file {}: {err}",
+            output_path.display()
+        )
+    })?;
+    let mut zip = ZipWriter::new(file);
+    // The mimetype must come first and be stored uncompressed.
+    add_file(
+        &mut zip,
+        "mimetype",
+        b"application/epub+zip",
+        CompressionMethod::Stored,
+    )?;
+    add_file(
+        &mut zip,
+        "META-INF/container.xml",
+        CONTAINER_XML.as_bytes(),
+        CompressionMethod::Deflated,
+    )?;
+
+    for chapter in chapters {
+        let xhtml = chapter_xhtml(chapter, &links, &stylesheets)?;
+        add_file(
+            &mut zip,
+            &format!("OEBPS/{}", files[chapter.id.as_str()]),
+            xhtml.as_bytes(),
+            CompressionMethod::Deflated,
+        )?;
+    }
+    for path in assets.keys() {
+        let contents = fs::read(options.public_dir.join(path))
+            .map_err(|err| eco_format!("failed to read asset {path}: {err}"))?;
+        add_file(
+            &mut zip,
+            &format!("OEBPS/public/{path}"),
+            &contents,
+            CompressionMethod::Deflated,
+        )?;
+    }
+
+    let titles: HashMap<&str, &str> = chapters
+        .iter()
+        .map(|chapter| (chapter.id.as_str(), chapter.title.as_str()))
+        .collect();
+    add_file(
+        &mut zip,
+        "OEBPS/nav.xhtml",
+        nav_xhtml(book, &titles, &files, lang).as_bytes(),
+        CompressionMethod::Deflated,
+    )?;
+    let package = package_opf(book, chapters, &files, &assets, root, lang, options);
+    add_file(
+        &mut zip,
+        "OEBPS/content.opf",
+        package.as_bytes(),
+        CompressionMethod::Deflated,
+    )?;
+
+    zip.finish()
+        .map_err(|err| eco_format!("failed to write epub file {}: {err}", output_path.display()))?;
+    Ok(())
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn add_file<W: Write + std::io::Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    contents: &[u8],
+    compression: CompressionMethod,
+) -> StrResult<()> {
+    let options = SimpleFileOptions::default().compression_method(compression);
+    zip.start_file(name, options)
+        .map_err(|err| eco_format!("failed to add {name} to epub: {err}"))?;
+    zip.write_all(contents)
+        .map_err(|err| eco_format!("failed to add {name} to epub: {err}"))
+}
+
+/// The file names of the chapters, relative to the package document, by the
+/// ID of their note. They are numbered in reading order, as note IDs can
+/// contain characters that are not safe in file names.
+fn chapter_files(chapters: &[Chapter]) -> StrResult<HashMap<&str, String>> {
+    let mut files = HashMap::with_capacity(chapters.len());
+    for (index, chapter) in chapters.iter().enumerate() {
+        let file = format!("chapter-{index}.xhtml");
+        if files.insert(chapter.id.as_str(), file).is_some() {
+            return Err(eco_format!("note {} has two chapters", chapter.id));
+        }
+    }
+    Ok(files)
+}
+
+/// Collects the files of the public directory that can be embedded, keyed by
+/// their path relative to it.
+fn collect_assets(public_dir: &Path) -> StrResult<BTreeMap<String, &'static str>> {
+    let mut assets = BTreeMap::new();
+    if !public_dir.exists() {
+        return Ok(assets);
+    }
+    let mut stack = vec![public_dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let entries = fs::read_dir(&dir)
+            .map_err(|err| eco_format!("failed to read directory {}: {err}", dir.display()))?;
+        for entry in entries {
+            let entry =
+                entry.map_err(|err| eco_format!("failed to read directory entry: {err}"))?;
+            let path = entry.path();
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            let Some(media_type) = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(asset_media_type)
+            else {
+                continue;
+            };
+            let relative = path.strip_prefix(public_dir).unwrap_or(&path);
+            let relative = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            assets.insert(relative, media_type);
+        }
+    }
+    Ok(assets)
+}
+
+/// The media type of the files embedded into an EPUB. Scripts are left out,
+/// since e-readers rarely run them.
+fn asset_media_type(extension: &str) -> Option<&'static str> {
+    Some(match extension.to_ascii_lowercase().as_str() {
+        "css" => "text/css",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => return None,
+    })
+}
+
+struct LinkRewriter<'a> {
+    /// The site URL of every note with a chapter, and the chapter file.
+    chapters: HashMap<String, String>,
+    assets: &'a BTreeMap<String, &'static str>,
+    site: &'a SiteSettings,
+}
+
+impl LinkRewriter<'_> {
+    fn rewrite(&self, value: &str) -> String {
+        let (path, fragment) = match value.find('#') {
+            Some(index) => value.split_at(index),
+            None => (value, ""),
+        };
+        if path.is_empty() {
+            return value.to_string();
+        }
+        if let Some(chapter) = self.chapters.get(path) {
+            return format!("{chapter}{fragment}");
+        }
+        let Some(relative) = path.strip_prefix(self.site.root_dir.as_str()) else {
+            return value.to_string();
+        };
+        if self.assets.contains_key(relative) {
+            return format!("public/{relative}{fragment}");
+        }
+        build_absolute_url(value, self.site)
+    }
+}
+
+fn chapter_xhtml(
+    chapter: &Chapter,
+    links: &LinkRewriter,
+    stylesheets: &[&str],
+) -> StrResult<String> {
+    let lang = escape_attr(chapter.lang.as_deref().unwrap_or("en"));
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n");
+    out.push_str(&format!(
+        "<html xmlns=\"{XHTML_NS}\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{lang}\" xml:lang=\"{lang}\">\n"
+    ));
+    out.push_str("<head>\n<meta charset=\"UTF-8\"/>\n");
+    out.push_str(&format!("<title>{}</title>\n", escape_text(&chapter.title)));
+    for stylesheet in stylesheets {
+        out.push_str(&format!(
+            "<link rel=\"stylesheet\" type=\"text/css\" href=\"public/{}\"/>\n",
+            escape_attr(stylesheet)
+        ));
+    }
+    out.push_str("</head>\n<body>\n<article>\n");
+    let fragment = Html::parse_fragment(&chapter.body_html);
+    for child in fragment.root_element().children() {
+        write_xhtml(child, links, &mut out);
+    }
+    out.push_str("\n</article>\n</body>\n</html>\n");
+    Ok(out)
+}
+
+/// Serializes rendered HTML as XHTML.
+fn write_xhtml(node: NodeRef<Node>, links: &LinkRewriter, out: &mut String) {
+    match node.value() {
+        Node::Text(text) => out.push_str(&escape_text(text)),
+        Node::Element(element) => {
+            let name = element.name();
+            if name == "script" {
+                return;
+            }
+            // Collapsible blocks are shown expanded, as few e-readers support
+            // them.
+            let tag = match name {
+                "details" | "summary" => "div",
+                _ => name,
+            };
+            out.push('<');
+            out.push_str(tag);
+            let namespace = &*element.name.ns;
+            let parent_namespace = node
+                .parent()
+                .and_then(|parent| parent.value().as_element().map(|parent| &*parent.name.ns));
+            if (namespace == SVG_NS || namespace == MATHML_NS)
+                && parent_namespace != Some(namespace)
+            {
+                out.push_str(&format!(" xmlns=\"{namespace}\""));
+            }
+            let mut declared = Vec::new();
+            let mut used = Vec::new();
+            for (attr, value) in &element.attrs {
+                let (prefix, local) = match &attr.prefix {
+                    Some(prefix) => (Some(&**prefix), &*attr.local),
+                    None => match attr.local.split_once(':') {
+                        Some((prefix, local)) => (Some(prefix), local),
+                        None => (None, &*attr.local),
+                    },
+                };
+                let name = match prefix {
+                    // The namespace of the element is declared above.
+                    None if local == "xmlns" => continue,
+                    None => local.to_string(),
+                    Some("xmlns") => {
+                        declared.push(local);
+                        format!("xmlns:{local}")
+                    }
+                    Some(prefix) if prefix_namespace(prefix).is_some() => {
+                        used.push(prefix);
+                        format!("{prefix}:{local}")
+                    }
+                    // Undeclared prefixes would make the chapter ill-formed.
+                    Some(_) => continue,
+                };
+                let value = match local {
+                    "href" | "src" => links.rewrite(value),
+                    _ => value.to_string(),
+                };
+                out.push_str(&format!(" {name}=\"{}\"", escape_attr(&value)));
+            }
+            used.retain(|prefix| !declared.contains(prefix));
+            used.sort_unstable();
+            used.dedup();
+            for prefix in used {
+                if let Some(Some(namespace)) = prefix_namespace(prefix) {
+                    out.push_str(&format!(" xmlns:{prefix}=\"{namespace}\""));
+                }
+            }
+            if node.children().next().is_none() && is_void_element(name) {
+                out.push_str("/>");
+                return;
+            }
+            out.push('>');
+            for child in node.children() {
+                write_xhtml(child, links, out);
+            }
+            out.push_str(&format!("</{tag}>"));
+        }
+        _ => {
+            for child in node.children() {
+                write_xhtml(child, links, out);
+            }
+        }
+    }
+}
+
+/// The namespace of an attribute prefix chapters can use, if it is not
+/// declared on the root element or predefined.
+fn prefix_namespace(prefix: &str) -> Option<Option<&'static str>> {
+    match prefix {
+        "xlink" => Some(Some(XLINK_NS)),
+        "xml" | "epub" => Some(None),
+        _ => None,
+    }
+}
+
+fn nav_xhtml(
+    book: &Book,
+    titles: &HashMap<&str, &str>,
+    files: &HashMap<&str, String>,
+    lang: &str,
+) -> String {
+    let lang = escape_attr(lang);
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n");
+    out.push_str(&format!(
+        "<html xmlns=\"{XHTML_NS}\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{lang}\" xml:lang=\"{lang}\">\n"
+    ));
+    out.push_str("<head>\n<meta charset=\"UTF-8\"/>\n<title>Table of Contents</title>\n</head>\n");
+    out.push_str("<body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>Table of Contents</h1>\n<ol>\n");
+    write_nav_item(book, &book.root_id, titles, files, &mut out);
+    out.push_str("</ol>\n</nav>\n</body>\n</html>\n");
+    out
+}
+
+fn write_nav_item(
+    book: &Book,
+    id: &str,
+    titles: &HashMap<&str, &str>,
+    files: &HashMap<&str, String>,
+    out: &mut String,
+) {
+    let title = titles.get(id).copied().unwrap_or(id);
+    let file = files.get(id).map(String::as_str).unwrap_or_default();
+    out.push_str(&format!(
+        "<li><a href=\"{}\">{}</a>",
+        escape_attr(file),
+        escape_text(title)
+    ));
+    if let Some(children) = book.children.get(id) {
+        out.push_str("\n<ol>\n");
+        for child in children {
+            write_nav_item(book, child, titles, files, out);
+        }
+        out.push_str("</ol>\n");
+    }
+    out.push_str("</li>\n");
+}
+
+fn package_opf(
+    book: &Book,
+    chapters: &[Chapter],
+    files: &HashMap<&str, String>,
+    assets: &BTreeMap<String, &'static str>,
+    root: &Chapter,
+    lang: &str,
+    options: &EpubOptions,
+) -> String {
+    let identifier = match &options.site.domain {
+        Some(domain) if !domain.is_empty() => build_absolute_url(
+            &options.permalinks.href(&book.root_id, options.site),
+            options.site,
+        ),
+        _ => format!("urn:weibian:{}", book.root_id),
+    };
+    let modified = options.modified.format("%Y-%m-%dT%H:%M:%SZ");
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{}\">\n",
+        escape_attr(lang)
+    ));
+    out.push_str("<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+    out.push_str(&format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n",
+        escape_text(&identifier)
+    ));
+    out.push_str(&format!(
+        "<dc:title>{}</dc:title>\n",
+        escape_text(&root.title)
+    ));
+    out.push_str(&format!(
+        "<dc:language>{}</dc:language>\n",
+        escape_text(lang)
+    ));
+    out.push_str(&format!(
+        "<meta property=\"dcterms:modified\">{modified}</meta>\n"
+    ));
+    out.push_str("</metadata>\n<manifest>\n");
+    out.push_str(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n",
+    );
+    for (index, chapter) in chapters.iter().enumerate() {
+        let properties = if chapter.body_html.contains("<svg") {
+            " properties=\"svg\""
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "<item id=\"chapter-{index}\" href=\"{}\" media-type=\"application/xhtml+xml\"{properties}/>\n",
+            escape_attr(&files[chapter.id.as_str()])
+        ));
+    }
+    for (index, (path, media_type)) in assets.iter().enumerate() {
+        out.push_str(&format!(
+            "<item id=\"asset-{index}\" href=\"public/{}\" media-type=\"{media_type}\"/>\n",
+            escape_attr(path)
+        ));
+    }
+    out.push_str("</manifest>\n<spine>\n");
+    for index in 0..chapters.len() {
+        out.push_str(&format!("<itemref idref=\"chapter-{index}\"/>\n"));
+    }
+    out.push_str("</spine>\n</package>\n");
+    out
+}
+
+fn is_void_element(tag: &str) -> bool {
+    matches!(
+        tag,
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    fn chapter(id: &str, title: &str) -> Chapter {
+        Chapter {
+            id: id.to_string(),
+            title: title.to_string(),
+            lang: Some("en".to_string()),
+            body_html: format!("<p>{title}</p>"),
+        }
+    }
+
+    #[test]
+    fn test_write_epub_packages_chapters_in_reading_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let public_dir = dir.path().join("public");
+        fs::create_dir(&public_dir).unwrap();
+        fs::write(public_dir.join("style.css"), "p {}").unwrap();
+        let book = Book {
+            root_id: "root".to_string(),
+            ids: vec!["root".into(), "b".into(), "a".into()],
+            children: HashMap::from([("root".to_string(), vec!["b".into(), "a".into()])]),
+        };
+        let chapters = [
+            chapter("root", "Root"),
+            chapter("b", "Bee"),
+            chapter("a", "Ay"),
+        ];
+        let site = SiteSettings {
+            domain: None,
+            root_dir: "/".into(),
+            trailing_slash: false,
+            permalink: None,
+            home: "index".into(),
+            not_found: false,
+        };
+        let options = EpubOptions {
+            site: &site,
+            permalinks: &Permalinks::default(),
+            public_dir: &public_dir,
+            modified: DateTime::from_timestamp(0, 0).unwrap(),
+        };
+        let output_path = dir.path().join("root.epub");
+        write_epub(&output_path, &book, &chapters, &options).unwrap();
+
+        let mut archive = ZipArchive::new(fs::File::open(&output_path).unwrap()).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert!(names.contains(&"OEBPS/public/style.css"), "{names:?}");
+        let mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        drop(mimetype);
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+
+        assert!(read("OEBPS/chapter-1.xhtml").contains("<p>Bee</p>"));
+        let package = read("OEBPS/content.opf");
+        assert!(package.contains("<dc:title>Root</dc:title>"));
+        assert!(package.contains("urn:weibian:root"));
+        assert!(package.contains(
+            "<itemref idref=\"chapter-0\"/>\n<itemref idref=\"chapter-1\"/>\n<itemref idref=\"chapter-2\"/>\n"
+        ));
+        let nav = read("OEBPS/nav.xhtml");
+        let position = |text: &str| nav.find(text).unwrap();
+        assert!(position(">Root<") < position(">Bee<"));
+        assert!(position(">Bee<") < position(">Ay<"));
+        assert!(nav.contains("<a href=\"chapter-2.xhtml\">Ay</a>"));
+    }
+
+    #[test]
+    fn test_chapter_files_reject_duplicates() {
+        let chapters = [chapter("a", "A"), chapter("a", "A")];
+        assert!(chapter_files(&chapters).is_err());
+    }
+}
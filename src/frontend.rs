@@ -6,9 +6,10 @@ use crate::error::StrResult;
 use ecow::eco_format;
 
 use crate::compiler::{
-    CompileArtifact, CompileOutput, CompileRequest, CompileTarget, TypstCompiler,
+    CompileArtifact, CompileInput, CompileOutput, CompileRequest, CompileTarget, TypstCompiler,
 };
-use crate::config::BuildConfig;
+use crate::config::{BuildConfig, InputFilters};
+use crate::generated::GeneratedData;
 use crate::html::HtmlNote;
 
 pub fn compile_html(
@@ -37,7 +38,7 @@ pub fn compile_html(
 
     notes.reserve(sources.len());
     for source in &sources {
-        let note = compile_source_to_html(build_config, compiler, source.as_path(), &[])?;
+        let note = compile_source_to_html(build_config, compiler, source.as_path(), &[], None)?;
         register_note(
             note,
             source.display().to_string(),
@@ -49,17 +50,42 @@ pub fn compile_html(
     Ok(notes)
 }
 
+/// Compiles notes again, with the data generated from a previous pass.
+pub fn recompile_html(
+    build_config: &BuildConfig,
+    compiler: &dyn TypstCompiler,
+    sources: &[&Path],
+    data: &GeneratedData,
+) -> StrResult<Vec<HtmlNote>> {
+    sources
+        .iter()
+        .map(|source| compile_source_to_html(build_config, compiler, source, &[], Some(data)))
+        .collect()
+}
+
+/// Compiles a single note.
+pub fn compile_note(
+    build_config: &BuildConfig,
+    compiler: &dyn TypstCompiler,
+    source: &Path,
+) -> StrResult<HtmlNote> {
+    compile_source_to_html(build_config, compiler, source, &[], None)
+}
+
 fn compile_source_to_html(
     build_config: &BuildConfig,
     compiler: &dyn TypstCompiler,
     source: &Path,
     additional_inputs: &[(&str, &str)],
+    data: Option<&GeneratedData>,
 ) -> StrResult<HtmlNote> {
     let request = CompileRequest {
-        source,
+        source: CompileInput::File(source),
         target: CompileTarget::Html,
         output: CompileOutput::Stdout,
         additional_inputs,
+        pdf_standards: &[],
+        data,
     };
     let html = match compiler.compile(build_config, &request)? {
         CompileArtifact::Stdout(stdout) => String::from_utf8(stdout).map_err(|err| {
@@ -97,10 +123,14 @@ fn register_note(
     Ok(())
 }
 
-fn collect_typst_sources(build_config: &BuildConfig) -> StrResult<Vec<PathBuf>> {
-    let input_dir = &build_config.input_directory;
+pub fn collect_typst_sources(build_config: &BuildConfig) -> StrResult<Vec<PathBuf>> {
+    collect_sources(&build_config.input_directory, &build_config.input_filters)
+}
+
+/// The `.typ` files in an input directory that pass the filters.
+pub fn collect_sources(input_dir: &Path, filters: &InputFilters) -> StrResult<Vec<PathBuf>> {
     let mut sources = Vec::new();
-    let mut stack = vec![input_dir.clone()];
+    let mut stack = vec![input_dir.to_path_buf()];
 
     while let Some(dir) = stack.pop() {
         let entries = fs::read_dir(&dir).map_err(|err| {
@@ -129,7 +159,7 @@ fn collect_typst_sources(build_config: &BuildConfig) -> StrResult<Vec<PathBuf>>
             }
 
             let relative = path.strip_prefix(input_dir).unwrap_or(&path);
-            if !build_config.input_filters.allows(relative) {
+            if !filters.allows(relative) {
                 continue;
             }
 
@@ -139,3 +169,32 @@ fn collect_typst_sources(build_config: &BuildConfig) -> StrResult<Vec<PathBuf>>
 
     Ok(sources)
 }
+
+/// The ID in a file name of the form `@@<ID>==<taxon>--<slug>.typ`.
+pub fn file_name_id(name: &str) -> Option<&str> {
+    let rest = name.strip_prefix("@@")?;
+    let end = ["==", "--", ".typ"]
+        .iter()
+        .filter_map(|separator| rest.find(separator))
+        .min()?;
+    Some(&rest[..end])
+}
+
+/// The values of the `identifier` fields of a source.
+pub fn source_ids(source: &str) -> Vec<String> {
+    let mut ids = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("identifier") {
+        rest = &rest[start + "identifier".len()..];
+        let Some(value) = rest.trim_start().strip_prefix(':') else {
+            continue;
+        };
+        let Some(value) = value.trim_start().strip_prefix('"') else {
+            continue;
+        };
+        if let Some(end) = value.find('"') {
+            ids.push(value[..end].to_string());
+        }
+    }
+    ids
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ecow::eco_format;
+
+use crate::config::BuildConfig;
+use crate::error::StrResult;
+
+/// The namespace of the data package.
+const NAMESPACE: &str = "weibian";
+/// The package Typst documents import to read the generated data.
+pub const DATA_PACKAGE: &str = "@weibian/data:0.1.0";
+const DATA_PACKAGE_MANIFEST: &str = r#"[package]
+name = "data"
+version = "0.1.0"
+entrypoint = "lib.typ"
+"#;
+const DATA_PACKAGE_ENTRYPOINT: &str = r#"#let data = (
+  id-filename: json("id-filename.json"),
+  permalinks: json("permalinks.json"),
+  notes: json("notes.json"),
+)
+"#;
+
+/// The data Weibian generates for the paged exports of a build: the source
+/// file and the href of every note, and the note index.
+///
+/// It is written as a package into a temporary directory of its own, which is
+/// used as the local package directory of the compiler, so that the input
+/// directory is never written to and concurrent builds do not share files.
+/// The packages of the usual local package directory, or of the one given
+/// with `--package-path`, are linked into it (or copied, where directories
+/// cannot be linked), so that `@local` packages keep working. The directory
+/// is removed when the value is dropped.
+#[derive(Debug)]
+pub struct GeneratedData {
+    root: PathBuf,
+    package_path: PathBuf,
+}
+
+/// The files of the data package.
+#[derive(Debug, PartialEq)]
+pub struct DataFiles {
+    pub id_filename: String,
+    pub permalinks: String,
+    pub notes: String,
+}
+
+impl GeneratedData {
+    pub fn create(build_config: &BuildConfig, files: &DataFiles) -> StrResult<Self> {
+        // Compiling in several passes needs several data directories.
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let root = std::env::temp_dir().join(format!("weibian-{}-{count}", process::id()));
+        if root.exists() {
+            fs::remove_dir_all(&root).map_err(|err| {
+                eco_format!(
+                    "failed to clear generated data directory {}: {err}",
+                    root.display()
+                )
+            })?;
+        }
+        let data = Self {
+            package_path: root.join("packages"),
+            root,
+        };
+
+        let package_dir = data.package_path.join(NAMESPACE).join("data").join("0.1.0");
+        fs::create_dir_all(&package_dir).map_err(|err| {
+            eco_format!(
+                "failed to create generated data directory {}: {err}",
+                package_dir.display()
+            )
+        })?;
+        for (name, contents) in [
+            ("typst.toml", DATA_PACKAGE_MANIFEST),
+            ("lib.typ", DATA_PACKAGE_ENTRYPOINT),
+            ("id-filename.json", files.id_filename.as_str()),
+            ("permalinks.json", files.permalinks.as_str()),
+            ("notes.json", files.notes.as_str()),
+        ] {
+            let path = package_dir.join(name);
+            fs::write(&path, contents).map_err(|err| {
+                eco_format!("failed to write generated data {}: {err}", path.display())
+            })?;
+        }
+
+        if let Some(local) = local_package_path(build_config)
+            && local.is_dir()
+        {
+            merge_dir(&local, &data.package_path)?;
+        }
+        Ok(data)
+    }
+
+    /// The local package directory to compile with.
+    pub fn package_path(&self) -> &Path {
+        &self.package_path
+    }
+}
+
+impl Drop for GeneratedData {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// The local package directory the compiler would use on its own: the
+/// configured one, or the `typst/packages` directory in the data directory
+/// of the system.
+fn local_package_path(build_config: &BuildConfig) -> Option<PathBuf> {
+    if let Some(path) = &build_config.world.package.package_path {
+        return Some(path.clone());
+    }
+    let env = |key: &str| std::env::var_os(key).filter(|value| !value.is_empty());
+    let data_dir = if cfg!(target_os = "windows") {
+        PathBuf::from(env("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env("HOME")?).join("Library/Application Support")
+    } else {
+        env("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(env("HOME")?).join(".local/share")))?
+    };
+    Some(data_dir.join("typst").join("packages"))
+}
+
+/// Links the entries of a directory into another one, descending into the
+/// directories both have, so that the generated package sits alongside the
+/// other packages of its namespace.
+fn merge_dir(from: &Path, into: &Path) -> StrResult<()> {
+    let entries = fs::read_dir(from)
+        .map_err(|err| eco_format!("failed to read local packages {}: {err}", from.display()))?;
+    for entry in entries.flatten() {
+        let (source, target) = (entry.path(), into.join(entry.file_name()));
+        if !source.is_dir() {
+            continue;
+        }
+        if target.join("typst.toml").exists() {
+            // The generated package shadows a local one of the same version.
+            continue;
+        } else if target.is_dir() {
+            merge_dir(&source, &target)?;
+        } else {
+            link_dir(&source, &target).map_err(|err| {
+                eco_format!(
+                    "failed to link local packages {} into {}: {err}",
+                    source.display(),
+                    target.display()
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Links a directory, or copies it where symbolic links cannot be created,
+/// e.g. on Windows without the privilege to.
+fn link_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    if symlink_dir(original, link).is_ok() {
+        return Ok(());
+    }
+    copy_dir(original, link)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{CompileArgs, SourceArgs};
+    use crate::config::WeibianConfig;
+
+    fn package(dir: &Path, name: &str) {
+        fs::create_dir_all(dir.join(name)).unwrap();
+        fs::write(dir.join(name).join("typst.toml"), name).unwrap();
+    }
+
+    #[test]
+    fn test_create_writes_the_package_next_to_local_ones() {
+        let root = tempfile::tempdir().unwrap();
+        let input = root.path().join("typ");
+        fs::create_dir(&input).unwrap();
+        let local = root.path().join("packages");
+        package(&local, "local/util/0.1.0");
+        package(&local, "weibian/other/1.0.0");
+        package(&local, "weibian/data/0.1.0");
+        let args = CompileArgs {
+            sources: SourceArgs {
+                input: Some(input),
+                ..Default::default()
+            },
+            output: Some(root.path().join("dist")),
+            ..Default::default()
+        };
+        let mut build_config = BuildConfig::from(&args, &WeibianConfig::default()).unwrap();
+        build_config.world.package.package_path = Some(local.clone());
+        let files = DataFiles {
+            id_filename: r#"{"0001":"/0001.typ"}"#.to_string(),
+            permalinks: r#"{"0001":"/0001.html"}"#.to_string(),
+            notes: r#"{"0001":{}}"#.to_string(),
+        };
+
+        let data = GeneratedData::create(&build_config, &files).unwrap();
+        let path = data.package_path().to_path_buf();
+        let read = |name: &str| fs::read_to_string(path.join(name)).unwrap();
+        assert_eq!(read("weibian/data/0.1.0/typst.toml"), DATA_PACKAGE_MANIFEST);
+        assert_eq!(read("weibian/data/0.1.0/lib.typ"), DATA_PACKAGE_ENTRYPOINT);
+        assert_eq!(
+            read("weibian/data/0.1.0/id-filename.json"),
+            files.id_filename
+        );
+        assert_eq!(read("weibian/data/0.1.0/permalinks.json"), files.permalinks);
+        assert_eq!(read("weibian/data/0.1.0/notes.json"), files.notes);
+        assert_eq!(
+            read("weibian/other/1.0.0/typst.toml"),
+            "weibian/other/1.0.0"
+        );
+        assert_eq!(read("local/util/0.1.0/typst.toml"), "local/util/0.1.0");
+        // The local packages are linked, not written to.
+        assert_eq!(
+            fs::read_to_string(local.join("weibian/data/0.1.0/typst.toml")).unwrap(),
+            "weibian/data/0.1.0"
+        );
+
+        drop(data);
+        assert!(!path.exists());
+        assert!(local.join("weibian/other/1.0.0/typst.toml").exists());
+    }
+}
@@ -0,0 +1,421 @@
+use scraper::ElementRef;
+use serde::Serialize;
+
+use crate::backend::{escape_attr, escape_text};
+
+/// The `<head>` of an output page.
+///
+/// It is collected from the head Typst emitted for the note, and completed
+/// with the tags every page needs. A tag is only kept once: for `<title>`,
+/// the charset, and `<meta>` tags with the same `name`, `property` or
+/// `http-equiv`, the first one wins, so that tags set in the Typst document
+/// take precedence over the ones generated from the metadata.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PageHead {
+    pub charset: String,
+    pub title: Option<String>,
+    /// The absolute URL of the page, or its root-absolute href if the site
+    /// has no domain.
+    pub canonical: Option<String>,
+    /// The `<meta>` tags with a `name` or `http-equiv`.
+    pub meta: Vec<MetaTag>,
+    /// The `<meta>` tags with a `property`, i.e., the Open Graph tags.
+    pub properties: Vec<MetaTag>,
+    /// The translations of the page, including itself.
+    pub alternates: Vec<Alternate>,
+    /// Every other element, serialized.
+    pub extra: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetaTag {
+    /// The attribute naming the tag: `name`, `http-equiv` or `property`.
+    pub attribute: &'static str,
+    pub key: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alternate {
+    pub hreflang: String,
+    pub href: String,
+}
+
+/// What the generated tags of a page are built from.
+pub struct HeadDefaults<'a> {
+    pub url: &'a str,
+    pub title: &'a str,
+    pub description: Option<&'a str>,
+    pub lang: Option<&'a str>,
+    pub image: Option<&'a str>,
+    /// The URLs of the translations of the page, keyed by language.
+    pub alternates: &'a [(String, String)],
+}
+
+impl PageHead {
+    /// Collects the children of a `<head>` element.
+    pub fn collect(head: ElementRef) -> Self {
+        let mut out = Self::default();
+        for child in head.children().filter_map(ElementRef::wrap) {
+            let element = child.value();
+            match element.name() {
+                "title" => {
+                    if out.title.is_none() {
+                        out.title = Some(child.text().collect());
+                    }
+                }
+                "meta" => {
+                    if let Some(charset) = element.attr("charset") {
+                        out.set_charset(charset);
+                        continue;
+                    }
+                    let content = element.attr("content").unwrap_or_default();
+                    if let Some(key) = element.attr("http-equiv") {
+                        // The content type only carries the charset.
+                        if key.eq_ignore_ascii_case("content-type") {
+                            if let Some((_, charset)) = content.split_once("charset=") {
+                                out.set_charset(charset.trim());
+                            }
+                        } else {
+                            out.set_meta("http-equiv", key, content);
+                        }
+                    } else if let Some(key) = element.attr("name") {
+                        out.set_meta("name", key, content);
+                    } else if let Some(key) = element.attr("property") {
+                        out.set_meta("property", key, content);
+                    } else {
+                        out.push_extra(child.html());
+                    }
+                }
+                "link" => {
+                    let rel = element.attr("rel").unwrap_or_default();
+                    let href = element.attr("href").unwrap_or_default();
+                    if rel.eq_ignore_ascii_case("canonical") {
+                        if out.canonical.is_none() {
+                            out.canonical = Some(href.to_string());
+                        }
+                    } else if rel.eq_ignore_ascii_case("alternate")
+                        && let Some(hreflang) = element.attr("hreflang")
+                    {
+                        out.set_alternate(hreflang, href);
+                    } else {
+                        out.push_extra(child.html());
+                    }
+                }
+                _ => out.push_extra(child.html()),
+            }
+        }
+        out
+    }
+
+    /// Adds the tags generated for a page, unless they are already set.
+    pub fn complete(&mut self, defaults: &HeadDefaults) {
+        self.set_charset("utf-8");
+        if self.title.is_none() {
+            self.title = Some(defaults.title.to_string());
+        }
+        if self.canonical.is_none() {
+            self.canonical = Some(defaults.url.to_string());
+        }
+        let url = self.canonical.clone().unwrap_or_default();
+        let title = self.title.clone().unwrap_or_default();
+
+        self.set_meta("name", "viewport", "width=device-width");
+        if let Some(description) = defaults.description {
+            self.set_meta("name", "description", description);
+        }
+        self.set_meta("property", "og:type", "article");
+        self.set_meta("property", "og:title", &title);
+        self.set_meta("property", "og:url", &url);
+        if let Some(description) = defaults.description {
+            self.set_meta("property", "og:description", description);
+        }
+        if let Some(image) = defaults.image {
+            self.set_meta("property", "og:image", image);
+        }
+        let card = if self.has_meta("og:image") {
+            "summary_large_image"
+        } else {
+            "summary"
+        };
+        self.set_meta("name", "twitter:card", card);
+        self.set_meta("name", "twitter:title", &title);
+        if let Some(description) = self.meta_content("og:description") {
+            self.set_meta("name", "twitter:description", &description);
+        }
+        if let Some(image) = self.meta_content("og:image") {
+            self.set_meta("name", "twitter:image", &image);
+        }
+
+        if !defaults.alternates.is_empty() {
+            if let Some(lang) = defaults.lang {
+                self.set_alternate(lang, &url);
+            }
+            for (hreflang, href) in defaults.alternates {
+                self.set_alternate(hreflang, href);
+            }
+        }
+    }
+
+    fn set_charset(&mut self, charset: &str) {
+        if self.charset.is_empty() {
+            self.charset = charset.to_ascii_lowercase();
+        }
+    }
+
+    fn has_meta(&self, key: &str) -> bool {
+        self.meta
+            .iter()
+            .chain(&self.properties)
+            .any(|tag| tag.key.eq_ignore_ascii_case(key))
+    }
+
+    fn meta_content(&self, key: &str) -> Option<String> {
+        self.meta
+            .iter()
+            .chain(&self.properties)
+            .find(|tag| tag.key.eq_ignore_ascii_case(key))
+            .map(|tag| tag.content.clone())
+    }
+
+    fn set_meta(&mut self, attribute: &'static str, key: &str, content: &str) {
+        if self.has_meta(key) {
+            return;
+        }
+        let tag = MetaTag {
+            attribute,
+            key: key.to_string(),
+            content: content.to_string(),
+        };
+        if attribute == "property" {
+            self.properties.push(tag);
+        } else {
+            self.meta.push(tag);
+        }
+    }
+
+    fn set_alternate(&mut self, hreflang: &str, href: &str) {
+        if self
+            .alternates
+            .iter()
+            .any(|alternate| alternate.hreflang.eq_ignore_ascii_case(hreflang))
+        {
+            return;
+        }
+        self.alternates.push(Alternate {
+            hreflang: hreflang.to_string(),
+            href: href.to_string(),
+        });
+    }
+
+    fn push_extra(&mut self, html: String) {
+        if !self.extra.contains(&html) {
+            self.extra.push(html);
+        }
+    }
+
+    /// Serializes the head, to be inserted into the `<head>` of a template as
+    /// is.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!(
+            "<meta charset=\"{}\">\n",
+            escape_attr(&self.charset)
+        ));
+        if let Some(title) = &self.title {
+            out.push_str(&format!("<title>{}</title>\n", escape_text(title)));
+        }
+        if let Some(canonical) = &self.canonical {
+            out.push_str(&format!(
+                "<link rel=\"canonical\" href=\"{}\">\n",
+                escape_attr(canonical)
+            ));
+        }
+        for tag in self.meta.iter().chain(&self.properties) {
+            out.push_str(&format!(
+                "<meta {}=\"{}\" content=\"{}\">\n",
+                tag.attribute,
+                escape_attr(&tag.key),
+                escape_attr(&tag.content)
+            ));
+        }
+        for alternate in &self.alternates {
+            out.push_str(&format!(
+                "<link rel=\"alternate\" hreflang=\"{}\" href=\"{}\">\n",
+                escape_attr(&alternate.hreflang),
+                escape_attr(&alternate.href)
+            ));
+        }
+        for extra in &self.extra {
+            out.push_str(extra);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::{Html, Selector};
+
+    use super::*;
+
+    fn collect(head: &str) -> PageHead {
+        let document =
+            Html::parse_document(&format!("<html><head>{head}</head><body></body></html>"));
+        let selector = Selector::parse("head").unwrap();
+        PageHead::collect(document.select(&selector).next().unwrap())
+    }
+
+    fn defaults<'a>(alternates: &'a [(String, String)]) -> HeadDefaults<'a> {
+        HeadDefaults {
+            url: "https://example.org/0001/",
+            title: "Generated",
+            description: None,
+            lang: None,
+            image: None,
+            alternates,
+        }
+    }
+
+    #[test]
+    fn test_collect_drops_duplicates() {
+        let head = collect(
+            "<meta charset=\"UTF-8\"><meta http-equiv=\"Content-Type\" content=\"text/html; charset=latin1\">\
+             <title>First</title><title>Second</title>\
+             <meta name=\"author\" content=\"A\"><meta name=\"author\" content=\"B\">\
+             <meta property=\"og:title\" content=\"One\"><meta property=\"og:title\" content=\"Two\">\
+             <link rel=\"canonical\" href=\"/a\"><link rel=\"canonical\" href=\"/b\">\
+             <script src=\"x.js\"></script><script src=\"x.js\"></script>",
+        );
+        assert_eq!(head.charset, "utf-8");
+        assert_eq!(head.title.as_deref(), Some("First"));
+        assert_eq!(head.meta.len(), 1);
+        assert_eq!(head.meta_content("author").as_deref(), Some("A"));
+        assert_eq!(head.properties.len(), 1);
+        assert_eq!(head.meta_content("og:title").as_deref(), Some("One"));
+        assert_eq!(head.canonical.as_deref(), Some("/a"));
+        assert_eq!(head.extra, ["<script src=\"x.js\"></script>"]);
+    }
+
+    #[test]
+    fn test_charset_from_content_type() {
+        let head =
+            collect("<meta http-equiv=\"Content-Type\" content=\"text/html; charset=UTF-8\">");
+        assert_eq!(head.charset, "utf-8");
+        assert!(head.meta.is_empty());
+    }
+
+    #[test]
+    fn test_complete_injects_canonical_and_title() {
+        let mut head = collect("");
+        head.complete(&defaults(&[]));
+        assert_eq!(head.charset, "utf-8");
+        assert_eq!(head.title.as_deref(), Some("Generated"));
+        assert_eq!(head.canonical.as_deref(), Some("https://example.org/0001/"));
+        assert_eq!(
+            head.meta_content("og:url").as_deref(),
+            Some("https://example.org/0001/")
+        );
+        assert_eq!(
+            head.meta_content("twitter:card").as_deref(),
+            Some("summary")
+        );
+        assert!(
+            head.render()
+                .contains("<link rel=\"canonical\" href=\"https://example.org/0001/\">")
+        );
+
+        // Tags of the Typst document take precedence.
+        let mut head =
+            collect("<title>Own</title><link rel=\"canonical\" href=\"https://example.org/own/\">");
+        head.complete(&defaults(&[]));
+        assert_eq!(head.title.as_deref(), Some("Own"));
+        assert_eq!(head.canonical.as_deref(), Some("https://example.org/own/"));
+        assert_eq!(head.meta_content("og:title").as_deref(), Some("Own"));
+        assert_eq!(
+            head.meta_content("og:url").as_deref(),
+            Some("https://example.org/own/")
+        );
+    }
+
+    #[test]
+    fn test_complete_adds_open_graph_and_twitter_tags() {
+        let mut head = collect("<meta property=\"og:type\" content=\"website\">");
+        head.complete(&HeadDefaults {
+            description: Some("About <notes>"),
+            image: Some("https://example.org/og/0001.png"),
+            ..defaults(&[])
+        });
+        assert_eq!(head.meta_content("og:type").as_deref(), Some("website"));
+        assert_eq!(head.meta_content("og:title").as_deref(), Some("Generated"));
+        assert_eq!(
+            head.meta_content("description").as_deref(),
+            Some("About <notes>")
+        );
+        assert_eq!(
+            head.meta_content("og:description").as_deref(),
+            Some("About <notes>")
+        );
+        assert_eq!(
+            head.meta_content("og:image").as_deref(),
+            Some("https://example.org/og/0001.png")
+        );
+        assert_eq!(
+            head.meta_content("twitter:card").as_deref(),
+            Some("summary_large_image")
+        );
+        assert_eq!(
+            head.meta_content("twitter:title").as_deref(),
+            Some("Generated")
+        );
+        assert_eq!(
+            head.meta_content("twitter:description").as_deref(),
+            Some("About <notes>")
+        );
+        assert_eq!(
+            head.meta_content("twitter:image").as_deref(),
+            Some("https://example.org/og/0001.png")
+        );
+        assert!(
+            head.render()
+                .contains("<meta name=\"description\" content=\"About &lt;notes&gt;\">")
+        );
+    }
+
+    #[test]
+    fn test_complete_adds_hreflang_alternates() {
+        let alternates = [
+            ("zh".to_string(), "https://example.org/0001-zh/".to_string()),
+            ("fr".to_string(), "https://example.org/0001-fr/".to_string()),
+        ];
+        let mut head = collect(
+            "<link rel=\"alternate\" hreflang=\"fr\" href=\"https://fr.example.org/0001/\">",
+        );
+        head.complete(&HeadDefaults {
+            lang: Some("en"),
+            ..defaults(&alternates)
+        });
+        let alternates: Vec<(&str, &str)> = head
+            .alternates
+            .iter()
+            .map(|alternate| (alternate.hreflang.as_str(), alternate.href.as_str()))
+            .collect();
+        assert_eq!(
+            alternates,
+            [
+                ("fr", "https://fr.example.org/0001/"),
+                ("en", "https://example.org/0001/"),
+                ("zh", "https://example.org/0001-zh/"),
+            ]
+        );
+
+        // A page without translations does not list itself.
+        let mut head = collect("");
+        head.complete(&HeadDefaults {
+            lang: Some("en"),
+            ..defaults(&[])
+        });
+        assert!(head.alternates.is_empty());
+    }
+}
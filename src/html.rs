@@ -6,6 +6,7 @@ use ego_tree::{NodeId, NodeRef};
 use html5ever::{LocalName, Namespace, QualName};
 use scraper::{Html, Node, Selector};
 
+use crate::config::TransclusionMode;
 use crate::error::StrResult;
 
 pub struct HtmlNote {
@@ -41,6 +42,7 @@ pub fn extract_note_id(document: &Html, path: &Path) -> StrResult<String> {
         if (name == "id" || name == "identifier" || name == "wb-id")
             && let Some(content) = element.value().attr("content")
         {
+            validate_note_id(content).map_err(|err| eco_format!("{err} in {}", path.display()))?;
             return Ok(content.to_string());
         }
     }
@@ -51,6 +53,75 @@ pub fn extract_note_id(document: &Html, path: &Path) -> StrResult<String> {
     ))
 }
 
+/// Checks that a note ID can be used as a path relative to the output
+/// directory.
+///
+/// An ID is one or more segments separated by `/`, e.g. `0001` or
+/// `math/0001`; the segments before the last one are namespaces, which map
+/// to subdirectories of the output. A segment is made of ASCII letters,
+/// digits, `-`, `_` and `.`, and does not start with `.`, so that IDs
+/// cannot escape the output directory or name hidden files.
+pub fn validate_note_id(id: &str) -> StrResult<()> {
+    if id.is_empty() {
+        return Err(eco_format!("invalid note ID: the ID is empty"));
+    }
+    for segment in id.split('/') {
+        if segment.is_empty() {
+            return Err(eco_format!(
+                "invalid note ID \"{id}\": namespaces must be separated by a single \"/\", without a leading or trailing one"
+            ));
+        }
+        if segment == "." || segment == ".." {
+            return Err(eco_format!(
+                "invalid note ID \"{id}\": \".\" and \"..\" segments are not allowed"
+            ));
+        }
+        if segment.starts_with('.') {
+            return Err(eco_format!(
+                "invalid note ID \"{id}\": segment \"{segment}\" starts with \".\""
+            ));
+        }
+        if let Some(c) = segment
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        {
+            return Err(eco_format!(
+                "invalid note ID \"{id}\": {c:?} is not allowed; use ASCII letters, digits, \"-\", \"_\", \".\" and \"/\" between namespaces"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a character can be part of a note ID, namespaces included.
+pub fn is_note_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')
+}
+
+/// The notes a document links to, cites or transcludes, by the IDs it uses
+/// for them, without fragments.
+pub fn referenced_targets(document: &Html) -> Vec<String> {
+    document
+        .tree
+        .nodes()
+        .filter_map(|node| {
+            let Node::Element(element) = node.value() else {
+                return None;
+            };
+            let (attr, prefix) = if crate::permalink::TARGET_TAGS
+                .iter()
+                .any(|tag| element.name().eq_ignore_ascii_case(tag))
+            {
+                ("target", "")
+            } else {
+                ("href", "wb:")
+            };
+            let rest = element.attr(attr)?.trim().strip_prefix(prefix)?;
+            Some(normalize_target(rest.split('#').next().unwrap_or(rest)))
+        })
+        .collect()
+}
+
 pub fn extract_metadata(document: &Html) -> StrResult<HashMap<String, String>> {
     let selector = Selector::parse("head meta")
         .map_err(|err| eco_format!("failed to parse selector head meta: {err}"))?;
@@ -132,6 +203,17 @@ pub fn parse_non_negative_usize_attr(value: Option<&str>, default: usize) -> usi
     }
 }
 
+pub fn parse_transclusion_mode_attr(
+    value: Option<&str>,
+    default: TransclusionMode,
+) -> TransclusionMode {
+    match value.map(str::trim) {
+        Some(v) if v.eq_ignore_ascii_case("full") => TransclusionMode::Full,
+        Some(v) if v.eq_ignore_ascii_case("summary") => TransclusionMode::Summary,
+        _ => default,
+    }
+}
+
 pub fn normalize_target(raw: &str) -> String {
     let trimmed = raw.trim();
     let normalized = trimmed.strip_prefix("wb:").unwrap_or(trimmed).trim();
@@ -151,7 +233,6 @@ pub fn find_first_element(root: NodeRef<Node>) -> Option<NodeId> {
     None
 }
 
-#[allow(dead_code)]
 pub fn find_first_element_by_tag(root: NodeRef<Node>, tag: &str) -> Option<NodeId> {
     for node in root.descendants() {
         if let Some(element) = node.value().as_element()
@@ -198,3 +279,51 @@ pub fn add_class(class_value: &mut Option<String>, class: &str) {
     };
     *class_value = Some(updated);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_note_id_accepts_namespaces() {
+        for id in ["0001", "math/0001", "a/b/c-d_e.f", "v1.2"] {
+            assert!(validate_note_id(id).is_ok(), "{id}");
+        }
+    }
+
+    #[test]
+    fn test_validate_note_id_rejects_paths() {
+        let cases = [
+            ("", "empty"),
+            ("/0001", "single \"/\""),
+            ("math//0001", "single \"/\""),
+            ("math/", "single \"/\""),
+            ("../0001", "not allowed"),
+            ("math/.", "not allowed"),
+            (".hidden", "starts with \".\""),
+            ("a b", "' ' is not allowed"),
+            ("a\\b", "'\\\\' is not allowed"),
+            ("é", "'é' is not allowed"),
+        ];
+        for (id, message) in cases {
+            let err = validate_note_id(id).unwrap_err();
+            assert!(err.contains(message), "{id}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_extract_note_id_validates() {
+        let document = Html::parse_document(
+            "<html><head><meta name=\"identifier\" content=\"../x\"></head></html>",
+        );
+        let err = extract_note_id(&document, Path::new("typ/x.typ")).unwrap_err();
+        assert!(err.contains("in typ/x.typ"));
+        let document = Html::parse_document(
+            "<html><head><meta name=\"id\" content=\"math/0001\"></head></html>",
+        );
+        assert_eq!(
+            extract_note_id(&document, Path::new("typ/x.typ")).unwrap(),
+            "math/0001"
+        );
+    }
+}
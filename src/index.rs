@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::backend::build_absolute_url;
+use crate::config::SiteSettings;
+use crate::error::StrResult;
+use crate::html::{HtmlNote, collect_targets, extract_metadata, extract_note_title};
+use crate::permalink::Permalinks;
+
+/// What Typst documents know about a note through the generated data.
+#[derive(Debug, Serialize)]
+pub struct IndexedNote {
+    pub id: String,
+    /// The plain-text title, if the note has one.
+    pub title: Option<String>,
+    pub taxon: Option<String>,
+    /// The root-absolute href of the page of the note.
+    pub href: String,
+    /// The absolute URL of the page, or its href if the site has no domain.
+    pub url: String,
+    /// The root-absolute path of the source file.
+    pub source: Option<String>,
+    /// The notes linking to this one.
+    pub backlinks: Vec<String>,
+    /// The notes transcluding this one.
+    pub contexts: Vec<String>,
+}
+
+/// Builds the index of every note, keyed by ID.
+pub fn build_note_index(
+    html_notes: &[HtmlNote],
+    site: &SiteSettings,
+    permalinks: &Permalinks,
+    id_filename_map: &BTreeMap<String, String>,
+) -> StrResult<BTreeMap<String, IndexedNote>> {
+    let mut index = BTreeMap::new();
+    let mut backlinks: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut contexts: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for note in html_notes {
+        for target in collect_targets(&note.document, "wb-internal-link", &note.source_path)? {
+            backlinks.entry(target).or_default().insert(note.id.clone());
+        }
+        for target in collect_targets(&note.document, "wb-transclusion", &note.source_path)? {
+            contexts.entry(target).or_default().insert(note.id.clone());
+        }
+
+        let metadata = extract_metadata(&note.document)?;
+        let title = extract_note_title(&note.document, &metadata)?;
+        let href = permalinks.href(&note.id, site);
+        index.insert(
+            note.id.clone(),
+            IndexedNote {
+                id: note.id.clone(),
+                title,
+                taxon: metadata.get("taxon").cloned(),
+                url: build_absolute_url(&href, site),
+                href,
+                source: id_filename_map.get(&note.id).cloned(),
+                backlinks: Vec::new(),
+                contexts: Vec::new(),
+            },
+        );
+    }
+
+    for (id, sources) in backlinks {
+        if let Some(note) = index.get_mut(&id) {
+            note.backlinks = sources.into_iter().collect();
+        }
+    }
+    for (id, sources) in contexts {
+        if let Some(note) = index.get_mut(&id) {
+            note.contexts = sources.into_iter().collect();
+        }
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use scraper::Html;
+
+    use super::*;
+    use crate::permalink::Aliases;
+
+    fn note(id: &str, head: &str, body: &str) -> HtmlNote {
+        HtmlNote {
+            id: id.to_string(),
+            source_path: PathBuf::from(format!("typ/{id}.typ")),
+            document: Html::parse_document(&format!(
+                "<html><head>{head}</head><body>{body}</body></html>"
+            )),
+        }
+    }
+
+    #[test]
+    fn test_build_note_index() {
+        let notes = [
+            note(
+                "0001",
+                r#"<title>First</title><meta name="taxon" content="Definition">"#,
+                "",
+            ),
+            note(
+                "0002",
+                "",
+                r#"<wb-internal-link target="wb:0001"></wb-internal-link><wb-transclusion target="0001"></wb-transclusion>"#,
+            ),
+            note(
+                "0003",
+                "",
+                r#"<wb-internal-link target="0001"></wb-internal-link>"#,
+            ),
+        ];
+        let site = SiteSettings {
+            domain: Some("example.org".into()),
+            root_dir: "/".into(),
+            trailing_slash: false,
+            permalink: None,
+            home: "index".into(),
+            not_found: false,
+        };
+        let permalinks = Permalinks::collect(&notes, &site, &Aliases::default()).unwrap();
+        let id_filename_map = BTreeMap::from([("0001".to_string(), "/0001.typ".to_string())]);
+
+        let index = build_note_index(&notes, &site, &permalinks, &id_filename_map).unwrap();
+        let first = &index["0001"];
+        assert_eq!(first.id, "0001");
+        assert_eq!(first.title.as_deref(), Some("First"));
+        assert_eq!(first.taxon.as_deref(), Some("Definition"));
+        assert_eq!(first.href, "/0001.html");
+        assert_eq!(first.url, "https://example.org/0001.html");
+        assert_eq!(first.source.as_deref(), Some("/0001.typ"));
+        assert_eq!(first.backlinks, ["0002", "0003"]);
+        assert_eq!(first.contexts, ["0002"]);
+
+        let second = &index["0002"];
+        assert_eq!(second.title, None);
+        assert_eq!(second.taxon, None);
+        assert_eq!(second.source, None);
+        assert!(second.backlinks.is_empty() && second.contexts.is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use ecow::eco_format;
+
+use crate::args::InitCommand;
+use crate::error::StrResult;
+
+/// The files of a new project besides its configuration, by their path
+/// relative to the project directory.
+const STARTER_FILES: [(&str, &str); 17] = [
+    (
+        ".wb/templates/note.html",
+        include_str!("../.wb/templates/note.html"),
+    ),
+    (
+        ".wb/templates/internal_link.html",
+        include_str!("../.wb/templates/internal_link.html"),
+    ),
+    (
+        ".wb/templates/citation.html",
+        include_str!("../.wb/templates/citation.html"),
+    ),
+    (
+        ".wb/templates/transclusion.html",
+        include_str!("../.wb/templates/transclusion.html"),
+    ),
+    (
+        ".wb/templates/redirect.html",
+        include_str!("../.wb/templates/redirect.html"),
+    ),
+    (
+        ".wb/templates/404.html",
+        include_str!("../.wb/templates/404.html"),
+    ),
+    (
+        "typ/_template/template.typ",
+        include_str!("../typ/_template/template.typ"),
+    ),
+    (
+        "typ/_template/template-paged.typ",
+        include_str!("../typ/_template/template-paged.typ"),
+    ),
+    (
+        "typ/_template/lib.typ",
+        include_str!("../typ/_template/lib.typ"),
+    ),
+    (
+        "typ/_template/site.typ",
+        include_str!("../typ/_template/site.typ"),
+    ),
+    (
+        "typ/_template/bibliography-template.typ",
+        include_str!("../typ/_template/bibliography-template.typ"),
+    ),
+    (
+        "typ/_template/og-card.typ",
+        include_str!("../typ/_template/og-card.typ"),
+    ),
+    (
+        "typ/_template/modus_operandi.tmTheme",
+        include_str!("../typ/_template/modus_operandi.tmTheme"),
+    ),
+    (
+        "typ/_template/modus_vivendi.tmTheme",
+        include_str!("../typ/_template/modus_vivendi.tmTheme"),
+    ),
+    (
+        "public/css/weibian.css",
+        include_str!("../public/css/weibian.css"),
+    ),
+    (
+        "public/js/weibian.js",
+        include_str!("../public/js/weibian.js"),
+    ),
+    ("typ/@@index--welcome.typ", EXAMPLE_NOTE),
+];
+
+/// The home note of a new project.
+const EXAMPLE_NOTE: &str = r#"#import "/_template/template.typ": template, tr, ln, ct, inline-tree
+#show: template(
+  title:      [Welcome],
+  tags:       (),
+  identifier: "index",
+)
+
+This is the home page of your notes. Run `wb new "My first note"` to create a note, link to it from here with `#ln("wb:0000")[]`, and build the site with `wb compile`.
+"#;
+
+/// Lays down a new project from the bundled starter templates.
+pub fn init(command: &InitCommand) -> StrResult<()> {
+    let dir = command.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let config = starter_config(command);
+    let files: Vec<(PathBuf, &str)> = std::iter::once((dir.join(".wb/config.toml"), &*config))
+        .chain(
+            STARTER_FILES
+                .iter()
+                .map(|(path, contents)| (dir.join(path), *contents)),
+        )
+        .collect();
+
+    let existing: Vec<String> = files
+        .iter()
+        .filter(|(path, _)| path.exists())
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    if !existing.is_empty() {
+        return Err(eco_format!(
+            "refusing to overwrite existing files: {}",
+            existing.join(", ")
+        ));
+    }
+
+    for (path, contents) in &files {
+        write_new(path, contents)?;
+    }
+
+    println!("initialized a Weibian project in {}", dir.display());
+    Ok(())
+}
+
+/// The configuration file of a new project.
+fn starter_config(command: &InitCommand) -> String {
+    let domain = match &command.domain {
+        Some(domain) => format!("domain = \"{}\"", escape_string(domain)),
+        None => "# domain = \"https://example.com\"".to_string(),
+    };
+    let root_dir = escape_string(command.root_dir.as_deref().unwrap_or("/"));
+    format!(
+        r#"[files]
+input_dir = "typ"
+output_dir = "dist"
+public_dir = "public"
+exclude = ["_template/*.typ"]   # optional; exclude has priority over include
+# the above is the equivalent of the corresponding CLI flags
+
+[site]
+{domain} # the domain of the site; used for generating absolute URLs
+root_dir = "{root_dir}" # the root directory of the site; for example, if the site is hosted at example.com/notes/, set root_dir = "/notes/"; if hosted at the root domain, set root_dir = "/"
+trailing_slash = true # if true, the final URL of each note will have a trailing slash
+
+# [og]
+# card = "_template/og-card.typ" # the Typst template of the social preview image of each note, relative to input_dir
+"#
+    )
+}
+
+fn write_new(path: &Path, contents: &str) -> StrResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| eco_format!("failed to create directory {}: {err}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|err| eco_format!("failed to create {}: {err}", path.display()))?;
+    file.write_all(contents.as_bytes())
+        .map_err(|err| eco_format!("failed to write {}: {err}", path.display()))
+}
+
+/// Escapes text for a TOML basic string.
+fn escape_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(dir: &Path) -> InitCommand {
+        InitCommand {
+            dir: Some(dir.to_path_buf()),
+            domain: Some("example.org".into()),
+            root_dir: Some("/notes/".into()),
+        }
+    }
+
+    #[test]
+    fn test_init_writes_the_starter_project() {
+        let dir = tempfile::tempdir().unwrap();
+        init(&command(dir.path())).unwrap();
+        let config = fs::read_to_string(dir.path().join(".wb/config.toml")).unwrap();
+        assert!(config.contains("domain = \"example.org\""));
+        assert!(config.contains("root_dir = \"/notes/\""));
+        for (path, contents) in STARTER_FILES {
+            assert_eq!(fs::read_to_string(dir.path().join(path)).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn test_init_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let note = dir.path().join("typ/@@index--welcome.typ");
+        fs::create_dir_all(note.parent().unwrap()).unwrap();
+        fs::write(&note, "mine").unwrap();
+
+        let err = init(&command(dir.path())).unwrap_err();
+        assert!(err.contains("refusing to overwrite"), "{err}");
+        assert!(err.contains("@@index--welcome.typ"), "{err}");
+        // Nothing is written, not even the files that did not exist.
+        assert_eq!(fs::read_to_string(&note).unwrap(), "mine");
+        assert!(!dir.path().join(".wb").exists());
+    }
+
+    #[test]
+    fn test_starter_config_escapes_strings() {
+        let config = starter_config(&InitCommand {
+            dir: None,
+            domain: None,
+            root_dir: Some("/a \"b\"/".into()),
+        });
+        assert!(config.contains("# domain = "));
+        assert!(config.contains(r#"root_dir = "/a \"b\"/""#));
+    }
+}
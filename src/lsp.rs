@@ -0,0 +1,1021 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use ecow::eco_format;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument,
+    DidSaveTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, GotoDefinition, HoverRequest, References, RegisterCapability, Request as _,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, Diagnostic, DiagnosticSeverity, DidChangeWatchedFilesRegistrationOptions,
+    Documentation, FileChangeType, FileSystemWatcher, GlobPattern, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability,
+    InitializeParams, Location, MarkupContent, MarkupKind, OneOf, Position,
+    PublishDiagnosticsParams, ReferenceParams, Registration, RegistrationParams,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    TextDocumentSyncSaveOptions, TextEdit, Url,
+};
+use serde::de::DeserializeOwned;
+
+use crate::args::LspCommand;
+use crate::backend::NoteGraph;
+use crate::compiler::{CliTypstCompiler, TypstCompiler};
+use crate::config::{BuildConfig, WeibianConfig};
+use crate::error::StrResult;
+use crate::frontend;
+use crate::html::{HtmlNote, extract_metadata, extract_note_title, is_note_id_char};
+use crate::permalink::Aliases;
+
+/// The metadata shown when hovering a reference, in this order.
+const HOVER_METADATA: [&str; 5] = ["taxon", "author", "date", "description", "aliases"];
+
+/// Runs a language server on stdin and stdout until the client exits.
+///
+/// The notes are compiled once when the server starts, and each source again
+/// when it is saved, so that the graph reflects the saved state of the notes
+/// while positions are looked up in the text being edited.
+pub fn lsp(command: &LspCommand, config: &WeibianConfig) -> StrResult<()> {
+    let mut build_config = BuildConfig::from(&command.args, config)?;
+    // Sources are identified by the paths of the documents of the client.
+    build_config.input_directory =
+        fs::canonicalize(&build_config.input_directory).map_err(|err| {
+            eco_format!(
+                "failed to open input directory {}: {err}",
+                build_config.input_directory.display()
+            )
+        })?;
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(capabilities())
+        .map_err(|err| eco_format!("failed to encode server capabilities: {err}"))?;
+    let params = connection
+        .initialize(capabilities)
+        .map_err(|err| eco_format!("failed to initialize the language server: {err}"))?;
+    let params: InitializeParams = parse(params)?;
+    if params
+        .capabilities
+        .workspace
+        .and_then(|workspace| workspace.did_change_watched_files)
+        .and_then(|watched_files| watched_files.dynamic_registration)
+        .unwrap_or(false)
+    {
+        register_file_watchers(&connection)?;
+    }
+
+    let mut server = Server::new(build_config, &CliTypstCompiler);
+    server.compile_all()?;
+    server.publish_diagnostics(&connection)?;
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection
+                    .handle_shutdown(&request)
+                    .map_err(|err| eco_format!("failed to shut down: {err}"))?
+                {
+                    break;
+                }
+                let response = server.handle_request(request);
+                send(&connection, Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if server.handle_notification(notification)? {
+                    server.publish_diagnostics(&connection)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+
+    drop(connection);
+    io_threads
+        .join()
+        .map_err(|err| eco_format!("language server connection failed: {err}"))
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                ..Default::default()
+            },
+        )),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".into(), "/".into()]),
+            ..Default::default()
+        }),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        references_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Asks the client to notify the server of changes to sources made outside
+/// of it, e.g. by `wb new`, `wb mv` or version control. The client answers
+/// with a response that is ignored.
+fn register_file_watchers(connection: &Connection) -> StrResult<()> {
+    let options = DidChangeWatchedFilesRegistrationOptions {
+        watchers: vec![FileSystemWatcher {
+            glob_pattern: GlobPattern::String("**/*.typ".into()),
+            kind: None,
+        }],
+    };
+    let params = RegistrationParams {
+        registrations: vec![Registration {
+            id: "weibian-watched-files".into(),
+            method: DidChangeWatchedFiles::METHOD.into(),
+            register_options: Some(to_json(options)?),
+        }],
+    };
+    send(
+        connection,
+        Message::Request(Request::new(
+            RequestId::from("weibian-register-watched-files".to_string()),
+            RegisterCapability::METHOD.into(),
+            params,
+        )),
+    )
+}
+
+fn send(connection: &Connection, message: Message) -> StrResult<()> {
+    connection
+        .sender
+        .send(message)
+        .map_err(|err| eco_format!("failed to send a message to the client: {err}"))
+}
+
+/// What the server knows about the notes.
+struct Server<'a> {
+    build_config: BuildConfig,
+    compiler: &'a dyn TypstCompiler,
+    /// The compiled notes, with the canonical paths of their sources.
+    notes: Vec<HtmlNote>,
+    /// Why sources failed to compile.
+    errors: BTreeMap<PathBuf, String>,
+    /// The text of the documents open in the client.
+    documents: HashMap<PathBuf, String>,
+    /// The notes by ID or alias, as indices into `notes`.
+    graph: NoteGraph,
+    /// The sources diagnostics were last published for.
+    published: BTreeSet<PathBuf>,
+}
+
+impl<'a> Server<'a> {
+    fn new(build_config: BuildConfig, compiler: &'a dyn TypstCompiler) -> Self {
+        Self {
+            build_config,
+            compiler,
+            notes: Vec::new(),
+            errors: BTreeMap::new(),
+            documents: HashMap::new(),
+            graph: NoteGraph::new(&[], Aliases::default()),
+            published: BTreeSet::new(),
+        }
+    }
+
+    fn compile_all(&mut self) -> StrResult<()> {
+        for source in frontend::collect_typst_sources(&self.build_config)? {
+            self.compile(&source);
+        }
+        self.update_graph();
+        Ok(())
+    }
+
+    /// Compiles a source again, replacing its note, or forgets it if it no
+    /// longer exists.
+    fn compile(&mut self, source: &Path) {
+        self.notes.retain(|note| note.source_path != source);
+        self.errors.remove(source);
+        if !source.is_file() {
+            return;
+        }
+        match frontend::compile_note(&self.build_config, self.compiler, source) {
+            Ok(note) => self.notes.push(note),
+            Err(err) => {
+                self.errors.insert(source.to_path_buf(), err.to_string());
+            }
+        }
+    }
+
+    fn update_graph(&mut self) {
+        self.notes.sort_by(|a, b| a.source_path.cmp(&b.source_path));
+        let aliases = match Aliases::collect(&self.notes, &self.build_config.aliases) {
+            Ok(aliases) => aliases,
+            Err(err) => {
+                crate::print_warning(&err);
+                Aliases::default()
+            }
+        };
+        self.graph = NoteGraph::new(&self.notes, aliases);
+    }
+
+    /// Whether a path is a source of the notes.
+    fn is_source(&self, path: &Path) -> bool {
+        let input_dir = &self.build_config.input_directory;
+        path.extension().and_then(|ext| ext.to_str()) == Some("typ")
+            && path
+                .strip_prefix(input_dir)
+                .is_ok_and(|relative| self.build_config.input_filters.allows(relative))
+    }
+
+    /// The note with an ID or an alias of it.
+    fn resolve(&self, target: &str) -> Option<&HtmlNote> {
+        self.graph.resolve(target).map(|index| &self.notes[index])
+    }
+
+    /// The text of a source, as it is in the client if it is open there.
+    fn text(&self, path: &Path) -> String {
+        self.documents
+            .get(path)
+            .cloned()
+            .unwrap_or_else(|| fs::read_to_string(path).unwrap_or_default())
+    }
+
+    /// Handles a notification, and returns whether the notes changed.
+    fn handle_notification(&mut self, notification: Notification) -> StrResult<bool> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = parse(notification.params)?;
+                if let Some(path) = to_path(&params.text_document.uri) {
+                    self.documents.insert(path, params.text_document.text);
+                }
+                Ok(true)
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = parse(notification.params)?;
+                if let Some(path) = to_path(&params.text_document.uri)
+                    && let Some(change) = params.content_changes.into_iter().last()
+                {
+                    self.documents.insert(path, change.text);
+                }
+                Ok(false)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = parse(notification.params)?;
+                if let Some(path) = to_path(&params.text_document.uri) {
+                    self.documents.remove(&path);
+                }
+                Ok(false)
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: lsp_types::DidSaveTextDocumentParams = parse(notification.params)?;
+                let Some(path) = to_path(&params.text_document.uri) else {
+                    return Ok(false);
+                };
+                if !self.is_source(&path) {
+                    return Ok(false);
+                }
+                self.compile(&path);
+                self.update_graph();
+                Ok(true)
+            }
+            DidChangeWatchedFiles::METHOD => {
+                let params: lsp_types::DidChangeWatchedFilesParams = parse(notification.params)?;
+                let mut changed = false;
+                for event in params.changes {
+                    let Some(path) = to_path(&event.uri) else {
+                        continue;
+                    };
+                    if !self.is_source(&path) {
+                        continue;
+                    }
+                    if event.typ == FileChangeType::DELETED {
+                        self.documents.remove(&path);
+                    }
+                    self.compile(&path);
+                    changed = true;
+                }
+                if changed {
+                    self.update_graph();
+                }
+                Ok(changed)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn handle_request(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            Completion::METHOD => {
+                parse(request.params).and_then(|params| to_json(self.completion(params)))
+            }
+            GotoDefinition::METHOD => {
+                parse(request.params).and_then(|params| to_json(self.definition(params)))
+            }
+            HoverRequest::METHOD => {
+                parse(request.params).and_then(|params| to_json(self.hover(params)))
+            }
+            References::METHOD => {
+                parse(request.params).and_then(|params| to_json(self.references(params)))
+            }
+            method => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request {method}"),
+                );
+            }
+        };
+        respond(id, result)
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let path = to_path(&position.text_document.uri)?;
+        let text = self.text(&path);
+        let offset = to_offset(&text, position.position)?;
+
+        // The partial ID typed after `wb:`.
+        let start = text[..offset]
+            .rfind(|c: char| !is_note_id_char(c))
+            .map_or(0, |index| index + 1);
+        if !text[..start].ends_with("wb:") {
+            return None;
+        }
+        let range = lsp_types::Range::new(to_position(&text, start), to_position(&text, offset));
+
+        let mut items: Vec<CompletionItem> = self
+            .graph
+            .ids()
+            .map(|(id, index)| {
+                let note = &self.notes[index];
+                let metadata = extract_metadata(&note.document).unwrap_or_default();
+                let title = extract_note_title(&note.document, &metadata).ok().flatten();
+                CompletionItem {
+                    label: id.to_string(),
+                    kind: Some(CompletionItemKind::REFERENCE),
+                    detail: title.clone(),
+                    documentation: metadata
+                        .get("taxon")
+                        .map(|taxon| Documentation::String(taxon.clone())),
+                    // Titles are matched as well as IDs.
+                    filter_text: Some(match &title {
+                        Some(title) => format!("{id} {title}"),
+                        None => id.to_string(),
+                    }),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                        range,
+                        id.to_string(),
+                    ))),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        items.extend(
+            self.graph
+                .aliases()
+                .iter()
+                .map(|(alias, target)| CompletionItem {
+                    label: alias.to_string(),
+                    kind: Some(CompletionItemKind::REFERENCE),
+                    detail: Some(format!("alias of {target}")),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                        range,
+                        alias.to_string(),
+                    ))),
+                    ..Default::default()
+                }),
+        );
+        items.sort_by(|a, b| a.label.cmp(&b.label));
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let path = to_path(&position.text_document.uri)?;
+        let text = self.text(&path);
+        let (_, target) = reference_at(&text, to_offset(&text, position.position)?)?;
+        let note = self.resolve(target)?;
+        Some(GotoDefinitionResponse::Scalar(self.declaration(note)?))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let path = to_path(&position.text_document.uri)?;
+        let text = self.text(&path);
+        let (range, target) = reference_at(&text, to_offset(&text, position.position)?)?;
+        let note = self.resolve(target)?;
+
+        let metadata = extract_metadata(&note.document).ok()?;
+        let title = extract_note_title(&note.document, &metadata).ok().flatten();
+        let mut value = format!("**{}**", title.as_deref().unwrap_or(&note.id));
+        value.push_str(&format!("\n\n`{}`", note.id));
+        if note.id != target {
+            value.push_str(&format!(" (aliased as `{target}`)"));
+        }
+        value.push('\n');
+        for key in HOVER_METADATA {
+            if let Some(content) = metadata.get(key) {
+                value.push_str(&format!("\n- {key}: {content}"));
+            }
+        }
+        if let Ok(relative) = note
+            .source_path
+            .strip_prefix(&self.build_config.input_directory)
+        {
+            value.push_str(&format!("\n- source: {}", relative.display()));
+        }
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(lsp_types::Range::new(
+                to_position(&text, range.start),
+                to_position(&text, range.end),
+            )),
+        })
+    }
+
+    /// The notes linking to, citing or transcluding the note referenced at
+    /// the position, or the note of the document if there is no reference
+    /// there.
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let position = params.text_document_position;
+        let path = to_path(&position.text_document.uri)?;
+        let text = self.text(&path);
+        let index = match reference_at(&text, to_offset(&text, position.position)?) {
+            Some((_, target)) => self.graph.resolve(target)?,
+            None => self
+                .notes
+                .iter()
+                .position(|note| note.source_path == path)?,
+        };
+        let note = &self.notes[index];
+
+        let names: Vec<&str> = std::iter::once(note.id.as_str())
+            .chain(
+                self.graph
+                    .aliases()
+                    .iter()
+                    .filter(|(_, target)| *target == note.id)
+                    .map(|(alias, _)| alias),
+            )
+            .collect();
+        let mut locations = Vec::new();
+        if params.context.include_declaration {
+            locations.extend(self.declaration(note));
+        }
+        for other in self.graph.referrers(index) {
+            let other = &self.notes[other];
+            let Ok(uri) = Url::from_file_path(&other.source_path) else {
+                continue;
+            };
+            let text = self.text(&other.source_path);
+            let mut ranges: Vec<Range<usize>> = names
+                .iter()
+                .flat_map(|name| reference_ranges(&text, name))
+                .collect();
+            if ranges.is_empty() {
+                // The reference is not written as a `wb:` literal, e.g.
+                // `#tr("0001")`; notes referring to it in another way, e.g.
+                // through a variable, cannot be pointed at and are left out.
+                ranges = names
+                    .iter()
+                    .flat_map(|name| quoted_ranges(&text, name))
+                    .collect();
+            }
+            for range in ranges {
+                locations.push(Location::new(
+                    uri.clone(),
+                    lsp_types::Range::new(
+                        to_position(&text, range.start),
+                        to_position(&text, range.end),
+                    ),
+                ));
+            }
+        }
+        Some(locations)
+    }
+
+    /// Where a note is declared: its `identifier` field, or the start of its
+    /// source.
+    fn declaration(&self, note: &HtmlNote) -> Option<Location> {
+        let uri = Url::from_file_path(&note.source_path).ok()?;
+        let text = self.text(&note.source_path);
+        let range = identifier_range(&text, &note.id)
+            .map(|range| {
+                lsp_types::Range::new(
+                    to_position(&text, range.start),
+                    to_position(&text, range.end),
+                )
+            })
+            .unwrap_or_default();
+        Some(Location::new(uri, range))
+    }
+
+    /// Publishes the diagnostics of every source, and clears those of the
+    /// sources that no longer have any.
+    fn publish_diagnostics(&mut self, connection: &Connection) -> StrResult<()> {
+        let mut diagnostics: BTreeMap<PathBuf, Vec<Diagnostic>> = BTreeMap::new();
+        for (path, message) in &self.errors {
+            let text = self.text(path);
+            diagnostics
+                .entry(path.clone())
+                .or_default()
+                .extend(compile_diagnostics(message, path, &text));
+        }
+        for &(index, previous) in self.graph.duplicates() {
+            let note = &self.notes[index];
+            let text = self.text(&note.source_path);
+            let range = identifier_range(&text, &note.id)
+                .map(|range| {
+                    lsp_types::Range::new(
+                        to_position(&text, range.start),
+                        to_position(&text, range.end),
+                    )
+                })
+                .unwrap_or_default();
+            diagnostics
+                .entry(note.source_path.clone())
+                .or_default()
+                .push(diagnostic(
+                    range,
+                    DiagnosticSeverity::ERROR,
+                    format!(
+                        "duplicate note id {} (already used by {})",
+                        note.id,
+                        self.notes[previous].source_path.display()
+                    ),
+                ));
+        }
+        for (index, note) in self.notes.iter().enumerate() {
+            let text = self.text(&note.source_path);
+            for target in self.graph.references(index) {
+                let (severity, message) = if self.graph.contains(target) {
+                    continue;
+                } else if let Some(id) = self.graph.aliases().resolve(target) {
+                    (
+                        DiagnosticSeverity::WARNING,
+                        format!("{target} is an alias of {id}"),
+                    )
+                } else {
+                    (
+                        DiagnosticSeverity::ERROR,
+                        format!("note {target} does not exist"),
+                    )
+                };
+                let mut ranges: Vec<lsp_types::Range> = reference_ranges(&text, target)
+                    .into_iter()
+                    .map(|range| {
+                        lsp_types::Range::new(
+                            to_position(&text, range.start),
+                            to_position(&text, range.end),
+                        )
+                    })
+                    .collect();
+                if ranges.is_empty() {
+                    ranges.push(lsp_types::Range::default());
+                }
+                let entry = diagnostics.entry(note.source_path.clone()).or_default();
+                for range in ranges {
+                    entry.push(diagnostic(range, severity, message.clone()));
+                }
+            }
+        }
+
+        let cleared: Vec<PathBuf> = self
+            .published
+            .iter()
+            .filter(|path| !diagnostics.contains_key(*path))
+            .cloned()
+            .collect();
+        for path in cleared {
+            diagnostics.insert(path, Vec::new());
+        }
+        self.published.clear();
+        for (path, diagnostics) in diagnostics {
+            let Ok(uri) = Url::from_file_path(&path) else {
+                continue;
+            };
+            if !diagnostics.is_empty() {
+                self.published.insert(path);
+            }
+            let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+            send(
+                connection,
+                Message::Notification(Notification::new(
+                    PublishDiagnostics::METHOD.to_string(),
+                    params,
+                )),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The diagnostics of a source that failed to compile.
+///
+/// Typst reports each error with the span it is about, as
+/// `┌─ <path>:<line>:<column>` followed by the line underlined with carets;
+/// the spans in the source are mapped to ranges of its text. The whole message
+/// is reported at the start of the source if none of them are in it, e.g. when
+/// the error is in an imported file.
+fn compile_diagnostics(message: &str, source: &Path, text: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut current = None;
+    let mut lines = message.lines().peekable();
+    while let Some(line) = lines.next() {
+        if let Some(header) = diagnostic_header(line) {
+            current = Some(header);
+            continue;
+        }
+        let Some(location) = line.trim_start().strip_prefix("┌─ ") else {
+            continue;
+        };
+        // The width of the span is the number of carets under the line.
+        let mut width = 1;
+        while let Some(next) = lines.next_if(|next| !next.trim().is_empty()) {
+            if let Some((_, after)) = next.split_once('│') {
+                let after = after.trim();
+                if !after.is_empty() && after.chars().all(|c| c == '^') {
+                    width = after.chars().count();
+                }
+            }
+        }
+        let mut parts = location.trim().rsplitn(3, ':');
+        let (Some(column), Some(line), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let (Ok(line), Ok(column)) = (line.parse::<usize>(), column.parse::<usize>()) else {
+            continue;
+        };
+        let Some((severity, message)) = current else {
+            continue;
+        };
+        if !source.ends_with(path.trim_start_matches("./").trim_start_matches('/')) {
+            continue;
+        }
+        if let Some(range) = line_column_range(text, line, column, width) {
+            diagnostics.push(diagnostic(
+                lsp_types::Range::new(to_position(text, range.start), to_position(text, range.end)),
+                severity,
+                message.to_string(),
+            ));
+        }
+    }
+    if diagnostics.is_empty() {
+        diagnostics.push(diagnostic(
+            lsp_types::Range::default(),
+            DiagnosticSeverity::ERROR,
+            message.to_string(),
+        ));
+    }
+    diagnostics
+}
+
+/// The severity and message of a line starting a Typst diagnostic, which can
+/// follow the description of the failed compilation on the same line.
+fn diagnostic_header(line: &str) -> Option<(DiagnosticSeverity, &str)> {
+    [
+        ("error: ", DiagnosticSeverity::ERROR),
+        ("warning: ", DiagnosticSeverity::WARNING),
+    ]
+    .into_iter()
+    .find_map(|(marker, severity)| {
+        let index = line.find(marker)?;
+        (index == 0 || line[..index].ends_with(": "))
+            .then(|| (severity, &line[index + marker.len()..]))
+    })
+}
+
+/// The byte range of `width` characters from a 1-based line and column,
+/// clamped to the line.
+fn line_column_range(text: &str, line: usize, column: usize, width: usize) -> Option<Range<usize>> {
+    let mut line_start = 0;
+    for _ in 1..line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+    let line_text = text[line_start..].split('\n').next().unwrap_or("");
+    let offset = |chars: usize| {
+        line_start
+            + line_text
+                .char_indices()
+                .nth(chars)
+                .map_or(line_text.len(), |(index, _)| index)
+    };
+    let start = offset(column.saturating_sub(1));
+    Some(start..offset(column.saturating_sub(1) + width))
+}
+
+fn diagnostic(
+    range: lsp_types::Range,
+    severity: DiagnosticSeverity,
+    message: String,
+) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("weibian".into()),
+        message,
+        ..Default::default()
+    }
+}
+
+fn parse<T: DeserializeOwned>(params: serde_json::Value) -> StrResult<T> {
+    serde_json::from_value(params).map_err(|err| eco_format!("invalid parameters: {err}"))
+}
+
+fn to_json<T: serde::Serialize>(value: T) -> StrResult<serde_json::Value> {
+    serde_json::to_value(value).map_err(|err| eco_format!("failed to encode response: {err}"))
+}
+
+fn respond(id: RequestId, result: StrResult<serde_json::Value>) -> Response {
+    match result {
+        Ok(value) => Response::new_ok(id, value),
+        Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()),
+    }
+}
+
+fn to_path(uri: &Url) -> Option<PathBuf> {
+    let path = uri.to_file_path().ok()?;
+    Some(fs::canonicalize(&path).unwrap_or(path))
+}
+
+/// The byte offset of a position, whose character is counted in UTF-16 code
+/// units.
+fn to_offset(text: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return Some(line_start + index);
+        }
+        units += c.len_utf16();
+    }
+    Some(line_start + line.len())
+}
+
+fn to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Position::new(line as u32, character as u32)
+}
+
+/// The `wb:` reference around an offset, with the range of its ID.
+fn reference_at(text: &str, offset: usize) -> Option<(Range<usize>, &str)> {
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = text[offset..]
+        .find('\n')
+        .map_or(text.len(), |index| offset + index);
+    let line = &text[line_start..line_end];
+    line.match_indices("wb:").find_map(|(index, _)| {
+        let start = line_start + index + "wb:".len();
+        let end = text[start..line_end]
+            .find(|c: char| !is_note_id_char(c))
+            .map_or(line_end, |index| start + index);
+        let id = text[start..end].trim_end_matches(['.', '/']);
+        let end = start + id.len();
+        (start - "wb:".len() <= offset && offset <= end && !id.is_empty())
+            .then_some((start..end, id))
+    })
+}
+
+/// The ranges of the IDs of the `wb:<id>` references in a text.
+fn reference_ranges(text: &str, id: &str) -> Vec<Range<usize>> {
+    let pattern = format!("wb:{id}");
+    text.match_indices(&pattern)
+        .filter(|(index, _)| !text[index + pattern.len()..].starts_with(is_note_id_char))
+        .map(|(index, _)| index + "wb:".len()..index + pattern.len())
+        .collect()
+}
+
+/// The ranges of the IDs written as string literals, e.g. `"0001"`, in a
+/// text.
+fn quoted_ranges(text: &str, id: &str) -> Vec<Range<usize>> {
+    let pattern = format!("\"{id}\"");
+    text.match_indices(&pattern)
+        .map(|(index, _)| index + 1..index + 1 + id.len())
+        .collect()
+}
+
+/// The range of the value of the `identifier` field of a source.
+fn identifier_range(text: &str, id: &str) -> Option<Range<usize>> {
+    text.match_indices("identifier").find_map(|(index, key)| {
+        let rest = &text[index + key.len()..];
+        let value = rest.trim_start().strip_prefix(':')?.trim_start();
+        value
+            .strip_prefix('"')?
+            .strip_prefix(id)?
+            .strip_prefix('"')?;
+        let start = text.len() - value.len() + 1;
+        Some(start..start + id.len())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{
+        PartialResultParams, ReferenceContext, TextDocumentIdentifier, TextDocumentPositionParams,
+        WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::args::{CompileArgs, SourceArgs};
+    use crate::html::parse_note_html;
+
+    /// A server knowing notes given by ID, source text and links, whose
+    /// sources are written to a directory.
+    fn server<'a>(dir: &Path, notes: &[(&str, &str, &[&str])]) -> Server<'a> {
+        let args = CompileArgs {
+            sources: SourceArgs {
+                input: Some(dir.to_path_buf()),
+                ..Default::default()
+            },
+            output: Some(dir.with_file_name("dist")),
+            ..Default::default()
+        };
+        let build_config = BuildConfig::from(&args, &WeibianConfig::default()).unwrap();
+        let mut server = Server::new(build_config, &CliTypstCompiler);
+        for (id, text, links) in notes {
+            let path = dir.join(format!("{id}.typ"));
+            fs::write(&path, text).unwrap();
+            let links: String = links
+                .iter()
+                .map(|target| format!("<a href=\"wb:{target}\">{target}</a>"))
+                .collect();
+            let html = format!(
+                "<html><head><meta name=\"identifier\" content=\"{id}\"></head>\
+                 <body>{links}</body></html>"
+            );
+            server.notes.push(parse_note_html(&html, &path).unwrap());
+        }
+        server.update_graph();
+        server
+    }
+
+    fn position(path: &Path, text: &str, offset: usize) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(path).unwrap(),
+            },
+            position: to_position(text, offset),
+        }
+    }
+
+    fn references(server: &Server, path: &Path, text: &str, offset: usize) -> Vec<Location> {
+        server
+            .references(ReferenceParams {
+                text_document_position: position(path, text, offset),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+                partial_result_params: PartialResultParams::default(),
+                context: ReferenceContext {
+                    include_declaration: false,
+                },
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_definition() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(root.path()).unwrap().join("typ");
+        fs::create_dir(&dir).unwrap();
+        let a = "#show: note.with(\n  identifier: \"0001\",\n)\n";
+        let b = "See #ln(\"wb:0001\")[].\n";
+        let server = server(&dir, &[("0001", a, &[]), ("0002", b, &["0001"])]);
+
+        let path = dir.join("0002.typ");
+        let response = server.definition(GotoDefinitionParams {
+            text_document_position_params: position(&path, b, b.find("0001").unwrap()),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        });
+        let start = a.find("0001").unwrap();
+        assert_eq!(
+            response,
+            Some(GotoDefinitionResponse::Scalar(Location::new(
+                Url::from_file_path(dir.join("0001.typ")).unwrap(),
+                lsp_types::Range::new(to_position(a, start), to_position(a, start + 4)),
+            )))
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(root.path()).unwrap().join("typ");
+        fs::create_dir(&dir).unwrap();
+        let a = "#show: note.with(identifier: \"0001\")\n";
+        let b = "See #ln(\"wb:0001\")[] and #ln(\"wb:0001\")[].\n";
+        let c = "#tr(\"0001\")\n";
+        let d = "#let target = \"00\" + \"01\"\n#tr(target)\n";
+        let server = server(
+            &dir,
+            &[
+                ("0001", a, &[]),
+                ("0002", b, &["0001"]),
+                ("0003", c, &["0001"]),
+                ("0004", d, &["0001"]),
+            ],
+        );
+
+        let locations = references(&server, &dir.join("0001.typ"), a, 0);
+        let location = |id: &str, text: &str, start: usize| {
+            Location::new(
+                Url::from_file_path(dir.join(format!("{id}.typ"))).unwrap(),
+                lsp_types::Range::new(to_position(text, start), to_position(text, start + 4)),
+            )
+        };
+        assert_eq!(
+            locations,
+            vec![
+                location("0002", b, b.find("0001").unwrap()),
+                location("0002", b, b.rfind("0001").unwrap()),
+                location("0003", c, c.find("0001").unwrap()),
+            ]
+        );
+
+        // A reference finds the same notes as the note itself.
+        let at_reference = references(&server, &dir.join("0002.typ"), b, b.find("0001").unwrap());
+        assert_eq!(at_reference, locations);
+    }
+
+    #[test]
+    fn test_to_offset() {
+        let text = "ab\nc€d\n";
+        assert_eq!(to_offset(text, Position::new(0, 1)), Some(1));
+        assert_eq!(to_offset(text, Position::new(1, 0)), Some(3));
+        assert_eq!(to_offset(text, Position::new(1, 2)), Some(7));
+        assert_eq!(to_offset(text, Position::new(1, 9)), Some(8));
+        assert_eq!(to_offset(text, Position::new(5, 0)), None);
+    }
+
+    #[test]
+    fn test_to_offset_counts_utf16_units() {
+        let text = "😀x";
+        assert_eq!(to_offset(text, Position::new(0, 2)), Some(4));
+        assert_eq!(to_position(text, 4), Position::new(0, 2));
+    }
+
+    #[test]
+    fn test_reference_at() {
+        let text = "see #ln(\"wb:0001\")[] and wb:abc.\n";
+        let start = text.find("0001").unwrap();
+        assert_eq!(
+            reference_at(text, start + 2),
+            Some((start..start + 4, "0001"))
+        );
+        assert_eq!(
+            reference_at(text, start - 3),
+            Some((start..start + 4, "0001"))
+        );
+        let start = text.find("abc").unwrap();
+        assert_eq!(
+            reference_at(text, start + 3),
+            Some((start..start + 3, "abc"))
+        );
+        assert_eq!(reference_at(text, 1), None);
+        assert_eq!(reference_at("wb:", 0), None);
+    }
+
+    #[test]
+    fn test_compile_diagnostics_maps_spans() {
+        let text = "#let x = 1\n#let y = (\"a\" +)\n";
+        let message = "compilation failed: error: expected expression\n  \
+            ┌─ typ/x.typ:2:15\n  │\n2 │ #let y = (\"a\" +)\n  │                ^\n\n\
+            warning: unused\n  ┌─ typ/x.typ:1:6\n  │\n1 │ #let x = 1\n  │      ^\n";
+        let diagnostics = compile_diagnostics(message, Path::new("/notes/typ/x.typ"), text);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "expected expression");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diagnostics[0].range,
+            lsp_types::Range::new(Position::new(1, 14), Position::new(1, 15))
+        );
+        assert_eq!(diagnostics[1].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(
+            diagnostics[1].range,
+            lsp_types::Range::new(Position::new(0, 5), Position::new(0, 6))
+        );
+    }
+
+    #[test]
+    fn test_compile_diagnostics_falls_back_to_start() {
+        let message = "error: unknown variable\n  ┌─ _template/t.typ:3:1\n  │\n3 │ a\n  │ ^\n";
+        let diagnostics = compile_diagnostics(message, Path::new("/notes/typ/x.typ"), "text");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, lsp_types::Range::default());
+        assert_eq!(diagnostics[0].message, message);
+    }
+}
@@ -1,10 +1,20 @@
 mod args;
 mod backend;
+mod book;
 mod compiler;
 mod config;
+mod epub;
 mod error;
 mod frontend;
+mod generated;
+mod head;
 mod html;
+mod index;
+mod init;
+mod lsp;
+mod mv;
+mod new;
+mod permalink;
 mod terminal;
 // mod backend;
 mod compile;
@@ -55,9 +65,16 @@ fn dispatch() -> StrResult<()> {
 
     match &ARGS.command {
         Command::Compile(command) => crate::compile::compile(command, &config)?,
+        Command::Export(command) => crate::compile::export(command, &config)?,
+        Command::Init(command) => crate::init::init(command)?,
+        Command::New(command) => crate::new::new(command, &config)?,
+        Command::Mv(command) => {
+            crate::mv::mv(command, &config, ARGS.global.config_file.as_deref())?
+        }
+        Command::Config(command) => crate::config::config(command, &config)?,
+        Command::Lsp(command) => crate::lsp::lsp(command, &config)?,
         // Command::Watch(command) => crate::watch::watch(&mut timer, command)?,
         // Command::Serve(command) => todo!(),
-        // Command::Init(command) => crate::init::init(command)?,
         // Command::Query(command) => crate::query::query(command)?,
         // Command::Fonts(command) => crate::fonts::fonts(command),
         // Command::Update(command) => crate::update::update(command)?,
@@ -76,6 +93,17 @@ fn weibian_version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
 
+/// Print an application-level warning (independent from a source file).
+fn print_warning(msg: &str) {
+    let styles = term::Styles::default();
+
+    let mut output = terminal::out();
+    let _ = output.set_color(&styles.header_warning);
+    let _ = write!(output, "warning");
+    let _ = output.reset();
+    let _ = writeln!(output, ": {msg}");
+}
+
 /// Print an application-level error (independent from a source file).
 fn print_error(msg: &str) -> io::Result<()> {
     let styles = term::Styles::default();
@@ -0,0 +1,414 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ecow::eco_format;
+
+use crate::args::{CompileArgs, MvCommand};
+use crate::compiler::CliTypstCompiler;
+use crate::config::{BuildConfig, WeibianConfig, config_path};
+use crate::error::StrResult;
+use crate::frontend;
+use crate::html::{
+    HtmlNote, is_note_id_char, normalize_target, referenced_targets, validate_note_id,
+};
+
+/// A change to a file.
+struct Edit {
+    path: PathBuf,
+    before: String,
+    after: String,
+}
+
+/// Renames a note: its ID, its file and every reference to it.
+pub fn mv(
+    command: &MvCommand,
+    config: &WeibianConfig,
+    config_file: Option<&Path>,
+) -> StrResult<()> {
+    let (old, new) = (command.old.as_str(), command.new.as_str());
+    validate_note_id(new)?;
+    if old == new {
+        return Err(eco_format!("note {old} already has this ID"));
+    }
+
+    let args = CompileArgs {
+        sources: command.sources.clone(),
+        ..CompileArgs::default()
+    };
+    let build_config = BuildConfig::from(&args, config)?;
+    let html_notes = frontend::compile_html(&build_config, &CliTypstCompiler)?;
+    let note = html_notes
+        .iter()
+        .find(|note| note.id == old)
+        .ok_or_else(|| eco_format!("note {old} does not exist"))?;
+    if html_notes.iter().any(|note| note.id == new) {
+        return Err(eco_format!("note {new} already exists"));
+    }
+    if let Some(target) = build_config.aliases.get(new) {
+        return Err(eco_format!(
+            "{new} is an alias of {target} in the configuration; remove it first"
+        ));
+    }
+
+    let mut edits = Vec::new();
+    let mut unresolved = Vec::new();
+    for referencing in html_notes.iter().filter(|other| refers_to(other, old)) {
+        if referencing.source_path == note.source_path {
+            continue;
+        }
+        let before = read(&referencing.source_path)?;
+        let after = replace_references(&before, old, new);
+        if after == before {
+            unresolved.push(referencing.source_path.display().to_string());
+            continue;
+        }
+        edits.push(Edit {
+            path: referencing.source_path.clone(),
+            before,
+            after,
+        });
+    }
+
+    let before = read(&note.source_path)?;
+    let with_references = replace_references(&before, old, new);
+    let after = replace_identifier(&with_references, old, new);
+    if after == with_references {
+        return Err(eco_format!(
+            "cannot find the identifier of {old} in {}",
+            note.source_path.display()
+        ));
+    }
+    edits.push(Edit {
+        path: note.source_path.clone(),
+        before,
+        after,
+    });
+
+    let config_path = config_path(config_file);
+    let config_source = fs::read_to_string(&config_path).unwrap_or_default();
+    let retargeted = build_config
+        .aliases
+        .values()
+        .any(|target| normalize_target(target) == old);
+    if command.alias || retargeted {
+        let mut after = retarget_aliases(&config_source, old, new);
+        if command.alias {
+            after = add_alias(&after, old, new);
+        }
+        edits.push(Edit {
+            path: config_path,
+            before: config_source,
+            after,
+        });
+    }
+
+    let rename = renamed_path(&note.source_path, old, new)?;
+
+    if command.dry_run {
+        for edit in &edits {
+            print!("{}", unified_diff(&edit.path, &edit.before, &edit.after));
+        }
+        if let Some(to) = &rename {
+            println!("rename {} -> {}", note.source_path.display(), to.display());
+        }
+    } else {
+        for edit in &edits {
+            fs::write(&edit.path, &edit.after)
+                .map_err(|err| eco_format!("failed to write {}: {err}", edit.path.display()))?;
+            println!("updated {}", edit.path.display());
+        }
+        if let Some(to) = &rename {
+            fs::rename(&note.source_path, to).map_err(|err| {
+                eco_format!(
+                    "failed to rename {} to {}: {err}",
+                    note.source_path.display(),
+                    to.display()
+                )
+            })?;
+            println!("renamed {} -> {}", note.source_path.display(), to.display());
+        }
+    }
+
+    for path in unresolved {
+        crate::print_warning(&eco_format!(
+            "{path} refers to {old}, but not with a \"wb:{old}\" literal; update it by hand"
+        ));
+    }
+    Ok(())
+}
+
+fn read(path: &Path) -> StrResult<String> {
+    fs::read_to_string(path).map_err(|err| eco_format!("failed to read {}: {err}", path.display()))
+}
+
+/// Whether a note links to, cites or transcludes another note, by its ID.
+fn refers_to(note: &HtmlNote, id: &str) -> bool {
+    referenced_targets(&note.document)
+        .iter()
+        .any(|target| target == id)
+}
+
+/// Replaces the `wb:<old>` references in a source, keeping their fragments.
+fn replace_references(source: &str, old: &str, new: &str) -> String {
+    let pattern = format!("wb:{old}");
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find(&pattern) {
+        let end = start + pattern.len();
+        out.push_str(&rest[..start]);
+        if rest[end..].starts_with(is_note_id_char) {
+            out.push_str(&pattern);
+        } else {
+            out.push_str("wb:");
+            out.push_str(new);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replaces the value of the `identifier` field of a source.
+fn replace_identifier(source: &str, old: &str, new: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("identifier") {
+        let end = start + "identifier".len();
+        out.push_str(&rest[..end]);
+        rest = &rest[end..];
+        let after_key = rest.trim_start();
+        let Some(after_colon) = after_key.strip_prefix(':') else {
+            continue;
+        };
+        let value = after_colon.trim_start();
+        let quoted = format!("\"{old}\"");
+        if value.starts_with(&quoted) {
+            let prefix_len = rest.len() - value.len();
+            out.push_str(&rest[..prefix_len]);
+            out.push_str(&format!("\"{new}\""));
+            rest = &value[quoted.len()..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Points the configured aliases of a note to its new ID.
+fn retarget_aliases(config: &str, old: &str, new: &str) -> String {
+    let mut in_aliases = false;
+    let mut out = String::with_capacity(config.len());
+    for line in config.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_aliases = trimmed == "[aliases]";
+        }
+        let target = format!("\"{old}\"");
+        if in_aliases && let Some((key, value)) = line.split_once('=') {
+            // Targets may be written with or without the `wb:` prefix.
+            let value = if value.trim_start().starts_with(&target) {
+                value.replacen(&target, &format!("\"{new}\""), 1)
+            } else {
+                replace_references(value, old, new)
+            };
+            out.push_str(&format!("{key}={value}"));
+        } else {
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+/// Adds an alias from the old ID to the new one to the configuration.
+fn add_alias(config: &str, old: &str, new: &str) -> String {
+    let entry = format!("\"{old}\" = \"{new}\"\n");
+    let mut out = String::with_capacity(config.len() + entry.len());
+    let mut added = false;
+    for line in config.split_inclusive('\n') {
+        out.push_str(line);
+        if !added && line.trim() == "[aliases]" {
+            if !line.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&entry);
+            added = true;
+        }
+    }
+    if !added {
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str("[aliases]\n");
+        out.push_str(&entry);
+    }
+    out
+}
+
+/// The new path of a source named `@@<old>==<taxon>--<slug>.typ`, if it is
+/// named after the ID.
+fn renamed_path(path: &Path, old: &str, new: &str) -> StrResult<Option<PathBuf>> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(None);
+    };
+    let Some(rest) = name.strip_prefix(&format!("@@{old}")) else {
+        return Ok(None);
+    };
+    if !["==", "--", ".typ"]
+        .iter()
+        .any(|separator| rest.starts_with(separator))
+    {
+        return Ok(None);
+    }
+    // Namespaces are not part of file names.
+    let new_name = new.rsplit('/').next().unwrap_or(new);
+    let to = path.with_file_name(format!("@@{new_name}{rest}"));
+    if to.exists() {
+        return Err(eco_format!("{} already exists", to.display()));
+    }
+    Ok(Some(to))
+}
+
+/// A unified diff of two versions of a file, without context lines.
+fn unified_diff(path: &Path, before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+
+    // The longest common subsequence of lines, from the end.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = format!("--- {0}\n+++ {0}\n", path.display());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let (start_old, start_new) = (i, j);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                break;
+            }
+            if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+                j += 1;
+            } else {
+                i += 1;
+            }
+        }
+        // Empty ranges start at the line before them.
+        let start = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start(start_old, i - start_old),
+            i - start_old,
+            start(start_new, j - start_new),
+            j - start_new
+        ));
+        for line in &old[start_old..i] {
+            out.push_str(&format!("-{line}\n"));
+        }
+        for line in &new[start_new..j] {
+            out.push_str(&format!("+{line}\n"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_references() {
+        let source = "#ln(\"wb:0001\")[] #ln(\"wb:0001#intro\")[] wb:00012 wb:0001/x";
+        assert_eq!(
+            replace_references(source, "0001", "math/0002"),
+            "#ln(\"wb:math/0002\")[] #ln(\"wb:math/0002#intro\")[] wb:00012 wb:0001/x"
+        );
+        assert_eq!(
+            replace_references("no references", "0001", "0002"),
+            "no references"
+        );
+    }
+
+    #[test]
+    fn test_replace_identifier() {
+        let source = "#show: template(identifier: \"0001\")\n// identifier: \"00011\"\n#let identifier = 1\n";
+        assert_eq!(
+            replace_identifier(source, "0001", "0002"),
+            "#show: template(identifier: \"0002\")\n// identifier: \"00011\"\n#let identifier = 1\n"
+        );
+        assert_eq!(
+            replace_identifier("identifier  :  \"0001\"", "0001", "0002"),
+            "identifier  :  \"0002\""
+        );
+    }
+
+    #[test]
+    fn test_retarget_aliases() {
+        let config = "[site]\nold = \"0001\"\n\n[aliases]\nold = \"0001\"\nolder = \"wb:0001\"\nother = \"0003\"\n";
+        assert_eq!(
+            retarget_aliases(config, "0001", "0002"),
+            "[site]\nold = \"0001\"\n\n[aliases]\nold = \"0002\"\nolder = \"wb:0002\"\nother = \"0003\"\n"
+        );
+    }
+
+    #[test]
+    fn test_add_alias() {
+        assert_eq!(
+            add_alias("[aliases]\nx = \"0003\"\n", "0001", "0002"),
+            "[aliases]\n\"0001\" = \"0002\"\nx = \"0003\"\n"
+        );
+        assert_eq!(
+            add_alias("[site]\nhome = \"index\"", "0001", "0002"),
+            "[site]\nhome = \"index\"\n\n[aliases]\n\"0001\" = \"0002\"\n"
+        );
+        assert_eq!(
+            add_alias("", "0001", "0002"),
+            "[aliases]\n\"0001\" = \"0002\"\n"
+        );
+    }
+
+    #[test]
+    fn test_renamed_path() {
+        let renamed = |name: &str, new: &str| {
+            renamed_path(&Path::new("/nonexistent/typ").join(name), "0001", new).unwrap()
+        };
+        assert_eq!(
+            renamed("@@0001==thm--intro.typ", "0002"),
+            Some(PathBuf::from("/nonexistent/typ/@@0002==thm--intro.typ"))
+        );
+        assert_eq!(
+            renamed("@@0001.typ", "math/0002"),
+            Some(PathBuf::from("/nonexistent/typ/@@0002.typ"))
+        );
+        assert_eq!(renamed("@@00011.typ", "0002"), None);
+        assert_eq!(renamed("notes.typ", "0002"), None);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff(Path::new("typ/a.typ"), "a\nb\nc\n", "a\nB\nc\nd\n");
+        assert_eq!(
+            diff,
+            "--- typ/a.typ\n+++ typ/a.typ\n@@ -2,1 +2,1 @@\n-b\n+B\n@@ -3,0 +4,1 @@\n+d\n"
+        );
+        let diff = unified_diff(Path::new("x"), "a\nb\n", "b\n");
+        assert_eq!(diff, "--- x\n+++ x\n@@ -1,1 +0,0 @@\n-a\n");
+        assert_eq!(
+            unified_diff(Path::new("x"), "same\n", "same\n"),
+            "--- x\n+++ x\n"
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{Datelike, Local, NaiveDate, Timelike};
+use ecow::eco_format;
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::args::{CompileArgs, NewCommand, SourceArgs};
+use crate::config::{BuildConfig, WeibianConfig};
+use crate::error::StrResult;
+use crate::frontend::{self, file_name_id, source_ids};
+use crate::permalink::slugify;
+
+/// The skeleton of new notes, unless one is configured.
+const DEFAULT_SKELETON: &str = r#"#import "/_template/template.typ": template, tr, ln, ct, inline-tree
+#show: template(
+  title:      [{{ title }}],
+  date:       {{ date }},
+  tags:       (),
+  identifier: "{{ identifier }}",
+{%- if taxon %}
+  taxon:      "{{ taxon }}",
+{%- endif %}
+)
+
+"#;
+
+/// The width sequential IDs are padded to, e.g. `000B`.
+const ID_WIDTH: usize = 4;
+
+/// What the skeleton of a new note is filled with.
+#[derive(Serialize)]
+struct SkeletonContext {
+    /// The title, escaped for Typst markup.
+    title: String,
+    identifier: String,
+    /// The taxon, escaped for a Typst string.
+    taxon: Option<String>,
+    /// The creation time, as a Typst `datetime` expression.
+    date: String,
+    journal: bool,
+}
+
+/// Creates a note with the next free ID and prints its path.
+pub fn new(command: &NewCommand, config: &WeibianConfig) -> StrResult<()> {
+    let args = CompileArgs {
+        sources: SourceArgs {
+            input: command.input.clone(),
+            profile: command.profile.clone(),
+        },
+        ..CompileArgs::default()
+    };
+    let build_config = BuildConfig::from(&args, config)?;
+    let input_dir = &build_config.input_directory;
+    let sources = frontend::collect_typst_sources(&build_config)?;
+
+    let now = Local::now();
+    let title = match &command.title {
+        Some(title) => title.clone(),
+        None => now.format("%Y-%m-%d").to_string(),
+    };
+    let slug = slugify(&title);
+    if slug.is_empty() {
+        return Err(eco_format!(
+            "cannot derive a file name from the title \"{title}\""
+        ));
+    }
+    let dir = match &command.dir {
+        Some(dir) => input_dir.join(dir),
+        None if command.journal => input_dir.join(
+            config
+                .new
+                .journal_dir
+                .as_deref()
+                .unwrap_or(Path::new("journal")),
+        ),
+        None => input_dir.clone(),
+    };
+
+    // There is one journal note a day.
+    if command.journal
+        && let Some(existing) = find_note_of_day(&sources, &dir, now.date_naive())?
+    {
+        println!("{}", existing.display());
+        return Ok(());
+    }
+
+    let identifier = next_id(&sources, &build_config.aliases)?;
+    let mut file_name = format!("@@{identifier}");
+    if let Some(taxon) = command.taxon.as_deref().map(taxon_file_name)
+        && !taxon.is_empty()
+    {
+        file_name.push_str("==");
+        file_name.push_str(&taxon);
+    }
+    file_name.push_str(&format!("--{slug}.typ"));
+    let path = dir.join(file_name);
+
+    let skeleton_path = if command.journal {
+        config
+            .new
+            .journal_skeleton
+            .as_ref()
+            .or(config.new.skeleton.as_ref())
+    } else {
+        config.new.skeleton.as_ref()
+    };
+    let skeleton = match skeleton_path {
+        Some(path) => {
+            let path = input_dir.join(path);
+            fs::read_to_string(&path).map_err(|err| {
+                eco_format!("failed to read note skeleton {}: {err}", path.display())
+            })?
+        }
+        None => DEFAULT_SKELETON.to_string(),
+    };
+    let context = SkeletonContext {
+        title: escape_markup(&title),
+        identifier,
+        taxon: command.taxon.as_deref().map(escape_string),
+        date: format!(
+            "datetime(year: {}, month: {:02}, day: {:02}, hour: {:02}, minute: {:02}, second: {:02})",
+            now.year(),
+            now.month(),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second()
+        ),
+        journal: command.journal,
+    };
+    let context = Context::from_serialize(&context)
+        .map_err(|err| eco_format!("failed to prepare note skeleton: {err}"))?;
+    let source = Tera::one_off(&skeleton, &context, false)
+        .map_err(|err| eco_format!("failed to render note skeleton: {err}"))?;
+
+    fs::create_dir_all(&dir)
+        .map_err(|err| eco_format!("failed to create directory {}: {err}", dir.display()))?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|err| eco_format!("failed to create note {}: {err}", path.display()))?;
+    file.write_all(source.as_bytes())
+        .map_err(|err| eco_format!("failed to write note {}: {err}", path.display()))?;
+
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// The ID following the largest sequential ID in use, in the file names and
+/// `identifier` fields of the sources, or as a configured alias.
+fn next_id(sources: &[PathBuf], aliases: &BTreeMap<String, String>) -> StrResult<String> {
+    let mut ids: Vec<String> = aliases.keys().cloned().collect();
+    for path in sources {
+        if let Some(id) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(file_name_id)
+        {
+            ids.push(id.to_string());
+        }
+        ids.extend(source_ids(&read(path)?));
+    }
+
+    let last = ids
+        .iter()
+        .filter_map(|id| parse_sequential_id(id).map(|value| (value, id.len())))
+        .max();
+    Ok(match last {
+        Some((value, width)) => format_sequential_id(value + 1, width.max(ID_WIDTH)),
+        None => format_sequential_id(0, ID_WIDTH),
+    })
+}
+
+/// The value of an ID made of digits and uppercase letters, read in base 36.
+fn parse_sequential_id(id: &str) -> Option<u64> {
+    if id.is_empty()
+        || id.len() > 12
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+    {
+        return None;
+    }
+    u64::from_str_radix(id, 36).ok()
+}
+
+fn format_sequential_id(mut value: u64, width: usize) -> String {
+    let mut digits = Vec::new();
+    while value > 0 {
+        let digit = (value % 36) as u32;
+        digits.push(
+            char::from_digit(digit, 36)
+                .unwrap_or('0')
+                .to_ascii_uppercase(),
+        );
+        value /= 36;
+    }
+    while digits.len() < width {
+        digits.push('0');
+    }
+    digits.iter().rev().collect()
+}
+
+/// The journal note of a day in a directory: the note whose `date` field is
+/// on that day, or whose file name ends with the date, as when it is titled
+/// with it.
+fn find_note_of_day(sources: &[PathBuf], dir: &Path, day: NaiveDate) -> StrResult<Option<PathBuf>> {
+    let suffix = format!("--{}.typ", day.format("%Y-%m-%d"));
+    for path in sources.iter().filter(|path| path.parent() == Some(dir)) {
+        let named_after_day = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("@@") && name.ends_with(&suffix));
+        if named_after_day || source_date(&read(path)?) == Some(day) {
+            return Ok(Some(path.clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// The day of the `date` field of a source, when it is a `datetime(...)`
+/// expression with a year, a month and a day.
+fn source_date(source: &str) -> Option<NaiveDate> {
+    let mut rest = source;
+    while let Some(start) = rest.find("date") {
+        rest = &rest[start + "date".len()..];
+        let Some(value) = rest.trim_start().strip_prefix(':') else {
+            continue;
+        };
+        let Some(arguments) = value.trim_start().strip_prefix("datetime(") else {
+            continue;
+        };
+        let arguments = &arguments[..arguments.find(')')?];
+        let mut parts = (None, None, None);
+        for argument in arguments.split(',') {
+            let Some((name, value)) = argument.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim() {
+                "year" => parts.0 = value.parse().ok(),
+                "month" => parts.1 = value.parse().ok(),
+                "day" => parts.2 = value.parse().ok(),
+                _ => {}
+            }
+        }
+        return NaiveDate::from_ymd_opt(parts.0?, parts.1?, parts.2?);
+    }
+    None
+}
+
+/// A taxon as part of a file name, e.g. `Person` in
+/// `@@0001==Person--jane-doe.typ`. Its case is kept; whitespace and the
+/// characters that file names cannot contain, or that separate the parts of
+/// the name, become hyphens.
+fn taxon_file_name(taxon: &str) -> String {
+    let mut name = String::new();
+    for c in taxon.trim().chars() {
+        let separator = c.is_whitespace()
+            || c.is_control()
+            || matches!(
+                c,
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '=' | '-'
+            );
+        if !separator {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    name.trim_end_matches('-').to_string()
+}
+
+fn read(path: &Path) -> StrResult<String> {
+    fs::read_to_string(path).map_err(|err| eco_format!("failed to read {}: {err}", path.display()))
+}
+
+/// Escapes text for Typst markup.
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '[' | ']' | '#' | '*' | '_' | '`' | '$' | '@' | '<' | '>' | '~' | '/'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escapes text for a Typst string literal.
+fn escape_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sequential_id() {
+        assert_eq!(parse_sequential_id("0000"), Some(0));
+        assert_eq!(parse_sequential_id("000Z"), Some(35));
+        assert_eq!(parse_sequential_id("0010"), Some(36));
+        assert_eq!(parse_sequential_id("000b"), None);
+        assert_eq!(parse_sequential_id("math/0001"), None);
+        assert_eq!(parse_sequential_id("index"), None);
+        assert_eq!(parse_sequential_id(""), None);
+        assert_eq!(parse_sequential_id("0000000000000"), None);
+    }
+
+    #[test]
+    fn test_format_sequential_id() {
+        assert_eq!(format_sequential_id(0, ID_WIDTH), "0000");
+        assert_eq!(format_sequential_id(35, ID_WIDTH), "000Z");
+        assert_eq!(format_sequential_id(36, ID_WIDTH), "0010");
+        assert_eq!(format_sequential_id(36u64.pow(4), ID_WIDTH), "10000");
+    }
+
+    #[test]
+    fn test_taxon_file_name() {
+        assert_eq!(taxon_file_name("Person"), "Person");
+        assert_eq!(taxon_file_name(" Research Note "), "Research-Note");
+        assert_eq!(taxon_file_name("Q&A: Math/Logic"), "Q&A-Math-Logic");
+        assert_eq!(taxon_file_name("a--b==c"), "a-b-c");
+        assert_eq!(taxon_file_name("?"), "");
+    }
+
+    #[test]
+    fn test_next_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let sources = [
+            dir.path().join("@@0009--nine.typ"),
+            dir.path().join("notes.typ"),
+        ];
+        fs::write(&sources[0], "#show: template(identifier: \"0009\")").unwrap();
+        fs::write(&sources[1], "#show: template(identifier: \"000Z\")").unwrap();
+        let aliases = BTreeMap::from([("00ZZ".to_string(), "0009".to_string())]);
+
+        assert_eq!(next_id(&sources, &BTreeMap::new()).unwrap(), "0010");
+        assert_eq!(next_id(&sources, &aliases).unwrap(), "0100");
+        assert_eq!(next_id(&[], &BTreeMap::new()).unwrap(), "0000");
+    }
+}
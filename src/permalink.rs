@@ -0,0 +1,637 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use ecow::eco_format;
+use ego_tree::NodeId;
+use scraper::Node;
+
+use crate::config::SiteSettings;
+use crate::error::StrResult;
+use crate::html::HtmlNote;
+
+/// The tags whose `target` attribute names a note.
+pub const TARGET_TAGS: [&str; 3] = ["wb-internal-link", "wb-cite", "wb-transclusion"];
+
+/// The former IDs of notes, from the `aliases` metadata of the notes and the
+/// `[aliases]` table of the configuration.
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    targets: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn collect(notes: &[HtmlNote], configured: &BTreeMap<String, String>) -> StrResult<Self> {
+        let note_ids: HashSet<&str> = notes.iter().map(|note| note.id.as_str()).collect();
+        let mut declared = Vec::new();
+        for note in notes {
+            let metadata = crate::html::extract_metadata(&note.document)?;
+            if let Some(value) = metadata.get("aliases") {
+                for alias in value.split(',').map(crate::html::normalize_target) {
+                    if !alias.is_empty() {
+                        declared.push((alias, note.id.clone()));
+                    }
+                }
+            }
+        }
+        for (alias, target) in configured {
+            let target = crate::html::normalize_target(target);
+            if !note_ids.contains(target.as_str()) {
+                return Err(eco_format!(
+                    "alias {alias} in the configuration refers to note {target}, which does not exist"
+                ));
+            }
+            declared.push((crate::html::normalize_target(alias), target));
+        }
+
+        let mut aliases = Self::default();
+        for (alias, target) in declared {
+            crate::html::validate_note_id(&alias)
+                .map_err(|err| eco_format!("alias of {target}: {err}"))?;
+            if note_ids.contains(alias.as_str()) {
+                return Err(eco_format!(
+                    "alias {alias} of {target} is the ID of an existing note"
+                ));
+            }
+            if let Some(other) = aliases.targets.insert(alias.clone(), target.clone())
+                && other != target
+            {
+                return Err(eco_format!(
+                    "alias {alias} refers to both {other} and {target}"
+                ));
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// The note an alias stands for.
+    pub fn resolve(&self, alias: &str) -> Option<&str> {
+        self.targets.get(alias).map(String::as_str)
+    }
+
+    /// Every alias with the note it stands for.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.targets
+            .iter()
+            .map(|(alias, target)| (alias.as_str(), target.as_str()))
+    }
+
+    /// Replaces aliases used as the target of links, citations and
+    /// transclusions, or in `wb:` hrefs, by the IDs they stand for. Returns a
+    /// message for every replaced alias.
+    pub fn resolve_targets(&self, notes: &mut [HtmlNote]) -> Vec<String> {
+        let mut replaced = Vec::new();
+        if self.targets.is_empty() {
+            return replaced;
+        }
+        for note in notes {
+            let node_ids: Vec<NodeId> = note.document.tree.nodes().map(|node| node.id()).collect();
+            for node_id in node_ids {
+                let Some(mut node) = note.document.tree.get_mut(node_id) else {
+                    continue;
+                };
+                let Node::Element(element) = node.value() else {
+                    continue;
+                };
+                let (attr, prefix) = if TARGET_TAGS
+                    .iter()
+                    .any(|tag| element.name().eq_ignore_ascii_case(tag))
+                {
+                    ("target", "")
+                } else {
+                    ("href", "wb:")
+                };
+                let Some(rest) = element
+                    .attr(attr)
+                    .and_then(|value| value.trim().strip_prefix(prefix))
+                else {
+                    continue;
+                };
+                let (target, fragment) = match rest.find('#') {
+                    Some(index) => rest.split_at(index),
+                    None => (rest, ""),
+                };
+                let alias = crate::html::normalize_target(target);
+                let Some(resolved) = self.resolve(&alias) else {
+                    continue;
+                };
+                let value = format!("wb:{resolved}{fragment}");
+                replaced.push(format!(
+                    "{} refers to {resolved} by its alias {alias}",
+                    note.id
+                ));
+                crate::html::set_attr(element, attr, &value);
+            }
+        }
+        replaced
+    }
+}
+
+/// A page redirecting from the former URL of a note to its page.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub alias: String,
+    pub target: String,
+    path: String,
+}
+
+/// The pages of the notes, i.e., where each note is written and linked to.
+///
+/// The path of a page is relative to the root directory of the site. It ends
+/// with a slash if the page is written as `index.html` in a directory of its
+/// own, and is empty for the root of the site.
+#[derive(Debug, Clone, Default)]
+pub struct Permalinks {
+    paths: HashMap<String, String>,
+    aliases: Aliases,
+    redirects: Vec<Redirect>,
+    trailing_slash: bool,
+}
+
+impl Permalinks {
+    /// Fills the permalink pattern of the site, or the `permalink` metadata
+    /// of a note if it has one, for every note.
+    ///
+    /// The redirect page of an alias is written where the site's pattern puts
+    /// a note with the alias as its ID, filled with the metadata of the note
+    /// the alias stands for.
+    pub fn collect(notes: &[HtmlNote], site: &SiteSettings, aliases: &Aliases) -> StrResult<Self> {
+        let default_pattern = site.permalink.clone().unwrap_or_else(|| {
+            if site.trailing_slash {
+                "/{id}/".to_string()
+            } else {
+                "/{id}.html".to_string()
+            }
+        });
+
+        let mut permalinks = Self {
+            paths: HashMap::new(),
+            aliases: aliases.clone(),
+            redirects: Vec::new(),
+            trailing_slash: site.trailing_slash,
+        };
+        let mut owners: BTreeMap<PathBuf, &str> = BTreeMap::new();
+        let mut metadata_by_note = HashMap::new();
+        for note in notes {
+            let metadata = crate::html::extract_metadata(&note.document)?;
+            let path = match metadata.get("permalink") {
+                Some(pattern) => permalinks.fill(pattern, note, &note.id, &metadata)?,
+                None if note.id == site.home => String::new(),
+                None => permalinks.fill(&default_pattern, note, &note.id, &metadata)?,
+            };
+
+            let output = Self::relative_output_path(&path);
+            if let Some(other) = owners.insert(output.clone(), note.id.as_str()) {
+                return Err(eco_format!(
+                    "notes {other} and {} are both written to {}",
+                    note.id,
+                    output.display()
+                ));
+            }
+            permalinks.paths.insert(note.id.clone(), path);
+            metadata_by_note.insert(note.id.as_str(), (note, metadata));
+        }
+
+        for (alias, target) in aliases.iter() {
+            let Some((note, metadata)) = metadata_by_note.get(target) else {
+                continue;
+            };
+            let path = permalinks.fill(&default_pattern, note, alias, metadata)?;
+            // Patterns without `{id}` put an alias at the page itself.
+            if path == permalinks.paths[target] {
+                continue;
+            }
+            let output = Self::relative_output_path(&path);
+            if let Some(other) = owners.insert(output.clone(), alias) {
+                return Err(eco_format!(
+                    "the redirect page of alias {alias} of {target} and the page of {other} are both written to {}",
+                    output.display()
+                ));
+            }
+            permalinks.redirects.push(Redirect {
+                alias: alias.to_string(),
+                target: target.to_string(),
+                path,
+            });
+        }
+        Ok(permalinks)
+    }
+
+    /// The root-absolute href of the page of a note, or of the note an alias
+    /// stands for.
+    pub fn href(&self, note_id: &str, site: &SiteSettings) -> String {
+        let note_id = self.aliases.resolve(note_id).unwrap_or(note_id);
+        format!("{}{}", site.root_dir, self.path(note_id))
+    }
+
+    /// The redirect pages of the aliases.
+    pub fn redirects(&self) -> &[Redirect] {
+        &self.redirects
+    }
+
+    /// Where the redirect page of an alias is written.
+    pub fn redirect_output_path(&self, output_dir: &Path, redirect: &Redirect) -> PathBuf {
+        output_dir.join(Self::relative_output_path(&redirect.path))
+    }
+
+    /// Where the page of a note is written.
+    pub fn output_path(&self, output_dir: &Path, note_id: &str) -> PathBuf {
+        output_dir.join(Self::relative_output_path(&self.path(note_id)))
+    }
+
+    /// The root-absolute href of the page of every note, keyed by ID, and of
+    /// the note every alias stands for, keyed by the alias.
+    pub fn hrefs(&self, site: &SiteSettings) -> BTreeMap<String, String> {
+        self.paths
+            .keys()
+            .map(String::as_str)
+            .chain(self.aliases.iter().map(|(alias, _)| alias))
+            .map(|id| (id.to_string(), self.href(id, site)))
+            .collect()
+    }
+
+    fn path(&self, note_id: &str) -> String {
+        match self.paths.get(note_id) {
+            Some(path) => path.clone(),
+            None if self.trailing_slash => format!("{note_id}/"),
+            None => format!("{note_id}.html"),
+        }
+    }
+
+    fn relative_output_path(path: &str) -> PathBuf {
+        if path.is_empty() || path.ends_with('/') {
+            Path::new(path).join("index.html")
+        } else {
+            PathBuf::from(path)
+        }
+    }
+
+    /// Fills the placeholders of a permalink pattern with the metadata of a
+    /// note.
+    fn fill(
+        &self,
+        pattern: &str,
+        note: &HtmlNote,
+        id: &str,
+        metadata: &HashMap<String, String>,
+    ) -> StrResult<String> {
+        let mut path = String::new();
+        let mut rest = pattern;
+        while let Some(start) = rest.find('{') {
+            path.push_str(&rest[..start]);
+            let end = rest[start..].find('}').ok_or_else(|| {
+                eco_format!("unclosed placeholder in permalink pattern \"{pattern}\"")
+            })? + start;
+            let name = &rest[start + 1..end];
+            let value = placeholder_value(name, note, id, metadata).map_err(|err| {
+                eco_format!("failed to fill permalink \"{pattern}\" for {id}: {err}")
+            })?;
+            path.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        path.push_str(rest);
+
+        // Placeholders without a value leave empty segments, which are
+        // dropped, e.g. `/{taxon}/{id}/` becomes `/0001/` for a note without
+        // a taxon.
+        let is_directory = path.ends_with('/');
+        let segments: Vec<&str> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+        if segments
+            .iter()
+            .any(|segment| *segment == "." || *segment == "..")
+        {
+            return Err(eco_format!(
+                "invalid permalink \"{path}\" for {id}: \".\" and \"..\" segments are not allowed"
+            ));
+        }
+        let mut path = segments.join("/");
+        if path.is_empty() {
+            return Ok(path);
+        }
+        if is_directory {
+            path.push('/');
+        } else if !path.ends_with(".html") {
+            path.push_str(if self.trailing_slash { "/" } else { ".html" });
+        }
+        Ok(path)
+    }
+}
+
+fn placeholder_value(
+    name: &str,
+    note: &HtmlNote,
+    id: &str,
+    metadata: &HashMap<String, String>,
+) -> StrResult<String> {
+    let date = || {
+        let Some(date) = metadata.get("date") else {
+            return Ok(Default::default());
+        };
+        let parts: Vec<&str> = date.get(..10).unwrap_or_default().split('-').collect();
+        match parts.as_slice() {
+            [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => {
+                Ok((year.to_string(), month.to_string(), day.to_string()))
+            }
+            _ => Err(eco_format!("date \"{date}\" is not of the form YYYY-MM-DD")),
+        }
+    };
+    let optional = |key: &str| metadata.get(key).map(String::as_str).unwrap_or_default();
+    Ok(match name {
+        "id" => id.to_string(),
+        "slug" => note_slug(note, metadata)?,
+        "taxon" => slugify(optional("taxon")),
+        "lang" => slugify(optional("lang")),
+        "yyyy" => date()?.0,
+        "mm" => date()?.1,
+        "dd" => date()?.2,
+        _ => {
+            return Err(eco_format!(
+                "unknown placeholder {{{name}}} (expected one of {{id}}, {{slug}}, {{taxon}}, {{lang}}, {{yyyy}}, {{mm}} or {{dd}})"
+            ));
+        }
+    })
+}
+
+/// The slug of a note: its `slug` metadata, the part of its file name after
+/// `--` (as in `@@0008--writing-in-weibian.typ`), its title, or its ID.
+fn note_slug(note: &HtmlNote, metadata: &HashMap<String, String>) -> StrResult<String> {
+    if let Some(slug) = metadata.get("slug") {
+        return Ok(slugify(slug));
+    }
+    let from_filename = note
+        .source_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.split_once("--"))
+        .map(|(_, slug)| slugify(slug));
+    if let Some(slug) = from_filename.filter(|slug| !slug.is_empty()) {
+        return Ok(slug);
+    }
+    let title = crate::html::extract_note_title(&note.document, metadata)?;
+    Ok(match title.map(|title| slugify(&title)) {
+        Some(slug) if !slug.is_empty() => slug,
+        _ => note.id.clone(),
+    })
+}
+
+/// Lowercases a text and joins its words with hyphens.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A note with `meta` elements for the given metadata.
+    fn note(source: &str, id: &str, metadata: &[(&str, &str)]) -> HtmlNote {
+        let meta: String = metadata
+            .iter()
+            .map(|(name, content)| format!("<meta name=\"{name}\" content=\"{content}\">"))
+            .collect();
+        HtmlNote {
+            id: id.to_string(),
+            source_path: PathBuf::from(source),
+            document: scraper::Html::parse_document(&format!(
+                "<html><head>{meta}</head><body></body></html>"
+            )),
+        }
+    }
+
+    fn site(permalink: Option<&str>, trailing_slash: bool) -> SiteSettings {
+        SiteSettings {
+            domain: None,
+            root_dir: "/".into(),
+            trailing_slash,
+            permalink: permalink.map(str::to_string),
+            home: "index".into(),
+            not_found: false,
+        }
+    }
+
+    fn fill(pattern: &str, note: &HtmlNote, trailing_slash: bool) -> StrResult<String> {
+        let permalinks = Permalinks {
+            trailing_slash,
+            ..Default::default()
+        };
+        let metadata = crate::html::extract_metadata(&note.document)?;
+        permalinks.fill(pattern, note, &note.id, &metadata)
+    }
+
+    #[test]
+    fn test_fill_placeholders() {
+        let note = note(
+            "typ/@@0008--writing-in-weibian.typ",
+            "0008",
+            &[
+                ("taxon", "Tutorial Note"),
+                ("date", "2024-03-05T10:00"),
+                ("lang", "en"),
+            ],
+        );
+        assert_eq!(
+            fill("/{taxon}/{yyyy}/{mm}/{dd}/{id}/", &note, false).unwrap(),
+            "tutorial-note/2024/03/05/0008/"
+        );
+        assert_eq!(
+            fill("/{lang}/{slug}", &note, false).unwrap(),
+            "en/writing-in-weibian.html"
+        );
+        assert_eq!(fill("/{id}", &note, true).unwrap(), "0008/");
+        assert_eq!(
+            fill("/notes/{id}.html", &note, true).unwrap(),
+            "notes/0008.html"
+        );
+    }
+
+    #[test]
+    fn test_fill_drops_empty_segments() {
+        let note = note("typ/0001.typ", "0001", &[]);
+        assert_eq!(fill("/{taxon}/{id}/", &note, false).unwrap(), "0001/");
+        assert_eq!(fill("/{taxon}/", &note, false).unwrap(), "");
+    }
+
+    #[test]
+    fn test_fill_slugifies_lang() {
+        let tagged = note("typ/0001.typ", "0001", &[("lang", " zh_Hans ")]);
+        assert_eq!(
+            fill("/{lang}/{id}/", &tagged, false).unwrap(),
+            "zh-hans/0001/"
+        );
+        let unsafe_lang = note("typ/0001.typ", "0001", &[("lang", "../en/x")]);
+        assert_eq!(
+            fill("/{lang}/{id}/", &unsafe_lang, false).unwrap(),
+            "en-x/0001/"
+        );
+    }
+
+    #[test]
+    fn test_fill_slug_fallbacks() {
+        let titled = note("typ/0001.typ", "0001", &[("title", "Hello, World!")]);
+        assert_eq!(fill("/{slug}/", &titled, false).unwrap(), "hello-world/");
+        let explicit = note("typ/0001--file.typ", "0001", &[("slug", "Own Slug")]);
+        assert_eq!(fill("/{slug}/", &explicit, false).unwrap(), "own-slug/");
+        let bare = note("typ/0001.typ", "0001", &[]);
+        assert_eq!(fill("/{slug}/", &bare, false).unwrap(), "0001/");
+    }
+
+    #[test]
+    fn test_fill_rejects_invalid_patterns() {
+        let note = note(
+            "typ/0001.typ",
+            "0001",
+            &[("date", "March 5"), ("taxon", "..")],
+        );
+        assert!(fill("/{id", &note, false).unwrap_err().contains("unclosed"));
+        assert!(
+            fill("/{title}/", &note, false)
+                .unwrap_err()
+                .contains("unknown placeholder")
+        );
+        assert!(
+            fill("/{yyyy}/", &note, false)
+                .unwrap_err()
+                .contains("YYYY-MM-DD")
+        );
+        assert!(
+            fill("/../{id}/", &note, false)
+                .unwrap_err()
+                .contains("not allowed")
+        );
+    }
+
+    #[test]
+    fn test_collect_uses_overrides_and_home() {
+        let notes = [
+            note("typ/index.typ", "index", &[]),
+            note("typ/0001.typ", "0001", &[("taxon", "Post")]),
+            note("typ/0002.typ", "0002", &[("permalink", "/about/")]),
+        ];
+        let site = site(Some("/{taxon}/{id}/"), false);
+        let permalinks = Permalinks::collect(&notes, &site, &Aliases::default()).unwrap();
+        assert_eq!(permalinks.href("index", &site), "/");
+        assert_eq!(permalinks.href("0001", &site), "/post/0001/");
+        assert_eq!(permalinks.href("0002", &site), "/about/");
+        assert_eq!(
+            permalinks.output_path(Path::new("out"), "0001"),
+            Path::new("out/post/0001/index.html")
+        );
+    }
+
+    #[test]
+    fn test_collect_rejects_collisions() {
+        let notes = [
+            note("typ/0001.typ", "0001", &[("slug", "same")]),
+            note("typ/0002.typ", "0002", &[("slug", "Same")]),
+        ];
+        let err = Permalinks::collect(&notes, &site(Some("/{slug}/"), false), &Aliases::default())
+            .unwrap_err();
+        assert!(err.contains("notes 0001 and 0002 are both written to same/index.html"));
+
+        // A directory page and a file page with the same output collide too.
+        let notes = [
+            note("typ/0001.typ", "0001", &[("permalink", "/a/")]),
+            note("typ/0002.typ", "0002", &[("permalink", "/a/index.html")]),
+        ];
+        assert!(Permalinks::collect(&notes, &site(None, false), &Aliases::default()).is_err());
+    }
+
+    fn configured(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(alias, target)| (alias.to_string(), target.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_collect_aliases() {
+        let notes = [
+            note("typ/0001.typ", "0001", &[("aliases", "old, wb:older")]),
+            note("typ/0002.typ", "0002", &[]),
+        ];
+        let aliases = Aliases::collect(&notes, &configured(&[("legacy", "wb:0002")])).unwrap();
+        assert_eq!(aliases.resolve("old"), Some("0001"));
+        assert_eq!(aliases.resolve("older"), Some("0001"));
+        assert_eq!(aliases.resolve("legacy"), Some("0002"));
+        assert_eq!(aliases.resolve("0001"), None);
+    }
+
+    #[test]
+    fn test_collect_aliases_rejects_conflicts() {
+        let notes = [
+            note("typ/0001.typ", "0001", &[("aliases", "old")]),
+            note("typ/0002.typ", "0002", &[("aliases", "0001")]),
+        ];
+        let err = Aliases::collect(&notes, &BTreeMap::new()).unwrap_err();
+        assert!(err.contains("alias 0001 of 0002 is the ID of an existing note"));
+
+        let notes = [
+            note("typ/0001.typ", "0001", &[("aliases", "old")]),
+            note("typ/0002.typ", "0002", &[]),
+        ];
+        let err = Aliases::collect(&notes, &configured(&[("old", "0002")])).unwrap_err();
+        assert!(err.contains("alias old refers to both 0001 and 0002"));
+        let err = Aliases::collect(&notes, &configured(&[("gone", "0003")])).unwrap_err();
+        assert!(err.contains("refers to note 0003, which does not exist"));
+    }
+
+    #[test]
+    fn test_resolve_targets() {
+        let mut notes = [
+            note("typ/0002.typ", "0002", &[]),
+            note("typ/0001.typ", "0001", &[("aliases", "old")]),
+        ];
+        notes[0].document = scraper::Html::parse_document(
+            "<html><body><a href=\"wb:old#part\">a</a><wb-transclusion target=\"old\"></wb-transclusion><a href=\"wb:0001\">b</a></body></html>",
+        );
+        let aliases = Aliases::collect(&notes, &BTreeMap::new()).unwrap();
+        let replaced = aliases.resolve_targets(&mut notes);
+        assert_eq!(replaced.len(), 2);
+        assert_eq!(replaced[0], "0002 refers to 0001 by its alias old");
+        let html = notes[0].document.html();
+        assert!(html.contains("href=\"wb:0001#part\""));
+        assert!(html.contains("target=\"wb:0001\""));
+        assert!(html.contains("href=\"wb:0001\""));
+    }
+
+    #[test]
+    fn test_href_of_alias() {
+        let notes = [
+            note("typ/index.typ", "index", &[]),
+            note("typ/0001.typ", "0001", &[("aliases", "old")]),
+        ];
+        let site = site(None, true);
+        let aliases = Aliases::collect(&notes, &BTreeMap::new()).unwrap();
+        let permalinks = Permalinks::collect(&notes, &site, &aliases).unwrap();
+        assert_eq!(permalinks.href("old", &site), "/0001/");
+        let [redirect] = permalinks.redirects() else {
+            panic!("expected one redirect");
+        };
+        assert_eq!(
+            (redirect.alias.as_str(), redirect.target.as_str()),
+            ("old", "0001")
+        );
+        assert_eq!(
+            permalinks.redirect_output_path(Path::new("out"), redirect),
+            Path::new("out/old/index.html")
+        );
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("  Hello, World!  "), "hello-world");
+        assert_eq!(slugify("--a  b--"), "a-b");
+        assert_eq!(slugify("Écrire en 中文"), "écrire-en-中文");
+        assert_eq!(slugify("!!!"), "");
+    }
+}
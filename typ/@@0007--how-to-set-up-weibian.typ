@@ -30,4 +30,8 @@ notes/
     └── ...
 ```
 
-Most of the above directories is the default configuration, which can be overridden by passing command line arguments when running Weibian. The `.wb` directory is necessary for now, since it keeps the HTML template files and configuration file (if any). You must create the `.wb` directory manually for now; in the future, Weibian may provide a command to initialize a project structure automatically.
+Most of the above directories is the default configuration, which can be overridden by passing command line arguments when running Weibian. The `.wb` directory is necessary for now, since it keeps the HTML template files and configuration file (if any). Run `wb init notes` (or `wb init` in an existing directory) to lay down this structure from the bundled starter templates: a configuration file, the HTML templates, the Typst template library in `typ/_template/`, the stylesheet and script in `public/`, and a home note with the ID `index`. The `--site-domain` and `--site-root-dir` options fill in the `[site]` section of the configuration. `wb init` never overwrites files: if any of the files it would create already exists, it stops without writing anything.
+
+To start writing, run `wb new "My first note"` in the project directory: it creates a note with the next free ID in `typ/` and prints its path. See #ln("wb:0008")[] for the options of `wb new`.
+
+Editors can run `wb lsp` in the project directory as a second language server for Typst files, next to tinymist. It accepts the same options as `wb compile` and offers, for `wb:` references, completion of note IDs and aliases with their titles, go-to-definition to the source of the note, hover previews of its title and metadata, and the places referring to it (also from anywhere in the note itself), as `wb:` references or as its ID in quotes, e.g. `#tr("0001")`. It reports links, citations and transclusions to notes that do not exist, references through aliases, duplicate note IDs at the `identifier` field, and notes that fail to compile, at the spans Typst reports for them. The notes are compiled when the server starts, and each one again when it is saved. If the editor supports it, the server also watches the `.typ` files, so notes created, changed or deleted outside of the editor are picked up as well.
@@ -18,28 +18,28 @@ This section describes the internal rendering process of Weibian. To quickly get
 
 Weibian will first export each note in the input directory to HTML. This does not involve any special processing; the Typst files are compiled as-is, and user is responsible for generating HTML files with the Weibian conventions described below. The generated HTML is not used for display directly nor saved as file, but rather as an in-memory intermediate representation for further processing.
 
-In the `<body>` of the HTML, there could be three special custom elements: `<wb-transclusion target="wb:..." show-metadata="..." expanded="..." disable-numbering="..." demote-headings="..."></wb-transclusion>`, `<wb-internal-link target="wb:...">...</wb-internal-link>` and `<wb-cite target="wb:..."></wb-cite>`. 
+In the `<body>` of the HTML, there could be four special custom elements: `<wb-transclusion target="wb:..." show-metadata="..." expanded="..." disable-numbering="..." demote-headings="..."></wb-transclusion>`, `<wb-internal-link target="wb:...">...</wb-internal-link>`, `<wb-cite target="wb:..."></wb-cite>` and `<wb-footnote>...</wb-footnote>`. 
 `<wb-transclusion>` is used to represent transcluded notes, `<wb-internal-link>` is used for internal links between notes, and `<wb-cite>` is used for citations to notes, which is basically a special kind of internal link.
-For `<wb-transclusion>`, its body must be empty; the `target` attribute, starting with `wb:`, specifies the ID (not including the `wb:` prefix) of the note to be transcluded, while `show-metadata` and `expanded` are boolean attributes that control the display of metadata and whether the transclusion is expanded by default, respectively; due to limitations in Typst's HTML export capabilities, their values are represented as strings ("true" or "false"). `disable-numbering` is a boolean attribute that controls whether to disable heading numbering in the transcluded content, and `demote-headings` is a non-negative integer attribute that controls how many levels to demote headings in the transcluded content (e.g., if `demote-headings` is 1, then all `h1` headings in the transcluded content will be demoted to `h2`, all `h2` will be demoted to `h3`, and so on). For `<wb-internal-link>`, the `target` attribute specifies the ID of the note to link to, and its body contains the link text. For `<wb-cite>`, the `target` attribute specifies the ID of the note to cite, and its body contains the citation text.
+For `<wb-transclusion>`, its body must be empty; the `target` attribute, starting with `wb:`, specifies the ID (not including the `wb:` prefix) of the note to be transcluded, while `show-metadata` and `expanded` are boolean attributes that control the display of metadata and whether the transclusion is expanded by default, respectively; due to limitations in Typst's HTML export capabilities, their values are represented as strings ("true" or "false"). `disable-numbering` is a boolean attribute that controls whether to disable heading numbering in the transcluded content, and `demote-headings` is a non-negative integer attribute that controls how many levels to demote headings in the transcluded content (e.g., if `demote-headings` is 1, then all `h1` headings in the transcluded content will be demoted to `h2`, all `h2` will be demoted to `h3`, and so on). The optional `mode` attribute is either `full` (the default), which transcludes the whole body of the target note, or `summary`, which only transcludes its header (title and metadata) and an excerpt. The excerpt is the content of the first `<wb-excerpt>` element in the target note (produced by the `excerpt` function of the default template), or its first paragraph if there is none. The optional `lazy` attribute, when `true`, makes the transclusion load the body of the target note in the browser when it is opened, instead of inlining it; the backend then only inlines the header of the target note and writes its rendered body to `fragments/<id>.html` in the output directory. Without the attribute, collapsed (`expanded="false"`) transclusions are made lazy when the body of the target note has more than `lazy_threshold` elements, if set in the `[render]` section of the configuration file. The elements are counted in the intermediate HTML of the note, each transclusion in it counting as the elements of its own target, so the decision does not depend on how the note is rendered. Lazy loading is performed by `js/weibian.js` from the `public` directory. For `<wb-internal-link>`, the `target` attribute specifies the ID of the note to link to, and its body contains the link text. For `<wb-cite>`, the `target` attribute specifies the ID of the note to cite, and its body contains the citation text. `<wb-footnote>` holds the content of a footnote; the default template emits it for every `footnote`, since Typst does not support its own footnotes together with a custom `<html>` element.
 
 Then, Weibian extracts information from the generated HTML, and use the Tera templating engine and user-supplied templates to produce the final HTML files for the notes. By default, Weibian looks for templates in `.wb/templates/`. 
 
-This rendering process begins by parsing the intermediate HTMLs to build a transclusion graph with respect to the `<wb-transclusion>` elements. Then, the notes are processed in topological order. For each note, the aforementioned custom elements are replaced with the actual content they represent.
+This rendering process begins by parsing the intermediate HTMLs to build a transclusion graph with respect to the `<wb-transclusion>` elements. Then, each note is rendered as a page: the body of the note is walked from top to bottom, and the aforementioned custom elements are replaced with the actual content they represent. Transcluded notes are rendered again in the context of every page they appear in, so that they can be numbered according to their position in that page.
 
 First, the transclusion and linking relationships are analyzed to build a transclusion graph. Each note is represented as a node in the graph, and a directed edge from node A to node B exists if note A transcludes note B. If there are cycles in the transclusion graph, Weibian will report an error and abort the rendering process, as cyclic transclusions are not supported.
 
-Then, transclusions are processed. For `<wb-transclusion>`, it is rendered via the `transclusion.html` template, which is provided with a a `transclusion` context (`transclusion.target`, `transclusion.show_metadata`, `transclusion.expanded`, `transclusion.hide_numbering`, `transclusion.demote_headings`, `transclusion.metadata`, `transclusion.content`). The `transclusion.target`, `transclusion.show_metadata`, `transclusion.expanded`, `transclusion.hide_numbering`, and `transclusion.demote_headings` are extracted from the corresponding attributes of the `<wb-transclusion>` element, while `transclusion.metadata` is the metadata of the target note, extracted from the `<meta>` tags in the `<head>` of the intermediate HTML of the target note, and `transclusion.content` is the processed content of the target note's final HTML file, to help simplify transclusion rendering in templates. Two Tera filters are registered to help transclusion rendering: `wb_hide_numbering` and `wb_demote_headings`. They apply unconditionally; template conditionals decide whether to invoke them (see the default `transclusion.html`). The result of rendering this template replaces the corresponding `<wb-transclusion>` element in the final HTML file. By processing the notes in topological order, the target note should have already been processed when processing the current note. After this step, there should be only `<wb-internal-link>` and `<wb-cite>` elements left in the HTML file.
+Then, transclusions are processed. For `<wb-transclusion>`, it is rendered via the `transclusion.html` template, which is provided with a a `transclusion` context (`transclusion.target`, `transclusion.mode`, `transclusion.show_metadata`, `transclusion.expanded`, `transclusion.hide_numbering`, `transclusion.demote_headings`, `transclusion.lazy`, `transclusion.fragment_href`, `transclusion.metadata`, `transclusion.content`). The `transclusion.target`, `transclusion.show_metadata`, `transclusion.expanded`, `transclusion.hide_numbering`, and `transclusion.demote_headings` are extracted from the corresponding attributes of the `<wb-transclusion>` element, while `transclusion.metadata` is the metadata of the target note, extracted from the `<meta>` tags in the `<head>` of the intermediate HTML of the target note, and `transclusion.content` is the processed content of the target note's final HTML file, to help simplify transclusion rendering in templates. Every note is processed once, after the notes it transcludes, and its processed content is reused wherever it is transcluded. Two Tera filters are registered to help transclusion rendering: `wb_hide_numbering` and `wb_demote_headings`. They apply unconditionally; template conditionals decide whether to invoke them (see the default `transclusion.html`). The result of rendering this template replaces the corresponding `<wb-transclusion>` element in the final HTML file. Before the content of the target note is passed to the template, every `id` attribute in it that is already used by the transcluding note (or by an earlier transclusion in it) is renamed by appending a numeric suffix (e.g., `intro` becomes `intro-2`), and links of the form `href="#..."` inside the transcluded content are rewritten accordingly, so that headings, footnotes and labels stay unique in the final page. When the transcluded content holds several elements with the same ID (e.g., when it transcludes a note twice), a link is resolved to the one closest to it, and is left alone if that one keeps its ID. The IDs of a lazy fragment are renamed in the same way against those of the pages it is loaded into. Once the transclusions of a page are inlined, the backend numbers its headings hierarchically (`1`, `1.2`, `1.2.3`, ...) in document order: `h1` headings are not numbered, and the headings of a transcluded note are numbered at the level they are demoted to, continuing the numbering of the transcluding note. Headings with the `disable-numbering` class, which `wb_disable_numbering` adds to the headings of transclusions with `disable-numbering="true"`, are skipped. The number of a heading is stored in its `data-number` attribute and in its `--num` CSS custom property, which the default stylesheet displays. The number of a transcluded note on the page is the number of its title heading, which is recognized by the `[ID]` link of its header. The headings of a lazy fragment are numbered relative to the target note, and prefixed with the number of the transclusion (recorded in the `data-wb-number` attribute of its `<details>`) when the fragment is loaded. Each `<wb-footnote>` is replaced by a reference to the footnote (an `<a role="doc-noteref">`), and the footnotes of a note are listed after its body in a `<section role="doc-endnotes">`, with the markup of Typst's own footnotes; the header and the excerpt of a note list the footnotes they refer to in the same way, so the footnotes of a summary transclusion are kept. Once the whole page is rendered, its footnotes are numbered: by default, they are moved into one list at the end of the page and numbered in order of first reference, and the backlinks of the list items are updated accordingly. When `footnotes` is set to `"section"` in the `[render]` section of the configuration file, every transcluded note keeps its own list instead, numbered on its own. After this step, there should be only `<wb-internal-link>` and `<wb-cite>` elements left in the HTML file.
 
-For `<wb-internal-link>`, it is rendered via the `internal_link.html` template, which is provided with a `link` context (`link.target`, `link.text`, `link.href`). The `link.target` and `link.text` are extracted from the corresponding attribute and body of the `<wb-internal-link>` element, while `link.href` is the generated URL to the target note's final HTML file, to help simplify link generation in templates. When the body of the `<wb-internal-link>` element is empty, the title of the target note is used as the link text. The result of rendering this template replaces the corresponding `<wb-internal-link>` element in the final HTML file. The rendering process for `<wb-cite>` is similar, except that it uses the `citation.html` template and a `citation` context (`citation.target`, `citation.text`, `citation.href`).
+For `<wb-internal-link>`, it is rendered via the `internal_link.html` template, which is provided with a `link` context (`link.target`, `link.text`, `link.href`, `link.number`, `link.label`). The `link.target` and `link.text` are extracted from the corresponding attribute and body of the `<wb-internal-link>` element, while `link.href` is the generated URL to the target note's final HTML file, to help simplify link generation in templates. If the target note is transcluded into the current page, `link.number` is the number it gets there, and `link.label` prefixes it with the taxon of the target note, e.g., "Theorem 1.2"; both are absent otherwise. The default template does not show them; adding `{% if link.label %} ({{ link.label }}){% endif %}` to it makes cross-references show the label. When the body of the `<wb-internal-link>` element is empty, the title of the target note is used as the link text. The result of rendering this template replaces the corresponding `<wb-internal-link>` element in the final HTML file. The rendering process for `<wb-cite>` is similar, except that it uses the `citation.html` template and a `citation` context (`citation.target`, `citation.text`, `citation.href`, `citation.number`, `citation.label`).
 
 Then, backmatters are generated for each note. As for now, Weibian supports four types of backmatter sections: contexts, references, backlinks, and related notes.
 - A context for note A is defined as any note that directly transcludes note A.
 - A reference from note A to note B exists if note A links to note B via an citation link.
 - A backlink from note A to note B exists if note B links to note A via an internal link.
 - A related note to note A to note B exists if note A links to note B via an internal link.
-The content of each backmatter section is generated by rendering transclusions of all notes relevant to that backmatter section with `show-metadata="true"`, `expanded="false"`, `disable-numbering="true"`, and `demote-headings="<number>"` options. Then, for each backmatter section, a Tera context is created with `title` being the name of the backmatter section (e.g., "Backlinks", "Contexts") and `content` being the HTML of the rendered transclusion described above. These contexts are packed into an array and passed to the `note.html` template as `note.backmatter_sections` for rendering, see the next paragraph.
+The content of each backmatter section is generated by rendering transclusions of all notes relevant to that backmatter section with `mode="summary"` (configurable with `backmatter_mode` in the `[render]` section of the configuration file), `show-metadata="true"`, `expanded="false"`, `disable-numbering="true"`, and `demote-headings="<number>"` options. Then, for each backmatter section, a Tera context is created with `title` being the name of the backmatter section (e.g., "Backlinks", "Contexts") and `content` being the HTML of the rendered transclusion described above. These contexts are packed into an array and passed to the `note.html` template as `note.backmatter_sections` for rendering, see the next paragraph.
 
-Finally, the final HTML file for each note will be constructed. The template for that is `note.html`. It receives a `note` context (`note.id`, `note.title`, `note.metadata`, `note.head`, `note.content`, `note.toc`, `note.backmatter`). `note.id` and `note.title` are extracted from the corresponding `<meta>` tags, provided for convenience.  The `note.metadata` is a map of metadata key-value pairs extracted from `<meta>` tags with `name` and `content` attributes in the `<head>` section of the intermediate HTML. The `note.head` is the raw HTML content of the `<head>` section of the intermediate HTML. `note.content` is the processed content of the `<body>` section of the intermediate HTML, with all transclusions and internal links resolved as described above. `note.backmatter_sections` is an array of backmatter section contexts as described in the previous paragraph. The `toc` field is an array of `Heading` objects, where each `Heading` object has the following structure:
+Finally, the final HTML file for each note will be constructed. The template for that is `note.html`. It receives a `note` context (`note.id`, `note.title`, `note.metadata`, `note.head`, `note.page_head`, `note.content`, `note.toc`, `note.backmatter`). `note.id` and `note.title` are extracted from the corresponding `<meta>` tags, provided for convenience.  The `note.metadata` is a map of metadata key-value pairs extracted from `<meta>` tags with `name` and `content` attributes in the `<head>` section of the intermediate HTML. `note.head` is the head of the page, built from the `<head>` section of the intermediate HTML: every tag is kept once (for `<title>`, the charset, and `<meta>` tags with the same `name`, `property` or `http-equiv`, the first one wins), and the tags every page needs are added unless the intermediate HTML already sets them: the charset, the viewport, a `<link rel="canonical">` to the URL of the page, Open Graph tags (`og:type`, `og:title`, `og:url`, and `og:description` and `og:image` when the note has a `description` or a social preview image), Twitter tags (`twitter:card`, `twitter:title`, and `twitter:description` and `twitter:image` with the same content as their Open Graph counterparts), and `<link rel="alternate" hreflang>` tags for the translations listed in the `alternates` metadata field (e.g., `alternates: (zh: "wb:0001-zh")`, which the default template turns into `<meta name="alternates" content="zh=wb:0001-zh">`), including the note itself in its `lang`. URLs are absolute when `domain` is set. `note.head` is the whole head serialized, to be inserted into the template as is with `{{ note.head | safe }}`; its parts are also available as `note.page_head.charset`, `note.page_head.title`, `note.page_head.canonical`, `note.page_head.meta` and `note.page_head.properties` (arrays of tags with `attribute`, `key` and `content`), `note.page_head.alternates` (an array of `hreflang` and `href`) and `note.page_head.extra` (every other element, serialized), for templates that lay out the head themselves. `note.content` is the processed content of the `<body>` section of the intermediate HTML, with all transclusions and internal links resolved as described above. `note.backmatter_sections` is an array of backmatter section contexts as described in the previous paragraph. The `toc` field is an array of `Heading` objects, where each `Heading` object has the following structure:
 
 ```
 // The hX level
@@ -50,13 +50,19 @@ id: String;
 content: String;
 // Whether the heading has the "disable-numbering" class
 disable_numbering: Bool;
+// The section number computed by the backend, e.g. "1.2", if numbered
+number: String | None;
+// The number prefixed with the taxon of the note, e.g. "Theorem 1.2", if numbered
+label: String | None;
 // All lower level headers below this header
 children: Array<Heading>;
 ```
 
 Along all the rendering process, a `site` context (`site.root_dir`, `site.trailing_slash`, `site.domain`) is also provided to all templates to help with link generation and other site-wide settings.
 
-When the document has `export-pdf` metadata set to true, an additional PDF export will be generated for the note. The PDF is simply generated by running the Typst compiler on the original Typst file, with an extra `wb-id-filename-map-file` input set to the root-absolute path of a JSON file that maps note IDs to their corresponding source Typst file root-absolute paths, to help resolving internal links and transclusions in the PDF export. The PDF file is saved with the name `<identifier>.pdf` in the `pdf/` subdirectory of the output directory. The PDF export is independent from the HTML export; it does not use the intermediate HTML nor the Tera templates, and it is triggered solely by the presence of the `export-pdf` metadata field in the original Typst file, which is processed in the default template to add a corresponding `<meta name="export-pdf" content="true">` tag in the intermediate HTML. For a starter template for both HTML and PDF export, see the #link("https://github.com/hanwenguo/weibian/tree/main/typ")[source of this very site].
+When the document has `export-pdf` metadata set to true, an additional PDF export will be generated for the note. The PDF is simply generated by running the Typst compiler on the original Typst file, with an extra `wb-data-package` input naming a package generated by Weibian for the build (`@weibian/data:0.1.0`), whose `data` dictionary maps note IDs to their corresponding source Typst file root-absolute paths (`data.id-filename`) and to the hrefs of their pages (`data.permalinks`), to help resolving internal links and transclusions in the PDF export; the default template imports it as `wb-data` in `lib.typ`. `data.notes` is the index of all notes, keyed by ID: every entry has the `id`, plain-text `title`, `taxon`, root-absolute `href`, absolute `url` (the href if `domain` is not set), root-absolute `source` file, and the IDs of the notes linking to it (`backlinks`) and transcluding it (`contexts`). The default template reads it as `note-index`, uses the `url` of a note for links to the site, and shows the title of the target note for internal links and citations without text, e.g. `#ln("wb:0001")[]`, as the HTML export does. The HTML export of a note can use the same data when the note sets `two-pass: true` in the default template, which adds a `<meta name="wb-two-pass" content="true">` tag: after all notes have been compiled once, such notes are compiled again with the `wb-data-package` input, so that they can lay out content that depends on the note graph, e.g. a badge with `note-index.at("0001").backlinks.len()` citing notes. Since the recompiled notes may change the graph themselves, they are compiled again until the data generated from them no longer changes, and Weibian reports an error if it still changes after four passes. During the first pass, `wb-data` is empty, so such notes must handle missing entries. The package is written into a temporary directory that is passed to the compiler as its local package directory, with the namespaces of the usual local package directory linked into it, so that `@local` packages keep working; nothing is written into the input directory, and the directory is removed when the build ends. The PDF file is saved with the name `<identifier>.pdf` in the `pdf/` subdirectory of the output directory. The PDF export is independent from the HTML export; it does not use the intermediate HTML nor the Tera templates, and it is triggered solely by the presence of the `export-pdf` metadata field in the original Typst file, which is processed in the default template to add a corresponding `<meta name="export-pdf" content="true">` tag in the intermediate HTML. Which outputs are produced is controlled by `--format` (`html`, `pdf` or `all`, the default), or by `format` in the `[files]` section of the configuration file. `--format html` skips the PDF export, which is usually the slowest part of a build; `--format pdf` skips rendering the HTML site, although the intermediate HTML is still compiled, as it provides the list of notes and their `export-pdf` metadata. The PDF standards given with `--pdf-standard` (or `standards` in the `[pdf]` section of the configuration file) are enforced for every PDF export; a note can override them with the `pdf-standard` metadata field (e.g., `pdf-standard: "a-2b"` or `pdf-standard: ("a-2b", "1.7")`), which the default template turns into a `<meta name="pdf-standard" content="a-2b,1.7">` tag. A note that fails to export, for example because it does not conform to a requested standard, is reported with its ID after the other notes have been exported.
+
+A note can also be exported as a book, i.e., as one PDF containing the note together with every note it transcludes, directly or not, either with the `wb export book <ID>` command or by setting the `pdf-mode` metadata field to `"book"` (the default is `"note"`) in a note that has `export-pdf` set to true. `wb export book` accepts the same options as `wb compile`, and writes the PDF to `pdf/<ID>.pdf` in the output directory unless `--output-file` is given. The book is compiled from a driver document generated by Weibian that only includes the note in paged mode, so the other notes appear where they are transcluded, in the order of the transclusion tree; an extra `wb-book-ids` input holds the comma-separated IDs of all notes in the book. The default template uses it to label the first occurrence of every note in the book, so that internal links and citations to notes in the book become links within the PDF, while links to other notes point to their pages on the site (built from `wb-domain`, `wb-root-dir` and `wb-trailing-slash`). A note and its transclusion tree can likewise be exported as an EPUB 3 book with `wb export epub <ID>`, which writes `epub/<ID>.epub` in the output directory unless `--output-file` is given. Every note in the book becomes a chapter rendered with the HTML templates; transclusions of notes that have their own chapter are replaced by a link to it, and the table of contents follows the transclusion tree. Links between chapters stay within the EPUB, links to other notes point to the site, and the stylesheets, images and fonts of the public directory are embedded, while scripts are left out. The pages of a single note can also be exported as images with `wb export image <ID> --image-format <svg|png>` (PNG by default), which compiles the note in paged mode and writes one image per page to `<FORMAT>/<ID>-{p}.<FORMAT>` in the output directory, where `{p}` is the page number, unless `--output-file` is given; a path given with `--output-file` should contain `{p}` as well if the note has more than one page. For a starter template for both HTML and PDF export, see the #link("https://github.com/hanwenguo/weibian/tree/main/typ")[source of this very site].
 ]
 
 #inline-tree(
@@ -74,17 +80,67 @@ output_dir = "dist"
 public_dir = "public"
 # include = ["**/*.typ"]  # optional; defaults to all files in input_dir
 # exclude = ["draft-*"]   # optional; exclude has priority over include
+# format = "all"          # optional; "html", "pdf" or "all" (the default)
 # the above is the equivalent of the corresponding CLI flags
 
 [site]
 domain = "example.com" # the domain of the site; used for generating absolute URLs
 root_dir = "/" # the root directory of the site; for example, if the site is hosted at example.com/notes/, set root_dir = "/notes/"
 trailing_slash = true # if true, the final URL of each note will have a trailing slash
+# home = "index" # the ID of the note served at the site root; when set, it must be the ID of a note
+# not_found = true # whether to render the 404 page with the 404.html template
+# permalink = "/{taxon}/{id}/" # optional; the pattern of the paths of note pages
+
+[render]
+backmatter_mode = "summary" # "summary" or "full"; how notes are transcluded into backmatter sections
+# lazy_threshold = 500 # optional; collapsed transclusions of notes with more elements than this are loaded lazily
+footnotes = "page" # "page" or "section"; whether footnotes are listed at the end of the page or of each transcluded note
+# alias_warnings = true # optional; warn about notes referring to others by an alias
+
+[pdf]
+# standards = ["a-2b"] # optional; PDF standards ("1.7", "a-2b" or "a-3b") enforced for PDF exports, like --pdf-standard
+
+[aliases]
+# "0001-old" = "0001" # optional; former note IDs and the notes they stand for
+
+[og]
+# card = "_template/og-card.typ" # optional; the Typst template of the social preview image of each note, relative to input_dir
+
+[new]
+# skeleton = "_template/skeleton.typ" # optional; the Tera template of notes created by `wb new`, relative to input_dir
+# journal_skeleton = "_template/journal.typ" # optional; the Tera template of journal notes, relative to input_dir; defaults to skeleton
+# journal_dir = "journal" # optional; where journal notes are created, relative to input_dir
+
+[profile.public] # optional; overrides selected with --profile public
+# include = ["**/*.typ"]
+# exclude = ["_template/*.typ", "private/**"]
+# output_dir = "dist-public"
+# domain = "example.com"
+# root_dir = "/notes/"
+# inputs = { edition = "public" } # extra key-value pairs visible through sys.inputs
 ```
 
 The configuration file is parsed at the start of the program, and values are used as defaults for the corresponding CLI flags. If `cache_dir` is omitted, Weibian uses a project-specific directory under the system temporary directory for intermediate HTML. By default, the configuration file is looked for in `.wb/config.toml` relative to the project root, but a different path can be specified with `--config-file <PATH>`. Site settings can also be overridden via CLI with `--site-domain`, `--site-root-dir`, and `--trailing-slash <BOOL>`. The settings in the `[site]` section are also passed to the Typst compiler as inputs, with the prefix `wb-` and underscores converted to hyphens (e.g., `site.domain` becomes `wb-domain`).
 
-The `trailing_slash` option will affect how internal links are generated and how the output files are organized. If `trailing_slash` is true, each note will be saved in a subdirectory named after its ID, with an `index.html` file inside (e.g., a note with ID `note-123` will be saved as `dist/note-123/index.html`). If false, each note will be saved directly as an HTML file named after its ID (e.g., `dist/note-123.html`). The `root_dir` setting only affects link generation; it does not change where files are written. Special case: a note with ID `index` is always saved as `dist/index.html` and links to the site root.
+Configuration values can also be set with environment variables named `WB_<SECTION>__<KEY>`, e.g. `WB_SITE__DOMAIN=example.org` or `WB_FILES__OUTPUT_DIR=public-build`, which is convenient in CI. Values are read as strings, so that `WB_SITE__HOME=0008` names note `0008`, and converted where a setting is a number or a boolean, e.g. `WB_SITE__TRAILING_SLASH=false`; lists are written like TOML arrays, e.g. `WB_FILES__EXCLUDE='["drafts/**"]'`. The precedence, from the lowest to the highest, is: built-in defaults, the configuration file, the selected profile (see below), environment variables, CLI flags. `wb config show` prints every setting of a build as resolved from all of them, with where its value comes from (a config file, a profile, an environment variable, the command line or the defaults); it accepts the same options as `wb compile`, so that e.g. `wb config show --profile public` shows the settings of the public site.
+
+The configuration is checked strictly before anything is built, and every problem is reported at once in a single list: unknown keys, with the known key they are closest to (e.g., `trailing_slashes` suggests `site.trailing_slash`), whether they come from the file or from the environment; an input directory, or a public directory set explicitly, that does not exist; an output directory inside the input directory (its files would be compiled as notes) or containing it, or overlapping with the public directory; a `domain` that is not a host name with an optional `http` or `https` scheme and port (paths belong in `root_dir`); and `include` globs that match no file in the input directory.
+
+Several sites can be built from the same notes with profiles: a `[profile.<NAME>]` table overrides `include`, `exclude`, `output_dir`, `domain` and `root_dir`, and adds `inputs` to `sys.inputs`, when `--profile <NAME>` is passed to `wb compile`, `wb export` or the other commands compiling notes, or when the `WB_PROFILE` environment variable is set to `<NAME>`. The profile takes precedence over the base configuration, but environment variables and CLI flags take precedence over the profile (an `--input` with the same key replaces the one of the profile). Selecting a profile that is not defined is an error.
+
+When `card` is set in the `[og]` section, every HTML build also renders a social preview image of each note into `og/<ID>.png` in the output directory, by compiling the given template once per note in PNG format with the inputs `wb-og-id`, `wb-og-title`, `wb-og-taxon` and `wb-og-authors` (the authors joined with commas, taken from the `author` meta tag of the note). The image is then referenced by the `og:image` Open Graph tag in the head of the note, as an absolute URL if `domain` is set. The starter template renders a 600pt by 315pt page, i.e., a 1200 by 630 pixel image at the default resolution.
+
+New notes can be created with `wb new <TITLE>`, which allocates the next free ID and prints the path of the created file. IDs are sequential base-36 numbers of at least four digits and uppercase letters (`0000`, `0001`, ..., `0009`, `000A`, ...): the new ID follows the largest such ID found in the file names and `identifier` fields of the `.typ` files in the input directory that pass the `include` and `exclude` filters (of the profile given with `--profile`, if any), and among the `[aliases]` of the configuration file. The file is named `@@<ID>--<slug>.typ` after the title, or `@@<ID>==<taxon>--<slug>.typ` with `--taxon <TAXON>`, where the taxon keeps its case (e.g. `==Person`) and characters that cannot be in file names become hyphens, and created in the input directory, or in `--dir <DIR>` relative to it. Its content is rendered from the `skeleton` template of the `[new]` section, a Tera template relative to the input directory that receives `title` (escaped for Typst markup), `identifier`, `taxon` (escaped for a Typst string, if given), `date` (the current time as a Typst `datetime(...)` expression) and `journal`; without one, the built-in skeleton produces the same front matter as this site's notes. `wb new --journal` creates the journal note of the day in `journal_dir` (`journal` by default), titled with the date (e.g. `2026-01-17`) unless a title is given and rendered from `journal_skeleton` if set; if the journal directory already has a note of the day, i.e., a note whose `date` is a `datetime(...)` on that day or whose file name ends with the date, its path is printed instead.
+
+The `trailing_slash` option will affect how internal links are generated and how the output files are organized. If `trailing_slash` is true, each note will be saved in a subdirectory named after its ID, with an `index.html` file inside (e.g., a note with ID `note-123` will be saved as `dist/note-123/index.html`). If false, each note will be saved directly as an HTML file named after its ID (e.g., `dist/note-123.html`). The `root_dir` setting only affects link generation; it does not change where files are written. Special case: the home note, `index` by default, is always saved as `dist/index.html` and links to the site root; another note can be made the home page with `home` in the `[site]` section (or `--site-home <ID>`).
+
+Note IDs are used as paths in the output directory, so they follow a fixed grammar: one or more segments separated by `/`, each made of ASCII letters, digits, `-`, `_` and `.`, and not starting with `.`. The segments before the last one are namespaces, which map to subdirectories: a note with ID `math/0001` is saved as `dist/math/0001/index.html` (or `dist/math/0001.html`), and its PDF export as `pdf/math/0001.pdf`. A note whose `identifier` does not follow the grammar, e.g. `../0001` or `a//b`, is reported as an error with the path of its source file, and so is such an alias.
+
+The paths of note pages can be changed with the `permalink` pattern in the `[site]` section (or `--site-permalink`), relative to `root_dir`, e.g., `"/{taxon}/{id}/"` or `"/{yyyy}/{slug}/"`. The placeholders are `{id}`, `{slug}`, `{taxon}`, `{lang}`, `{yyyy}`, `{mm}` and `{dd}`, filled from the metadata of each note: `{slug}` is the `slug` metadata field, or else the part of the file name after `--` (e.g., `writing-in-weibian` for this note), or else the title; `{taxon}` and `{lang}` are slugified like the title (e.g., `zh-hans` for `zh_Hans`); the date placeholders come from the `date` metadata field. Placeholders without a value are dropped together with their segment, so `"/{taxon}/{id}/"` puts a note without a taxon at `/<ID>/`. A pattern ending with a slash writes the note as `index.html` in a directory of its own, a pattern ending with `.html` writes it as that file, and other patterns follow `trailing_slash`. A note can override the pattern with its own `permalink` metadata field (e.g., `permalink: "/about/"`), which may use the same placeholders. Without a pattern, notes follow `trailing_slash` as described above. Templates that need the URL of a note in the intermediate HTML can use an `href` of the form `wb:<ID>` (optionally followed by a `#fragment`), which Weibian replaces by the href of the page of the note, as the default template does for the `[ID]` link next to note titles. Links, transclusion headings in the table of contents and the URLs of PDF exports always use the same paths as the written files, and two notes written to the same file are reported as an error. The href of every note is passed to paged exports as `permalinks` in the generated data package.
+
+When a note is renamed or renumbered, its former IDs can be kept as aliases, either with the `aliases` metadata field of the note (e.g., `aliases: ("0001-old",)`) or in the `[aliases]` table of the configuration file. For every alias, a redirect page is written where the site's permalink pattern would put a note with the alias as its ID, so that links to the old URL keep working; it uses the `redirect.html` template if there is one, which receives a `redirect` context (`redirect.alias`, `redirect.target`, `redirect.href` and `redirect.url`, the absolute URL of the target when `domain` is set), and a built-in page with a meta refresh and a canonical link otherwise. Aliases can also be used as `wb:` targets of links, citations and transclusions, in both the HTML and paged exports; set `alias_warnings` to true in the `[render]` section to be warned about every such use, e.g., to update old links. An alias that is the ID of an existing note, or that stands for two different notes, is an error. A note can be renamed with `wb mv <OLD> <NEW> [INPUT]`, which takes the input directory and `--profile` like `wb compile` to select the notes: it compiles the notes to find every note linking to, citing or transcluding the note, replaces the `wb:<OLD>` references in their sources (keeping `#fragment`s) and the `identifier` of the note, points the aliases of the note in the `[aliases]` table to the new ID, and renames a file named `@@<OLD>...typ` to `@@<NEW>...typ`. With `--alias`, the old ID is also added to the `[aliases]` table, so that its URL redirects to the new one. With `--dry-run`, the changes are printed as a diff instead of being made. A note referring to the renamed note in another way than a `wb:<OLD>` literal, e.g. through a variable, is reported so that it can be updated by hand.
+
+If the template directory contains a `404.html` template, it is rendered to `dist/404.html` unless `not_found` is false in the `[site]` section, the page most static hosts serve for unknown paths. Besides `site`, it receives a `notes` list with the `id`, `title`, `taxon` and `href` of every note, so that the page can suggest notes close to the requested path; the default template compares the last segment of the path to the IDs and titles of the notes in the browser.
 ]
 
 #tr("wb:0009", expanded: false)
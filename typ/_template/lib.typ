@@ -26,6 +26,11 @@
     }
   } else if f == _sequence {
     it.children.map(plain-text).filter(t => type(t) == str).join()
+  } else if it.has("text") {
+    // E.g. escaped characters.
+    it.text
+  } else if it.has("body") {
+    plain-text(it.body)
   } else {
     none
   }
@@ -38,8 +43,57 @@
 } else {
   false
 }
+#let home = sys.inputs.at("wb-home", default: "index")
 #let target = sys.inputs.at("wb-target", default: none)
 
+/// The IDs of the notes of the book being exported, or `none` outside of a
+/// book export.
+#let book-ids = {
+  let ids = sys.inputs.at("wb-book-ids", default: none)
+  if ids != none { ids.split(",") }
+}
+
+/// The data Weibian generates for paged exports, read from the package
+/// named by the `wb-data-package` input, or an empty dictionary without it.
+#let wb-data = {
+  let spec = sys.inputs.at("wb-data-package", default: none)
+  if spec != none {
+    import spec: data
+    data
+  } else {
+    (:)
+  }
+}
+
+/// The href of the page of every note, as written by Weibian.
+#let permalinks = wb-data.at("permalinks", default: (:))
+
+/// The index of the notes, keyed by ID. Every entry has the `id`, `title`,
+/// `taxon`, `href`, `url`, `source`, `backlinks` and `contexts` of a note.
+#let note-index = wb-data.at("notes", default: (:))
+
+/// The title of a note as plain text, or its ID if it has none.
+#let note-title(identifier) = {
+  let title = note-index.at(identifier, default: (:)).at("title", default: none)
+  if title == none { identifier } else { title }
+}
+
+/// The absolute URL of a note on the site.
+#let note-url(identifier) = {
+  if identifier in note-index {
+    return note-index.at(identifier).url
+  }
+  let base = if domain == "" or domain.contains("://") { domain } else { "https://" + domain }
+  let path = if identifier in permalinks {
+    permalinks.at(identifier)
+  } else if identifier == home {
+    root-dir
+  } else {
+    root-dir + identifier + (if trailing-slash { "/" } else { ".html" })
+  }
+  base.trim("/", at: end) + path
+}
+
 #let _meta-item-html(body) = {
   html.li(class: "meta-item", body)
 }
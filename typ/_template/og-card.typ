@@ -0,0 +1,26 @@
+// The social preview image of a note, rendered once per note into
+// `og/<identifier>.png` when `card` is set in the `[og]` section of the
+// configuration file. At the default resolution of 144 PPI, the page below
+// yields a 1200 x 630 image.
+
+#let title = sys.inputs.at("wb-og-title", default: "")
+#let taxon = sys.inputs.at("wb-og-taxon", default: "")
+#let authors = sys.inputs.at("wb-og-authors", default: "")
+#let identifier = sys.inputs.at("wb-og-id", default: "")
+
+#set page(width: 600pt, height: 315pt, margin: 36pt, fill: rgb("#fdfdfc"))
+#set text(font: "Libertinus Serif", size: 16pt, fill: rgb("#1a1a1a"))
+
+#if taxon != "" {
+  text(size: 14pt, fill: rgb("#6a6a6a"), upper(taxon))
+}
+
+#v(1fr)
+#block(text(size: 32pt, weight: "bold", title))
+#v(1fr)
+
+#grid(
+  columns: (1fr, auto),
+  text(fill: rgb("#4a4a4a"), authors),
+  text(size: 12pt, fill: rgb("#6a6a6a"), raw(identifier)),
+)
@@ -1,7 +1,7 @@
 #import "site.typ"
-#import "/_template/lib.typ": plain-text, domain, root-dir, trailing-slash, target, _guard-and-render-metadata, _meta-item-paged as _meta-item
+#import "/_template/lib.typ": wb-data, plain-text, domain, root-dir, trailing-slash, target, book-ids, note-url, note-title, _guard-and-render-metadata, _meta-item-paged as _meta-item
 
-#let id-names-map = json(sys.inputs.at("wb-id-filename-map-file", default: bytes("{}")))
+#let id-names-map = wb-data.at("id-filename", default: (:))
 
 #let sans-fonts = ("Libertinus Sans", "Noto Sans SC", "Source Han Sans SC",)
 #let serif-fonts = ("Libertinus Serif", "Noto Serif SC", "Source Han Serif SC",)
@@ -107,6 +107,15 @@
   ..attrs,
 ) = {
   let taxon = attrs.at("taxon", default: none)
+  // In a book, the first occurrence of every note is labelled, so that links
+  // to it can point within the PDF.
+  if book-ids != none and identifier in book-ids {
+    let labelled = state("wb-book-labelled", ())
+    context if identifier not in labelled.get() {
+      [#metadata(identifier)#label("wb-" + identifier)]
+    }
+    labelled.update(ids => if identifier in ids { ids } else { ids + (identifier,) })
+  }
   context heading(
     depth: counter("transclusion-depth").get().at(0) + 1, 
     {
@@ -214,11 +223,28 @@
 }
 
 
-#let ln-paged(dest, body) = link(dest, body)
+#let _note-link(dest, body) = {
+  if type(dest) == str and dest.starts-with("wb:") {
+    let identifier = dest.slice(3)
+    // Links without text show the title of the note.
+    if body == [] {
+      body = note-title(identifier)
+    }
+    if book-ids != none and identifier in book-ids {
+      link(label("wb-" + identifier), body)
+    } else {
+      link(note-url(identifier), body)
+    }
+  } else {
+    link(dest, body)
+  }
+}
 
-#let ct-paged(dest, body) = link(dest, body)
+#let ln-paged(dest, body) = _note-link(dest, body)
 
-#let tr-paged(url, show-metadata: false, expanded: true, disable-numbering: false, demote-headings: 1) = {
+#let ct-paged(dest, body) = _note-link(dest, body)
+
+#let tr-paged(url, show-metadata: false, expanded: true, disable-numbering: false, demote-headings: 1, mode: "full", lazy: auto) = {
   context state("show-metadata").update(show-metadata)
   context state("expanded").update(expanded)
   context state("disable-numbering").update(disable-numbering)
@@ -233,6 +259,8 @@
   context state("disable-numbering").update(false)
 }
 
+#let excerpt-paged(body) = body
+
 #let inline-tree-paged(
   body,
   identifier: none,
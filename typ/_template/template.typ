@@ -1,6 +1,6 @@
 #import "/_template/site.typ"
 #import "/_template/lib.typ": plain-text, domain, root-dir, trailing-slash, target, _guard-and-render-metadata, _meta-item-html as _meta-item
-#import "/_template/template-paged.typ": template-paged, ln-paged, ct-paged, tr-paged, inline-tree-paged
+#import "/_template/template-paged.typ": template-paged, ln-paged, ct-paged, tr-paged, excerpt-paged, inline-tree-paged
 
 #let ln-html(dest, body) = {
   html.span(
@@ -24,17 +24,23 @@
   )
 }
 
-#let tr-html(id, show-metadata: false, expanded: true, disable-numbering: false, demote-headings: 1) = {
-  html.elem(
-    "wb-transclusion",
-    attrs: (
-      target: id,
-      show-metadata: if show-metadata { "true" } else { "false" },
-      expanded: if expanded { "true" } else { "false" },
-      disable-numbering: if disable-numbering { "true" } else { "false" },
-      demote-headings: str(demote-headings),
-    )
+#let tr-html(id, show-metadata: false, expanded: true, disable-numbering: false, demote-headings: 1, mode: "full", lazy: auto) = {
+  let attrs = (
+    target: id,
+    mode: mode,
+    show-metadata: if show-metadata { "true" } else { "false" },
+    expanded: if expanded { "true" } else { "false" },
+    disable-numbering: if disable-numbering { "true" } else { "false" },
+    demote-headings: str(demote-headings),
   )
+  if lazy != auto {
+    attrs.insert("lazy", if lazy { "true" } else { "false" })
+  }
+  html.elem("wb-transclusion", attrs: attrs)
+}
+
+#let excerpt-html(body) = {
+  html.elem("wb-excerpt", body)
 }
 
 #let _default-metadata = (..attrs) => {
@@ -145,7 +151,8 @@
           let href = if inline {
             "#" + identifier
           } else {
-            root-dir + identifier + (if trailing-slash { "/" } else { ".html" })
+            // Resolved to the page of the note by Weibian.
+            "wb:" + identifier
           }
           html.a(class: "slug", href: href, "[" + identifier + "]")
         }
@@ -172,6 +179,36 @@
     if attrs.at("taxon", default: none) != none {
       html.meta(name: "taxon", content: attrs.at("taxon"))
     }
+    if attrs.at("author", default: none) != none {
+      html.meta(name: "author", content: attrs.at("author").map(plain-text).join(", "))
+    }
+    if attrs.at("date", default: none) != none {
+      let date = attrs.at("date")
+      html.meta(
+        name: "date",
+        content: if type(date) == datetime { date.display("[year]-[month]-[day]") } else { date },
+      )
+    }
+    if attrs.at("slug", default: none) != none {
+      html.meta(name: "slug", content: attrs.at("slug"))
+    }
+    if attrs.at("aliases", default: none) != none {
+      // Former IDs of the note, e.g. `aliases: ("0001-old",)`.
+      html.meta(name: "aliases", content: attrs.at("aliases").join(","))
+    }
+    if attrs.at("permalink", default: none) != none {
+      html.meta(name: "permalink", content: attrs.at("permalink"))
+    }
+    if attrs.at("description", default: none) != none {
+      html.meta(name: "description", content: plain-text(attrs.at("description")))
+    }
+    if attrs.at("alternates", default: none) != none {
+      // Translations of the note, e.g. `alternates: (zh: "wb:0001-zh")`.
+      html.meta(
+        name: "alternates",
+        content: attrs.at("alternates").pairs().map(((lang, id)) => lang + "=" + id).join(","),
+      )
+    }
     if attrs.at("lang", default: site.config.default-lang) != none {
       html.meta(name: "lang", content: attrs.at("lang", default: site.config.default-lang))
     }
@@ -185,6 +222,19 @@
     } else {
       html.meta(name: "export-pdf", content: "false")
     }
+    if attrs.at("pdf-mode", default: none) != none {
+      html.meta(name: "pdf-mode", content: attrs.at("pdf-mode"))
+    }
+    if attrs.at("two-pass", default: false) {
+      html.meta(name: "wb-two-pass", content: "true")
+    }
+    if attrs.at("pdf-standard", default: none) != none {
+      let standard = attrs.at("pdf-standard")
+      html.meta(
+        name: "pdf-standard",
+        content: if type(standard) == array { standard.join(",") } else { standard },
+      )
+    }
     html.title(plain-text(title))
   })
 }
@@ -289,7 +339,7 @@
     )
   )
 
-  show footnote: it => html.aside(it.body)
+  show footnote: it => html.span(class: "footnote", html.elem("wb-footnote", it.body))
   
   html.html({
     _head(
@@ -330,6 +380,12 @@
   tr-paged
 }
 
+#let excerpt = if target == "html" {
+  excerpt-html
+} else {
+  excerpt-paged
+}
+
 #let inline-tree = if target == "html" {
   inline-tree-html
 } else {